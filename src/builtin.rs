@@ -0,0 +1,95 @@
+//! Rust の関数として実装した組み込みの関数
+//!
+//! 埋め込む側が `Context::register` で登録し、評価器は引数が揃ったところで関数を呼ぶ
+//!
+//! ```
+//! use tuber::builtin::Builtin;
+//! use tuber::{Context, EvalSteps, Expr};
+//!
+//! let mut context = Context::new();
+//! context.register(Builtin::new("TWICE".into(), 1, |args| {
+//!     Some(Expr::a(args[0].clone(), args[0].clone()))
+//! }));
+//!
+//! let steps = EvalSteps::new(Expr::a("TWICE".into(), ":a".into()), &context);
+//! assert_eq!(steps.last(), Some(Expr::a(":a".into(), ":a".into())));
+//! ```
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::expression::Expr;
+use crate::identifier::Ident;
+
+/// 引数の式から結果の式を計算する、適用できなければ None を返す
+pub type BuiltinFn = dyn Fn(&[Expr]) -> Option<Expr> + Send + Sync;
+
+/// Rust の関数として実装した組み込みの関数
+#[derive(Clone)]
+pub struct Builtin {
+    name: Ident,
+    arity: usize,
+
+    /// 真なら、引数をそれ以上簡約できなくなるまで簡約してから関数を呼ぶ
+    strict: bool,
+
+    function: Arc<BuiltinFn>,
+}
+
+impl Builtin {
+    pub fn new(
+        name: Ident,
+        arity: usize,
+        function: impl Fn(&[Expr]) -> Option<Expr> + Send + Sync + 'static,
+    ) -> Builtin {
+        Builtin {
+            name,
+            arity,
+            strict: false,
+            function: Arc::new(function),
+        }
+    }
+
+    /// 引数を簡約してから関数を呼ぶようにする
+    pub fn strict(mut self) -> Builtin {
+        self.strict = true;
+        self
+    }
+
+    pub fn name(&self) -> &Ident {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// 引数に適用した結果を返す、適用できなければ None を返す
+    pub fn apply(&self, args: &[Expr]) -> Option<Expr> {
+        (self.function)(args)
+    }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("strict", &self.strict)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 同じ名前と引数の個数で、同じ関数を共有していれば等しい
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.arity == other.arity
+            && self.strict == other.strict
+            && Arc::ptr_eq(&self.function, &other.function)
+    }
+}
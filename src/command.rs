@@ -1,15 +1,17 @@
 pub mod display;
 
+use crate::data::DataType;
 use crate::expression::Expr;
 use crate::function::Func;
 use crate::identifier::Ident;
 
 // TODO: Func や Expr を Box に入れたほうがいいかも
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Del(Ident), // 関数を削除
     // Add(Ident, Func),      // 関数定義 (定義済み関数の上書きを許さない)
     Update(Func),          // 関数定義 (定義済み関数の上書きを許す)
+    Data(DataType),        // データ型のコンストラクタと場合分けの関数をまとめて定義
     Eval(Expr),            // β変換列を表示
     EvalLast(Expr),        // β変結果のみ表示
     EvalHead(usize, Expr), // β変換列の先頭のみ表示
@@ -17,4 +19,13 @@ pub enum Command {
     Info(Ident),           // Global から定義済み関数を検索
     Global,                // Global 全体を表示
     Unlambda(Expr),        // Expr からラムダ抽象を除去する
+    Stats(Expr),           // β変換の統計情報を表示
+    Whnf(Expr),            // 弱頭部正規形まで簡約
+    Hnf(Expr),             // 頭部正規形まで簡約
+    Assert(Expr, Expr),    // 両辺の正規形が α 同値であることを確かめる
+    Equiv(Ident, Ident),   // 2 つの関数を同じシンボルに適用した正規形を比べる
+    History,               // 定義の変更の履歴を表示
+    Undo(usize),           // 直近の定義の変更を取り消す
+    Redo(usize),           // 取り消した定義の変更をやり直す
+    Diff(usize),           // 直近の定義の変更の前後の差分を表示
 }
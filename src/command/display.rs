@@ -10,6 +10,7 @@ impl Display for Command {
         match self {
             Command::Del(i) => write!(f, "{} = {}", i, i),
             Command::Update(func) => write!(f, "{}", func),
+            Command::Data(data) => write!(f, "{}", data),
             Command::Eval(e) => write!(f, "{}", e),
             Command::EvalLast(e) => write!(f, "! {}", e),
             Command::EvalHead(len, e) => write!(f, "!{} {}", len, e),
@@ -17,10 +18,38 @@ impl Display for Command {
             Command::Info(i) => write!(f, "? {}", i),
             Command::Global => write!(f, "?"),
             Command::Unlambda(e) => write!(f, "?? {}", e),
+            Command::Stats(e) => write!(f, ":stats {}", e),
+            Command::Whnf(e) => write!(f, ":whnf {}", e),
+            Command::Hnf(e) => write!(f, ":hnf {}", e),
+            Command::Assert(l, r) => write!(f, "== {} {}", l, r),
+            Command::Equiv(l, r) => write!(f, ":equiv {} {}", l, r),
+            _ => write_history_command(f, self),
         }
     }
 }
 
+/// 履歴を操作するコマンドを印字する、どちらのスタイルでも同じ書式になる
+///
+/// 回数が 1 であれば省略する
+pub(super) fn write_history_command(
+    f: &mut std::fmt::Formatter<'_>,
+    command: &Command,
+) -> std::fmt::Result {
+    let (name, count) = match command {
+        Command::History => return write!(f, ":history"),
+        Command::Undo(n) => ("undo", n),
+        Command::Redo(n) => ("redo", n),
+        Command::Diff(n) => ("diff", n),
+        _ => unreachable!("not a history command"),
+    };
+
+    if *count == 1 {
+        write!(f, ":{}", name)
+    } else {
+        write!(f, ":{} {}", name, count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +161,42 @@ mod tests {
     fn test_global() {
         assert_eq!(Command::Global.to_string(), "?");
     }
+
+    #[test]
+    fn test_head_normal_forms() {
+        let e = Expr::a("a".into(), "b".into());
+        assert_eq!(Command::Whnf(e.clone()).to_string(), ":whnf `ab");
+        assert_eq!(Command::Hnf(e).to_string(), ":hnf `ab");
+    }
+
+    #[test]
+    fn test_assert() {
+        let l = Expr::a(Expr::a("ADD".into(), "2".into()), "3".into());
+        assert_eq!(Command::Assert(l, "5".into()).to_string(), "== ``ADD 2 3 5");
+    }
+
+    #[test]
+    fn test_equiv() {
+        assert_eq!(
+            Command::Equiv("K".into(), "k".into()).to_string(),
+            ":equiv K k"
+        );
+    }
+
+    #[test]
+    fn test_history_commands() {
+        assert_eq!(Command::History.to_string(), ":history");
+        assert_eq!(Command::Undo(1).to_string(), ":undo");
+        assert_eq!(Command::Undo(3).to_string(), ":undo 3");
+        assert_eq!(Command::Redo(2).to_string(), ":redo 2");
+        assert_eq!(Command::Diff(1).to_string(), ":diff");
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            Command::Stats(Expr::a("a".into(), "b".into())).to_string(),
+            ":stats `ab"
+        );
+    }
 }
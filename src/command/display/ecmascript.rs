@@ -1,4 +1,6 @@
+use crate::command::display::write_history_command;
 use crate::command::Command;
+use crate::data::display::ECMAScriptStyle as DataECMAScriptStyle;
 use crate::expression::display::ecmascript::ECMAScriptStyle as ExprECMAScriptStyle;
 use crate::function::display::ecmascript::ECMAScriptStyle as FuncECMAScriptStyle;
 use std::fmt::Display;
@@ -10,14 +12,26 @@ impl Display for ECMAScriptStyle<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
             Command::Del(i) => write!(f, "{} = {}", i, i),
-            Command::Update(func) => write!(f, "{}", FuncECMAScriptStyle(&func)),
-            Command::Eval(e) => write!(f, "{}", ExprECMAScriptStyle(&e)),
-            Command::EvalLast(e) => write!(f, "! {}", ExprECMAScriptStyle(&e)),
-            Command::EvalHead(len, e) => write!(f, "!{} {}", len, ExprECMAScriptStyle(&e)),
-            Command::EvalTail(len, e) => write!(f, "!-{} {}", len, ExprECMAScriptStyle(&e)),
+            Command::Update(func) => write!(f, "{}", FuncECMAScriptStyle(func)),
+            Command::Data(data) => write!(f, "{}", DataECMAScriptStyle(data)),
+            Command::Eval(e) => write!(f, "{}", ExprECMAScriptStyle(e)),
+            Command::EvalLast(e) => write!(f, "! {}", ExprECMAScriptStyle(e)),
+            Command::EvalHead(len, e) => write!(f, "!{} {}", len, ExprECMAScriptStyle(e)),
+            Command::EvalTail(len, e) => write!(f, "!-{} {}", len, ExprECMAScriptStyle(e)),
             Command::Info(i) => write!(f, "? {}", i),
             Command::Global => write!(f, "?"),
-            Command::Unlambda(e) => write!(f, "?? {}", ExprECMAScriptStyle(&e)),
+            Command::Unlambda(e) => write!(f, "?? {}", ExprECMAScriptStyle(e)),
+            Command::Stats(e) => write!(f, ":stats {}", ExprECMAScriptStyle(e)),
+            Command::Whnf(e) => write!(f, ":whnf {}", ExprECMAScriptStyle(e)),
+            Command::Hnf(e) => write!(f, ":hnf {}", ExprECMAScriptStyle(e)),
+            Command::Equiv(l, r) => write!(f, ":equiv {} {}", l, r),
+            Command::Assert(l, r) => write!(
+                f,
+                "== {} {}",
+                ExprECMAScriptStyle(l),
+                ExprECMAScriptStyle(r)
+            ),
+            command => write_history_command(f, command),
         }
     }
 }
@@ -1,27 +1,576 @@
+//! 設定
+//!
+//! 組み込みの既定値、`~/.tuber/config.toml`、カレントディレクトリの `.tuber.toml`、
+//! `TUBER_*` 環境変数、コマンドライン引数の順に読み、後から読んだものほど優先する
+//!
+//! ```toml
+//! display_style = "lazy-k"
+//! line_width = 80
+//! fold_definitions = false
+//! strategy = "normal"
+//! color = "auto"
+//! prelude = "prelude.txt"
+//!
+//! [limits]
+//! steps = 1000
+//! size = 1000000
+//! depth = 10000
+//! time = 5.0
+//! ```
+
 use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[cfg(feature = "cli")]
+use home_dir::HomeDirExt;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DisplayStyle {
     LazyK,
     Ecmascript,
 }
 
-pub fn display_style() -> DisplayStyle {
-    match env::var("TUBER_DISPLAY_STYLE") {
-        Ok(s) => match s.as_str() {
-            "Lazy_K" => DisplayStyle::LazyK,
-            "ECMAScript" => DisplayStyle::Ecmascript,
-            _ => DisplayStyle::LazyK,
-        },
-        Err(_) => DisplayStyle::LazyK,
+impl FromStr for DisplayStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Lazy_K" | "lazy-k" => Ok(DisplayStyle::LazyK),
+            "ECMAScript" | "ecmascript" => Ok(DisplayStyle::Ecmascript),
+            _ => Err(format!("unknown display style: {}", s)),
+        }
+    }
+}
+
+/// 出力の形式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// 人が読むためのテキスト
+    Text,
+
+    /// プログラムが読むための JSON
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// 簡約戦略
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// 最左最外簡約、ラムダ抽象の本体は簡約しない
+    Normal,
+
+    /// 最左最外簡約、ラムダ抽象の本体も簡約して β 正規形を求める
+    Full,
+
+    /// 頭部の redex だけを簡約して弱頭部正規形を求める、引数もラムダ抽象の本体も簡約しない
+    WeakHead,
+
+    /// 頭部の redex だけを簡約して頭部正規形を求める、ラムダ抽象の本体の頭部も簡約する
+    Head,
+}
+
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Normal => "normal",
+            Strategy::Full => "full",
+            Strategy::WeakHead => "whnf",
+            Strategy::Head => "hnf",
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" | "leftmost-outermost" => Ok(Strategy::Normal),
+            "full" | "strong" => Ok(Strategy::Full),
+            "whnf" | "weak-head" => Ok(Strategy::WeakHead),
+            "hnf" | "head" => Ok(Strategy::Head),
+            _ => Err(format!("unknown strategy: {}", s)),
+        }
+    }
+}
+
+/// 出力を色付けするかどうか
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    /// 出力先が端末なら色付けする
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(&self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub display_style: DisplayStyle,
+
+    /// 簡約のステップ数の上限
+    pub step_limit: usize,
+
+    /// 簡約中の式のノード数の上限
+    pub size_limit: usize,
+
+    /// 簡約中の式の深さの上限
+    pub depth_limit: usize,
+
+    /// 簡約にかけてよい時間、None なら制限しない
+    pub time_limit: Option<Duration>,
+
+    /// 端末に出力するときの 1 行あたりの最大幅
+    pub line_width: usize,
+
+    /// 定義済み関数の本体と一致する部分式を関数名に置き換えて表示するかどうか
+    pub fold_definitions: bool,
+
+    pub strategy: Strategy,
+
+    pub color: ColorChoice,
+
+    /// 組み込みの定義の代わりに、履歴より先に読み込む定義ファイル
+    pub prelude: Option<PathBuf>,
+}
+
+/// コマンドライン引数で指定した設定、指定しなかったものは None
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConfigArgs {
+    pub display_style: Option<DisplayStyle>,
+    pub step_limit: Option<usize>,
+    pub strategy: Option<Strategy>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            display_style: DisplayStyle::LazyK,
+            step_limit: 1000,
+            size_limit: 1_000_000,
+            depth_limit: 10_000,
+            time_limit: None,
+            line_width: 80,
+            fold_definitions: false,
+            strategy: Strategy::Normal,
+            color: ColorChoice::Auto,
+            prelude: None,
+        }
+    }
+}
+
+impl Config {
+    /// 既定値に設定ファイルと環境変数を順に重ねた設定を読む
+    pub fn load() -> Result<Config, String> {
+        let mut config = Config::default();
+
+        #[cfg(feature = "cli")]
+        if let Ok(path) = Path::new("~/.tuber/config.toml").expand_home() {
+            config.merge_file(&path)?;
+        }
+        config.merge_file(Path::new(".tuber.toml"))?;
+        config.merge_env(env::vars())?;
+
+        Ok(config)
+    }
+
+    /// 設定ファイルがあれば読んで重ねる
+    fn merge_file(&mut self, path: &Path) -> Result<(), String> {
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.merge_toml(&source, path.parent())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// TOML で書かれた設定を重ねる
+    ///
+    /// prelude に相対パスが書かれていれば dir からの相対パスとして扱う
+    pub fn merge_toml(&mut self, source: &str, dir: Option<&Path>) -> Result<(), String> {
+        let table = source.parse::<toml::Table>().map_err(|e| e.to_string())?;
+
+        for (key, value) in table.iter() {
+            match key.as_str() {
+                "display_style" => self.display_style = string(key, value)?.parse()?,
+                "line_width" => self.line_width = integer(key, value)?,
+                "fold_definitions" => self.fold_definitions = boolean(key, value)?,
+                "strategy" => self.strategy = string(key, value)?.parse()?,
+                "color" => self.color = string(key, value)?.parse()?,
+                "prelude" => {
+                    let path = Path::new(string(key, value)?);
+                    self.prelude = Some(match dir {
+                        Some(dir) if path.is_relative() => dir.join(path),
+                        _ => path.to_path_buf(),
+                    });
+                }
+
+                "limits" => {
+                    let limits = value
+                        .as_table()
+                        .ok_or_else(|| format!("`{}` must be a table", key))?;
+
+                    for (key, value) in limits.iter() {
+                        match key.as_str() {
+                            "steps" => self.step_limit = integer(key, value)?,
+                            "size" => self.size_limit = integer(key, value)?,
+                            "depth" => self.depth_limit = integer(key, value)?,
+                            "time" => self.time_limit = Some(seconds(key, value)?),
+                            _ => return Err(format!("unknown key: limits.{}", key)),
+                        }
+                    }
+                }
+
+                _ => return Err(format!("unknown key: {}", key)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `TUBER_*` 環境変数を重ねる
+    pub fn merge_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), String> {
+        for (key, value) in vars {
+            let invalid = |e: String| format!("{}: {}", key, e);
+
+            match key.as_str() {
+                "TUBER_DISPLAY_STYLE" => self.display_style = value.parse().map_err(invalid)?,
+                "TUBER_STEP_LIMIT" => self.step_limit = parse_usize(&value).map_err(invalid)?,
+                "TUBER_SIZE_LIMIT" => self.size_limit = parse_usize(&value).map_err(invalid)?,
+                "TUBER_DEPTH_LIMIT" => self.depth_limit = parse_usize(&value).map_err(invalid)?,
+                "TUBER_TIME_LIMIT" => {
+                    let secs = value
+                        .parse::<f64>()
+                        .map_err(|_| format!("invalid number of seconds: {}", value))
+                        .map_err(invalid)?;
+                    self.time_limit = Some(parse_seconds(secs).map_err(invalid)?);
+                }
+                "TUBER_LINE_WIDTH" => self.line_width = parse_usize(&value).map_err(invalid)?,
+                "TUBER_FOLD_DEFINITIONS" => {
+                    self.fold_definitions = match value.as_str() {
+                        "1" | "true" => true,
+                        "0" | "false" => false,
+                        _ => return Err(invalid(format!("invalid boolean: {}", value))),
+                    }
+                }
+                "TUBER_STRATEGY" => self.strategy = value.parse().map_err(invalid)?,
+                "TUBER_COLOR" => self.color = value.parse().map_err(invalid)?,
+                "TUBER_PRELUDE" => self.prelude = Some(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// コマンドライン引数で指定した設定を重ねる
+    pub fn merge_args(&mut self, args: &ConfigArgs) {
+        if let Some(style) = args.display_style {
+            self.display_style = style;
+        }
+        if let Some(limit) = args.step_limit {
+            self.step_limit = limit;
+        }
+        if let Some(strategy) = args.strategy {
+            self.strategy = strategy;
+        }
     }
 }
 
+fn string<'a>(key: &str, value: &'a toml::Value) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("`{}` must be a string", key))
+}
+
+fn integer(key: &str, value: &toml::Value) -> Result<usize, String> {
+    value
+        .as_integer()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| format!("`{}` must be a non-negative integer", key))
+}
+
+fn boolean(key: &str, value: &toml::Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("`{}` must be a boolean", key))
+}
+
+fn seconds(key: &str, value: &toml::Value) -> Result<Duration, String> {
+    let secs = match value {
+        toml::Value::Integer(n) => *n as f64,
+        toml::Value::Float(x) => *x,
+        _ => return Err(format!("`{}` must be a number of seconds", key)),
+    };
+    parse_seconds(secs).map_err(|e| format!("`{}`: {}", key, e))
+}
+
+fn parse_usize(s: &str) -> Result<usize, String> {
+    s.parse::<usize>()
+        .map_err(|_| format!("invalid non-negative integer: {}", s))
+}
+
+fn parse_seconds(secs: f64) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid number of seconds: {}", secs))
+}
+
+// ========================================================================== //
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// プロセス全体で使う設定を決める、2 回目以降の呼び出しは無視される
+///
+/// 決める前に参照された場合は既定値を使う
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+fn current() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+pub fn display_style() -> DisplayStyle {
+    current().display_style
+}
+
 pub fn step_limit() -> usize {
-    match env::var("TUBER_STEP_LIMIT") {
-        Ok(s) => match s.parse::<usize>() {
-            Ok(n) => n,
-            Err(_) => 1000,
-        },
-        Err(_) => 1000,
+    current().step_limit
+}
+
+/// 簡約中の式のノード数の上限
+pub fn size_limit() -> usize {
+    current().size_limit
+}
+
+/// 簡約中の式の深さの上限、式を再帰的に辿るときにスタックが溢れないようにする
+pub fn depth_limit() -> usize {
+    current().depth_limit
+}
+
+/// 簡約にかけてよい時間、指定がなければ制限しない
+pub fn time_limit() -> Option<Duration> {
+    current().time_limit
+}
+
+pub fn strategy() -> Strategy {
+    current().strategy
+}
+
+/// 端末に出力するときの 1 行あたりの最大幅
+pub fn line_width() -> usize {
+    current().line_width
+}
+
+/// 定義済み関数の本体と一致する部分式を関数名に置き換えて表示するかどうか
+pub fn fold_definitions() -> bool {
+    current().fold_definitions
+}
+
+pub fn color() -> ColorChoice {
+    current().color
+}
+
+/// 履歴より先に読み込む定義ファイル
+pub fn prelude() -> Option<&'static Path> {
+    current().prelude.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let mut config = Config::default();
+        config
+            .merge_toml(
+                r#"
+                display_style = "ecmascript"
+                fold_definitions = true
+                color = "never"
+                prelude = "lib/prelude.txt"
+
+                [limits]
+                steps = 42
+                time = 1.5
+                "#,
+                Some(Path::new("/home/user/project")),
+            )
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                display_style: DisplayStyle::Ecmascript,
+                step_limit: 42,
+                time_limit: Some(Duration::from_millis(1500)),
+                fold_definitions: true,
+                color: ColorChoice::Never,
+                prelude: Some(PathBuf::from("/home/user/project/lib/prelude.txt")),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge_toml_errors() {
+        let mut config = Config::default();
+
+        assert_eq!(
+            config.merge_toml(r#"display_style = "LazyK""#, None),
+            Err("unknown display style: LazyK".to_string())
+        );
+        assert_eq!(
+            config.merge_toml("display_styel = 'lazy-k'", None),
+            Err("unknown key: display_styel".to_string())
+        );
+        assert_eq!(
+            config.merge_toml("[limits]\nstep = 1", None),
+            Err("unknown key: limits.step".to_string())
+        );
+        assert_eq!(
+            config.merge_toml("line_width = -1", None),
+            Err("`line_width` must be a non-negative integer".to_string())
+        );
+        assert!(config.merge_toml("line_width = ", None).is_err());
+
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_merge_env() {
+        let mut config = Config::default();
+        config
+            .merge_env(env(&[
+                ("TUBER_DISPLAY_STYLE", "ECMAScript"),
+                ("TUBER_STEP_LIMIT", "10"),
+                ("TUBER_FOLD_DEFINITIONS", "1"),
+                ("HOME", "/root"),
+            ]))
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                display_style: DisplayStyle::Ecmascript,
+                step_limit: 10,
+                fold_definitions: true,
+                ..Config::default()
+            }
+        );
+
+        assert_eq!(
+            config.merge_env(env(&[("TUBER_DISPLAY_STYLE", "lazyk")])),
+            Err("TUBER_DISPLAY_STYLE: unknown display style: lazyk".to_string())
+        );
+        assert_eq!(
+            config.merge_env(env(&[("TUBER_STEP_LIMIT", "many")])),
+            Err("TUBER_STEP_LIMIT: invalid non-negative integer: many".to_string())
+        );
+    }
+
+    #[test]
+    fn test_layers() {
+        // 後から重ねたものほど優先される
+        let mut config = Config::default();
+        config
+            .merge_toml("line_width = 100\n[limits]\nsteps = 5", None)
+            .unwrap();
+        config.merge_toml("line_width = 120", None).unwrap();
+        config
+            .merge_env(env(&[("TUBER_LINE_WIDTH", "60")]))
+            .unwrap();
+
+        assert_eq!(config.line_width, 60);
+        assert_eq!(config.step_limit, 5);
+    }
+
+    #[test]
+    fn test_merge_args() {
+        // コマンドライン引数、環境変数、設定ファイルの順に優先する
+        let mut config = Config::default();
+        config
+            .merge_toml(
+                "display_style = 'ecmascript'\nstrategy = 'full'\nline_width = 100\n[limits]\nsteps = 5",
+                None,
+            )
+            .unwrap();
+        config
+            .merge_env(env(&[
+                ("TUBER_STEP_LIMIT", "10"),
+                ("TUBER_STRATEGY", "normal"),
+            ]))
+            .unwrap();
+        config.merge_args(&ConfigArgs {
+            step_limit: Some(20),
+            ..ConfigArgs::default()
+        });
+
+        assert_eq!(
+            config,
+            Config {
+                display_style: DisplayStyle::Ecmascript,
+                step_limit: 20,
+                strategy: Strategy::Normal,
+                line_width: 100,
+                ..Config::default()
+            }
+        );
+
+        config.merge_args(&ConfigArgs {
+            display_style: Some(DisplayStyle::LazyK),
+            step_limit: None,
+            strategy: Some(Strategy::Full),
+        });
+        assert_eq!(config.display_style, DisplayStyle::LazyK);
+        assert_eq!(config.step_limit, 20);
+        assert_eq!(config.strategy, Strategy::Full);
     }
 }
@@ -3,24 +3,50 @@ mod display;
 
 use std::collections::HashMap;
 
+use crate::builtin::Builtin;
 use crate::function::Func;
 use crate::identifier::Ident;
 
 /// 定義済みの名前空間を表現する
 ///
-/// 識別子と関数の組を保持する
+/// 識別子と関数の組、識別子と組み込みの関数の組を保持する
+/// 同じ名前の関数と組み込みの関数があれば関数を優先する
 #[derive(Debug, Clone, PartialEq)]
-pub struct Context(HashMap<Ident, Func>);
+pub struct Context(HashMap<Ident, Func>, HashMap<Ident, Builtin>);
 
 impl Context {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self(HashMap::new(), HashMap::new())
+    }
+
+    /// 組み込みの関数を登録する、同じ名前の組み込みの関数は上書きする
+    pub fn register(&mut self, builtin: Builtin) {
+        self.1.insert(builtin.name().clone(), builtin);
+    }
+
+    /// 組み込みの関数を検索する、同じ名前の関数が定義されていれば None を返す
+    pub fn builtin(&self, id: &Ident) -> Option<&Builtin> {
+        match self.0.get(id) {
+            Some(_) => None,
+            None => self.1.get(id),
+        }
     }
 
     pub fn def(&mut self, func: Func) {
         self.0.insert(func.name().clone(), func);
     }
 
+    /// 定義のコマンドを適用する
+    ///
+    /// パターンで定義した関数に同じ個数の仮引数の節を定義すると、関数を置き換えずに節を加える
+    /// 関数ごと置き換えるには、いったん削除してから定義する
+    pub fn update(&mut self, func: Func) {
+        match self.0.get_mut(func.name()) {
+            Some(f) if f.has_patterns() && f.arity() == func.arity() => f.add_clauses(func),
+            _ => self.def(func),
+        }
+    }
+
     pub fn get(&self, id: &Ident) -> Option<&Func> {
         self.0.get(id)
     }
@@ -39,7 +65,7 @@ impl Context {
         self.0.len()
     }
 
-    pub fn for_each(&self, callback: impl Fn(&Ident, &Func)) {
+    pub fn for_each<'a>(&'a self, mut callback: impl FnMut(&'a Ident, &'a Func)) {
         for (i, f) in &self.0 {
             callback(i, f);
         }
@@ -99,6 +125,18 @@ mod tests {
         assert_eq!(context.arity(&Ident::new("i")), None);
     }
 
+    #[test]
+    fn test_context_register() {
+        let mut context = Context::new();
+        context.register(Builtin::new("ID".into(), 1, |args| Some(args[0].clone())));
+        assert_eq!(context.builtin(&"ID".into()).map(|b| b.arity()), Some(1));
+        assert_eq!(context.count(), 0);
+
+        // 同じ名前の関数が定義されていれば関数を優先する
+        context.def(Func::new("ID".into(), vec![], ":a".into()));
+        assert_eq!(context.builtin(&"ID".into()), None);
+    }
+
     #[test]
     fn test_context_from() {
         let i: Func = Func::new("i".into(), vec![Ident::new("x")], Expr::v("x"));
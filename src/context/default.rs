@@ -459,3 +459,52 @@ impl Default for Context {
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+    use crate::engine::{EngineEvent, EventSink};
+    use crate::format::parse_line;
+    use crate::Engine;
+
+    /// 等式の検査に失敗したコマンドを集める
+    struct Failures(Vec<String>);
+
+    impl EventSink for Failures {
+        fn emit(&mut self, event: EngineEvent) {
+            if let EngineEvent::Asserted(assertion) = event {
+                if !assertion.passed() {
+                    self.0
+                        .push(format!("{} != {}", assertion.lhs, assertion.rhs));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_context() {
+        let checks = [
+            "== ```IF TRUE :a :b :a",
+            "== `NOT FALSE TRUE",
+            "== ``AND TRUE FALSE FALSE",
+            "== ``OR FALSE TRUE TRUE",
+            "== `CAR ``CONS :a :b :a",
+            "== `CDR ``CONS :a :b :b",
+            "== `SUCC 9 10",
+            "== ``ADD 2 3 5",
+            "== ``MUL 2 3 6",
+            "== ``POW 2 3 8",
+            "== `PRED 1 0",
+            "== ``SUB 7 3 4",
+            "== ``EQ 4 4 TRUE",
+            "== ``LTE 3 2 FALSE",
+        ];
+
+        let mut failures = Failures(Vec::new());
+        let mut engine = Engine::new(Context::default());
+        for check in checks {
+            engine.run(parse_line(check).unwrap(), &mut failures);
+        }
+        assert_eq!(failures.0, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,187 @@
+//! 代数的データ型の宣言
+//!
+//! `data TREE = LEAF | NODE TREE v TREE` から、コンストラクタと場合分けの関数を組み立てる
+//! フィールドに型の名前を書くと再帰的なフィールドになる、Church 符号化ではそこを畳み込む
+
+pub mod display;
+
+use std::collections::HashSet;
+
+use crate::expression::Expr;
+use crate::function::Func;
+use crate::identifier::Ident;
+
+/// データ型の値をラムダ式で表す方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// 値は、自分のコンストラクタに対応する継続にフィールドをそのまま渡す
+    Scott,
+
+    /// 値は、自分のコンストラクタに対応する継続に再帰的なフィールドを畳み込んだ結果を渡す
+    Church,
+}
+
+/// `data` で宣言したデータ型
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataType {
+    pub name: Ident,
+    pub encoding: Encoding,
+    pub constructors: Vec<Constructor>,
+}
+
+/// コンストラクタの名前とフィールドの名前
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constructor {
+    pub name: Ident,
+    pub fields: Vec<Ident>,
+}
+
+impl DataType {
+    /// 場合分けの関数の名前、Scott 符号化なら `CASE_型名`、Church 符号化なら `FOLD_型名`
+    pub fn eliminator(&self) -> Ident {
+        let prefix = match self.encoding {
+            Encoding::Scott => "CASE",
+            Encoding::Church => "FOLD",
+        };
+        Ident::new(&format!("{}_{}", prefix, self.name.label()))
+    }
+
+    /// コンストラクタと場合分けの関数を定義する
+    ///
+    /// n 個のコンストラクタのうち i 番目の値は、n 個の継続を受け取って i 番目の継続にフィールドを渡す
+    /// 場合分けの関数は、値を継続に適用するだけの関数になる
+    pub fn funcs(&self) -> Vec<Func> {
+        let mut used = self
+            .constructors
+            .iter()
+            .flat_map(|c| c.fields.iter())
+            .filter(|f| **f != self.name)
+            .cloned()
+            .collect::<HashSet<_>>();
+        let continuations = self
+            .constructors
+            .iter()
+            .map(|_| fresh(&mut used))
+            .collect::<Vec<_>>();
+
+        let mut funcs = Vec::new();
+        for (constructor, k) in self.constructors.iter().zip(&continuations) {
+            let mut params = Vec::new();
+            let mut body = Expr::Variable(k.clone());
+            let mut used = used.clone();
+            for field in &constructor.fields {
+                let param = match field {
+                    f if *f == self.name || params.contains(f) => fresh(&mut used),
+                    f => f.clone(),
+                };
+                let mut arg = Expr::Variable(param.clone());
+                if *field == self.name && self.encoding == Encoding::Church {
+                    arg = apply_all(arg, &continuations);
+                }
+                body = Expr::a(body, arg);
+                params.push(param);
+            }
+
+            let body = continuations
+                .iter()
+                .rev()
+                .fold(body, |body, k| Expr::l(k.clone(), body));
+            funcs.push(Func::new(constructor.name.clone(), params, body));
+        }
+
+        let value = fresh(&mut used);
+        let mut params = vec![value.clone()];
+        params.extend(continuations.iter().cloned());
+        let body = apply_all(Expr::Variable(value), &continuations);
+        funcs.push(Func::new(self.eliminator(), params, body));
+
+        funcs
+    }
+}
+
+/// 式を変数の列に順に適用する
+fn apply_all(e: Expr, vars: &[Ident]) -> Expr {
+    vars.iter()
+        .fold(e, |e, v| Expr::a(e, Expr::Variable(v.clone())))
+}
+
+/// 使われていない名前を a から z の順に選ぶ、すべて使われていれば番号を付ける
+fn fresh(used: &mut HashSet<Ident>) -> Ident {
+    let name = ('a'..='z')
+        .map(|c| Ident::new(&c.to_string()))
+        .find(|i| !used.contains(i))
+        .unwrap_or_else(|| Ident::new("k").new_name(used));
+    used.insert(name.clone());
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(encoding: Encoding) -> DataType {
+        DataType {
+            name: "TREE".into(),
+            encoding,
+            constructors: vec![
+                Constructor {
+                    name: "LEAF".into(),
+                    fields: vec![],
+                },
+                Constructor {
+                    name: "NODE".into(),
+                    fields: vec!["TREE".into(), "v".into(), "TREE".into()],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_scott() {
+        let funcs = tree(Encoding::Scott).funcs();
+        let names = funcs.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "LEAF = ^a.^b.a",
+                "```NODEcvd = ^a.^b.```bcvd",
+                "```CASE_TREEcab = ``cab",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_church() {
+        let funcs = tree(Encoding::Church).funcs();
+        assert_eq!(funcs[1].to_string(), "```NODEcvd = ^a.^b.```b``cabv``dab");
+        assert_eq!(funcs[2].name(), &"FOLD_TREE".into());
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_church_fold() {
+        use crate::context::Context;
+        use crate::evaluate::EvalSteps;
+
+        let funcs = tree(Encoding::Church).funcs();
+
+        // 葉の個数を数える: ```FOLD_TREE```NODE LEAF :v LEAF :1 ^l.^v.^r.``INT_ADD lr
+        let context = Context::from(funcs);
+        let node = Expr::a(
+            Expr::a(Expr::a("NODE".into(), "LEAF".into()), ":v".into()),
+            "LEAF".into(),
+        );
+        let add = Expr::l(
+            "l".into(),
+            Expr::l(
+                "v".into(),
+                Expr::l(
+                    "r".into(),
+                    Expr::a(Expr::a("INT_ADD".into(), "l".into()), "r".into()),
+                ),
+            ),
+        );
+        let expr = Expr::a(Expr::a(Expr::a("FOLD_TREE".into(), node), ":1".into()), add);
+        assert_eq!(EvalSteps::new(expr, &context).last(), Some(":2".into()));
+    }
+}
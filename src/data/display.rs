@@ -0,0 +1,80 @@
+use std::fmt::Display;
+
+use crate::data::{Constructor, DataType, Encoding};
+
+/// `data TREE = LEAF | NODE TREE v TREE`
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_head(f, self)?;
+        for (n, c) in self.constructors.iter().enumerate() {
+            write!(f, "{}{}", if n == 0 { "" } else { " | " }, c.name)?;
+            for field in &c.fields {
+                write!(f, " {}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `data TREE = LEAF | NODE(TREE, v, TREE)`
+#[derive(PartialEq, Debug)]
+pub struct ECMAScriptStyle<'a>(pub &'a DataType);
+
+impl Display for ECMAScriptStyle<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_head(f, self.0)?;
+        for (n, c) in self.0.constructors.iter().enumerate() {
+            write!(f, "{}{}", if n == 0 { "" } else { " | " }, c.name)?;
+            write_fields(f, c)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_head(f: &mut std::fmt::Formatter<'_>, data: &DataType) -> std::fmt::Result {
+    match data.encoding {
+        Encoding::Scott => write!(f, "data {} = ", data.name),
+        Encoding::Church => write!(f, "data church {} = ", data.name),
+    }
+}
+
+fn write_fields(f: &mut std::fmt::Formatter<'_>, c: &Constructor) -> std::fmt::Result {
+    if c.fields.is_empty() {
+        return Ok(());
+    }
+    let fields = c
+        .fields
+        .iter()
+        .map(|i| i.label())
+        .collect::<Vec<_>>()
+        .join(", ");
+    write!(f, "({})", fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_string() {
+        let data = DataType {
+            name: "LIST".into(),
+            encoding: Encoding::Church,
+            constructors: vec![
+                Constructor {
+                    name: "NIL".into(),
+                    fields: vec![],
+                },
+                Constructor {
+                    name: "CONS".into(),
+                    fields: vec!["x".into(), "LIST".into()],
+                },
+            ],
+        };
+        assert_eq!(data.to_string(), "data church LIST = NIL | CONS x LIST");
+        assert_eq!(
+            ECMAScriptStyle(&data).to_string(),
+            "data church LIST = NIL | CONS(x, LIST)"
+        );
+    }
+}
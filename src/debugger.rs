@@ -0,0 +1,426 @@
+//! 簡約を 1 ステップずつ対話的に進めるデバッガ
+
+use std::io::{self, BufRead, Write};
+
+use crate::config::{DisplayStyle, Strategy};
+use crate::context::Context;
+use crate::evaluate::{EvalSteps, Redex, RedexPosition};
+use crate::expression::display::ecmascript::ECMAScriptStyle;
+use crate::expression::display::lazy_k::LazyKStyle;
+use crate::expression::Expr;
+use crate::identifier::Ident;
+use crate::limit::Limits;
+
+const HELP: &str = "\
+s, step [N]        N ステップ (省略時は 1 ステップ) 進める
+c, continue        ブレークポイントに当たるか正規形に達するまで進める
+p, print           現在の式を表示する
+r, redex           次に簡約される redex を表示する
+stack              式の根から次に簡約される redex までに辿る部分式を表示する
+b, break NAME      関数 NAME が適用される直前で止まる
+b, break size N    式のノード数が N を超えたら止まる
+bl, breakpoints    ブレークポイントの一覧を表示する
+d, delete N        N 番目のブレークポイントを削除する
+h, help            このヘルプを表示する
+q, quit            デバッガを終了する";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// 指定した関数が適用される直前で止まる
+    Apply(Ident),
+
+    /// 式のノード数が指定した値を超えたら止まる
+    Size(usize),
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Apply(name) => write!(f, "apply {}", name),
+            Breakpoint::Size(n) => write!(f, "size > {}", n),
+        }
+    }
+}
+
+pub struct Debugger<'a> {
+    steps: EvalSteps<'a>,
+    current: Expr,
+
+    /// 先読みした次のステップ、正規形に達していれば None
+    upcoming: Option<Option<Upcoming>>,
+
+    count: usize,
+    breakpoints: Vec<Breakpoint>,
+    display_style: DisplayStyle,
+    step_limit: usize,
+    limits: Limits,
+}
+
+/// 先読みした次のステップの式と、そのステップで簡約される redex
+struct Upcoming {
+    expr: Expr,
+    redex: Option<Redex>,
+    position: Option<RedexPosition>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(
+        expr: Expr,
+        context: &'a Context,
+        display_style: DisplayStyle,
+        step_limit: usize,
+        strategy: Strategy,
+    ) -> Self {
+        Debugger {
+            steps: EvalSteps::with_strategy(expr.clone(), context, strategy),
+            current: expr,
+            upcoming: None,
+            count: 0,
+            breakpoints: Vec::new(),
+            display_style,
+            step_limit,
+            limits: Limits::from_config(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// 入力からデバッガのコマンドを読み、結果を出力に書き出す
+    pub fn repl(&mut self, input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.status())?;
+        write!(output, "(debug) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words.as_slice() {
+                [] => {}
+
+                ["s" | "step"] => writeln!(output, "{}", self.step(1))?,
+
+                ["s" | "step", n] => match n.parse::<usize>() {
+                    Ok(n) => writeln!(output, "{}", self.step(n))?,
+                    Err(_) => writeln!(output, "invalid step count: {}", n)?,
+                },
+
+                ["c" | "continue"] => writeln!(output, "{}", self.step(self.step_limit))?,
+
+                ["p" | "print"] => writeln!(output, "{}", self.status())?,
+
+                ["r" | "redex"] => writeln!(output, "{}", self.redex())?,
+
+                ["stack"] => writeln!(output, "{}", self.stack())?,
+
+                ["b" | "break", "size", n] => match n.parse::<usize>() {
+                    Ok(n) => {
+                        self.add_breakpoint(Breakpoint::Size(n));
+                        writeln!(
+                            output,
+                            "breakpoint {}: size > {}",
+                            self.breakpoints.len(),
+                            n
+                        )?;
+                    }
+                    Err(_) => writeln!(output, "invalid size: {}", n)?,
+                },
+
+                ["b" | "break", name] => match name.parse() {
+                    Ok(ident) => {
+                        self.add_breakpoint(Breakpoint::Apply(ident));
+                        writeln!(
+                            output,
+                            "breakpoint {}: apply {}",
+                            self.breakpoints.len(),
+                            name
+                        )?;
+                    }
+                    Err(e) => writeln!(output, "{}", e)?,
+                },
+
+                ["bl" | "breakpoints"] => {
+                    for (n, breakpoint) in self.breakpoints.iter().enumerate() {
+                        writeln!(output, "{}: {}", n + 1, breakpoint)?;
+                    }
+                }
+
+                ["d" | "delete", n] => match n.parse::<usize>() {
+                    Ok(n) if 1 <= n && n <= self.breakpoints.len() => {
+                        self.breakpoints.remove(n - 1);
+                    }
+                    _ => writeln!(output, "no such breakpoint: {}", n)?,
+                },
+
+                ["h" | "help"] => writeln!(output, "{}", HELP)?,
+
+                ["q" | "quit"] => return Ok(()),
+
+                _ => writeln!(output, "unknown command: {}, type `help` for help", line)?,
+            }
+
+            write!(output, "(debug) ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// 最大 n ステップ進め、止まった理由を返す
+    ///
+    /// 2 ステップ目以降はブレークポイントを確認し、当たればそこで止まる
+    /// 各ステップの式は Limits で見張り、上限を超える式には進まない
+    pub fn step(&mut self, n: usize) -> String {
+        // 利用者がコマンドを考えている間は数えないよう、コマンドごとに計測を始める
+        let watchdog = self.limits.start();
+        for i in 0..n {
+            if i > 0 {
+                if let Some(name) = self.hit_apply_breakpoint() {
+                    return format!("breakpoint: about to apply {}\n{}", name, self.status());
+                }
+            }
+
+            match self.peek() {
+                Some(upcoming) => {
+                    if let Err(e) = watchdog.check(&upcoming.expr) {
+                        return format!("error: {}\n{}", e, self.status());
+                    }
+                    self.advance()
+                }
+                None => match self.steps.exceeded() {
+                    Some(e) => return format!("error: {}\n{}", e, self.status()),
+                    None => return format!("normal form\n{}", self.status()),
+                },
+            }
+
+            if let Some(n) = self.hit_size_breakpoint() {
+                return format!("breakpoint: size > {}\n{}", n, self.status());
+            }
+        }
+
+        self.status()
+    }
+
+    /// 次に簡約される redex を表示する
+    fn redex(&mut self) -> String {
+        let current = self.current.clone();
+        let Some(upcoming) = self.peek() else {
+            return "no redex, normal form".to_string();
+        };
+
+        let term = upcoming.position.as_ref().and_then(|p| p.locate(&current));
+        match (term, &upcoming.redex) {
+            (Some(term), _) => format!("next: {}", self.show(term)),
+            (None, Some(redex)) => format!("next: {}", show_redex(redex)),
+            (None, None) => "next: unknown".to_string(),
+        }
+    }
+
+    /// 式の根から次に簡約される redex までに辿る部分式を、根から順に表示する
+    fn stack(&mut self) -> String {
+        let current = self.current.clone();
+        let Some(upcoming) = self.peek() else {
+            return "no redex, normal form".to_string();
+        };
+        let Some(position) = upcoming.position.clone() else {
+            return "unknown position".to_string();
+        };
+        let Some(frames) = position.frames(&current) else {
+            return "unknown position".to_string();
+        };
+
+        let mut lines = vec![format!("#0: {}", self.show(frames[0]))];
+        for (i, (n, frame)) in position.path.iter().zip(&frames[1..]).enumerate() {
+            let edge = match frames[i] {
+                Expr::Lambda { .. } => "body".to_string(),
+                _ => format!("arg {}", n),
+            };
+            lines.push(format!("#{}: {}: {}", i + 1, edge, self.show(frame)));
+        }
+        lines.join("\n")
+    }
+
+    fn peek(&mut self) -> Option<&Upcoming> {
+        if self.upcoming.is_none() {
+            let next = self.steps.next().map(|expr| Upcoming {
+                expr,
+                redex: self.steps.redex().cloned(),
+                position: self.steps.redex_position().cloned(),
+            });
+            self.upcoming = Some(next);
+        }
+        self.upcoming.as_ref().and_then(|u| u.as_ref())
+    }
+
+    fn advance(&mut self) {
+        if let Some(Some(upcoming)) = self.upcoming.take() {
+            self.current = upcoming.expr;
+            self.count += 1;
+        }
+    }
+
+    fn hit_apply_breakpoint(&mut self) -> Option<Ident> {
+        let name = match self.peek() {
+            Some(Upcoming {
+                redex: Some(Redex::Func(name)),
+                ..
+            }) => name.clone(),
+            _ => return None,
+        };
+
+        self.breakpoints
+            .iter()
+            .any(|b| b == &Breakpoint::Apply(name.clone()))
+            .then_some(name)
+    }
+
+    fn hit_size_breakpoint(&self) -> Option<usize> {
+        let size = self.current.size();
+        self.breakpoints.iter().find_map(|b| match b {
+            Breakpoint::Size(n) if size > *n => Some(*n),
+            _ => None,
+        })
+    }
+
+    fn status(&self) -> String {
+        format!("[{}] {}", self.count, self.show(&self.current))
+    }
+
+    fn show(&self, expr: &Expr) -> String {
+        match self.display_style {
+            DisplayStyle::LazyK => LazyKStyle(expr).to_string(),
+            DisplayStyle::Ecmascript => ECMAScriptStyle(expr).to_string(),
+        }
+    }
+}
+
+fn show_redex(redex: &Redex) -> String {
+    match redex {
+        Redex::Func(name) => name.to_string(),
+        Redex::Lambda(param) => format!("^{}.", param),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Func;
+
+    fn setup() -> Context {
+        Context::from(vec![
+            Func::new("i".into(), vec!["x".into()], "x".into()),
+            Func::new("k".into(), vec!["x".into(), "y".into()], "x".into()),
+        ])
+    }
+
+    fn run(expr: Expr, input: &str) -> String {
+        let context = setup();
+        let mut debugger =
+            Debugger::new(expr, &context, DisplayStyle::LazyK, 1000, Strategy::Normal);
+        let mut output = Vec::new();
+        debugger.repl(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step() {
+        // `i`i`i:a
+        let expr = Expr::a(
+            "i".into(),
+            Expr::a("i".into(), Expr::a("i".into(), ":a".into())),
+        );
+
+        assert_eq!(
+            run(expr, "s\ns 5\n"),
+            "[0] `i`i`i:a\n\
+             (debug) [1] `i`i:a\n\
+             (debug) normal form\n[3] :a\n\
+             (debug) "
+        );
+    }
+
+    #[test]
+    fn test_break_apply() {
+        // ``k`i:a:b
+        let expr = Expr::a(
+            Expr::a("k".into(), Expr::a("i".into(), ":a".into())),
+            ":b".into(),
+        );
+
+        assert_eq!(
+            run(expr, "b i\nc\nr\nc\n"),
+            "[0] ``k`i:a:b\n\
+             (debug) breakpoint 1: apply i\n\
+             (debug) breakpoint: about to apply i\n[1] `i:a\n\
+             (debug) next: `i:a\n\
+             (debug) normal form\n[2] :a\n\
+             (debug) "
+        );
+    }
+
+    #[test]
+    fn test_break_size() {
+        // ``k``:a:b:c:d
+        let expr = Expr::a(
+            Expr::a(
+                "k".into(),
+                Expr::a(Expr::a(":a".into(), ":b".into()), ":c".into()),
+            ),
+            ":d".into(),
+        );
+
+        let context = setup();
+        let mut debugger =
+            Debugger::new(expr, &context, DisplayStyle::LazyK, 1000, Strategy::Normal);
+        debugger.add_breakpoint(Breakpoint::Size(3));
+        assert_eq!(debugger.step(10), "breakpoint: size > 3\n[1] ``:a:b:c");
+    }
+
+    #[test]
+    fn test_stack() {
+        // `:a``k`i:b:c
+        let expr = Expr::a(
+            ":a".into(),
+            Expr::a(
+                Expr::a("k".into(), Expr::a("i".into(), ":b".into())),
+                ":c".into(),
+            ),
+        );
+
+        assert_eq!(
+            run(expr, "stack\nr\nq\n"),
+            "[0] `:a``k`i:b:c\n\
+             (debug) #0: `:a``k`i:b:c\n#1: arg 0: ``k`i:b:c\n\
+             (debug) next: ``k`i:b:c\n\
+             (debug) "
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        // ``k``:a:b:c:d
+        let expr = Expr::a(
+            Expr::a(
+                "k".into(),
+                Expr::a(Expr::a(":a".into(), ":b".into()), ":c".into()),
+            ),
+            ":d".into(),
+        );
+
+        let context = setup();
+        let mut debugger = Debugger::new(
+            expr.clone(),
+            &context,
+            DisplayStyle::LazyK,
+            1000,
+            Strategy::Normal,
+        );
+        debugger.limits.size = 3;
+        assert_eq!(
+            debugger.step(10),
+            "error: term size limit exceeded: 5 nodes > 3\n[0] ``k``:a:b:c:d"
+        );
+    }
+}
@@ -1,86 +1,523 @@
+mod json;
+mod text;
+
+pub use json::JsonOutput;
+pub use text::TextOutput;
+
+use std::collections::HashSet;
+
 use crate::command::Command;
+use crate::config::{step_limit, strategy, Strategy};
 use crate::context::Context;
-// use crate::display_style::DisplayStyle;
-use crate::config::{display_style, step_limit, DisplayStyle};
 use crate::evaluate::EvalSteps;
-use crate::expression::display::ecmascript::ECMAScriptStyle as ExprECMAScriptStyle;
-use crate::expression::display::lazy_k::LazyKStyle as ExprLazyKStyle;
-use crate::function::display::ecmascript::ECMAScriptStyle as FuncECMAScriptStyle;
-use crate::function::display::lazy_k::LazyKStyle as FuncLazyKStyle;
+use crate::expression::Expr;
+use crate::function::Func;
+use crate::history::{Change, Timeline};
+use crate::identifier::Ident;
+use crate::limit::{LimitExceeded, Limits};
+use crate::primitive::Primitive;
+use crate::stats::{Profiler, Stats};
+
+/// 簡約を止めた理由
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// 正規形に達した
+    NormalForm,
+
+    /// 正規形に達する前にステップ数の上限に達した
+    StepLimit,
+
+    /// 式の大きさ・深さ・経過時間の上限を超えた
+    Exceeded(LimitExceeded),
+
+    /// それ以上簡約できないが、どの節にも一致しなかったパターンで定義した関数の適用が残っている
+    NoMatch(Ident),
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::NormalForm => "normal_form",
+            Status::StepLimit => "step_limit",
+            Status::Exceeded(LimitExceeded::Size { .. }) => "size_limit",
+            Status::Exceeded(LimitExceeded::Depth { .. }) => "depth_limit",
+            Status::Exceeded(LimitExceeded::Time { .. }) => "time_limit",
+            Status::NoMatch(_) => "no_match",
+        }
+    }
+}
+
+/// 簡約の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reduction {
+    /// 最後に得た式、1 ステップも簡約できなければ None
+    pub last: Option<Expr>,
+
+    pub status: Status,
+    pub stats: Option<Stats>,
+    pub strategy: Strategy,
+}
+
+/// 等式の検査の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    /// 左辺を簡約して得た式
+    pub lhs: Expr,
+
+    /// 右辺を簡約して得た式
+    pub rhs: Expr,
+
+    /// 両辺とも正規形に達したなら NormalForm、そうでなければ先に上限に達した側の状態
+    pub status: Status,
+}
+
+impl Assertion {
+    /// 両辺が正規形に達し、それらが α 同値なら真
+    pub fn passed(&self) -> bool {
+        self.status == Status::NormalForm && self.lhs.alpha_eq(&self.rhs)
+    }
+}
+
+/// どのコマンドで簡約しているか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReduceKind {
+    /// 途中の式をすべて表示する
+    Eval,
+
+    /// 最後の式だけを表示する
+    EvalLast,
+
+    /// 最後の式と統計情報を表示する
+    Stats,
+}
+
+/// Engine がコマンドを実行するときに起きる出来事
+///
+/// 出力の形式ごとに EventSink を実装して、同じ出来事を異なる形で表示する
+#[derive(Debug)]
+pub enum EngineEvent<'a> {
+    /// コマンドを受け取った
+    Command(&'a Command),
+
+    /// 式の簡約を始める
+    Reduce {
+        kind: ReduceKind,
+        input: &'a Expr,
+        strategy: Strategy,
+    },
+
+    /// 簡約を 1 ステップ進めた
+    Step(&'a Expr),
+
+    /// 簡約を終えた
+    Reduced(&'a Reduction),
+
+    /// 等式を検査した
+    Asserted(&'a Assertion),
+
+    /// 2 つの関数を同じシンボルに適用した式を比べた
+    Equivalence {
+        lhs: &'a Expr,
+        rhs: &'a Expr,
+        assertion: &'a Assertion,
+    },
+
+    /// 式からラムダ抽象を除去した
+    Unlambda { input: &'a Expr, result: &'a Expr },
+
+    /// 関数を定義した
+    Defined(&'a Func),
+
+    /// 関数を削除した
+    Deleted(&'a Ident),
+
+    /// 定義や削除によって Context が変わった
+    ContextChanged(&'a Context),
+
+    /// 関数を検索した、定義されていなければ function は None
+    Info {
+        name: &'a Ident,
+        function: Option<&'a Func>,
+    },
+
+    /// 定義済みの関数を名前順に並べた
+    Global(Vec<&'a Func>),
+
+    /// 定義の変更の履歴、undone は取り消した順に並べる (末尾が次にやり直す変更)
+    History {
+        done: &'a [Change],
+        undone: &'a [Change],
+    },
+
+    /// 定義の変更を取り消した、取り消した順に並べる
+    Undone(&'a [Change]),
+
+    /// 取り消した定義の変更をやり直した、やり直した順に並べる
+    Redone(&'a [Change]),
+
+    /// 変更の前後で定義が異なる関数を名前順に並べた
+    Diff(&'a [(Ident, Option<Func>, Option<Func>)]),
+
+    /// コマンドを実行できなかった
+    Error(&'a str),
+}
+
+/// Engine の出来事を受け取って表示する
+pub trait EventSink {
+    fn emit(&mut self, event: EngineEvent);
+}
 
 pub struct Engine {
-    context: Context,
-    display_style: DisplayStyle,
+    timeline: Timeline,
+
+    /// 簡約中の式の大きさ・深さ・経過時間の上限
+    limits: Limits,
+
+    /// Eval でも簡約の統計情報を集める
+    show_stats: bool,
+
+    strategy: Strategy,
 }
 
 impl Engine {
     pub fn new(context: Context) -> Self {
+        Self::with_timeline(Timeline::new(context))
+    }
+
+    /// 履歴から組み立てた定義の変更の履歴を引き継ぐ
+    pub fn with_timeline(timeline: Timeline) -> Self {
         Self {
-            context,
-            display_style: display_style(),
+            timeline,
+            limits: Limits::from_config(),
+            show_stats: false,
+            strategy: strategy(),
         }
     }
 
-    pub fn run(&mut self, command: Command) {
+    pub fn show_stats(&mut self, enabled: bool) {
+        self.show_stats = enabled;
+    }
+
+    pub fn strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    /// コマンドを実行して出来事を sink に送る、簡約したならその結果を返す
+    pub fn run(&mut self, command: Command, sink: &mut dyn EventSink) -> Option<Reduction> {
+        sink.emit(EngineEvent::Command(&command));
+
         match command {
             Command::Del(i) => {
-                self.context.del(&i);
+                sink.emit(EngineEvent::Deleted(&i));
+                self.timeline.apply(Command::Del(i));
+                sink.emit(EngineEvent::ContextChanged(self.timeline.context()));
+                None
             }
 
             Command::Update(f) => {
-                self.context.def(f);
+                sink.emit(EngineEvent::Defined(&f));
+                self.timeline.apply(Command::Update(f));
+                sink.emit(EngineEvent::ContextChanged(self.timeline.context()));
+                None
             }
 
-            Command::Eval(e) => {
-                let steps = EvalSteps::new(e, &self.context);
-                for e in steps.take(step_limit()) {
-                    match &self.display_style {
-                        DisplayStyle::LazyK => println!("→ {}", ExprLazyKStyle(&e)),
-                        DisplayStyle::Ecmascript => println!("→ {}", ExprECMAScriptStyle(&e)),
-                    }
+            Command::Data(d) => {
+                for f in d.funcs() {
+                    sink.emit(EngineEvent::Defined(&f));
                 }
+                self.timeline.apply(Command::Data(d));
+                sink.emit(EngineEvent::ContextChanged(self.timeline.context()));
+                None
+            }
+
+            Command::Eval(e) => {
+                Some(self.reduce(ReduceKind::Eval, self.strategy, e, step_limit(), sink))
             }
 
             Command::EvalLast(e) => {
-                match &self.display_style {
-                    DisplayStyle::LazyK => println!("{}", ExprLazyKStyle(&e)),
-                    DisplayStyle::Ecmascript => println!("{}", ExprECMAScriptStyle(&e)),
-                }
+                Some(self.reduce(ReduceKind::EvalLast, self.strategy, e, 100, sink))
+            }
 
-                let mut steps = EvalSteps::new(e, &self.context);
-                if let (Some(e), _continue) = steps.eval_last(100) {
-                    println!("→ ...");
-                    match &self.display_style {
-                        DisplayStyle::LazyK => println!("→ {}", ExprLazyKStyle(&e)),
-                        DisplayStyle::Ecmascript => println!("→ {}", ExprECMAScriptStyle(&e)),
-                    }
-                } else {
-                    // TODO
-                }
+            Command::Stats(e) => {
+                Some(self.reduce(ReduceKind::Stats, self.strategy, e, step_limit(), sink))
+            }
+
+            Command::Whnf(e) => {
+                Some(self.reduce(ReduceKind::Eval, Strategy::WeakHead, e, step_limit(), sink))
+            }
+
+            Command::Hnf(e) => {
+                Some(self.reduce(ReduceKind::Eval, Strategy::Head, e, step_limit(), sink))
+            }
+
+            Command::Assert(lhs, rhs) => {
+                sink.emit(EngineEvent::Asserted(&self.assert(lhs, rhs)));
+                None
             }
 
-            Command::Info(i) => match self.context.get(&i) {
-                Some(f) => match &self.display_style {
-                    DisplayStyle::LazyK => println!("{}", FuncLazyKStyle(&f)),
-                    DisplayStyle::Ecmascript => println!("{}", FuncECMAScriptStyle(&f)),
-                },
+            Command::Equiv(l, r) => {
+                // 未定義の名前は自由変数のまま比べることになり、結果に意味が無い
+                let context = self.timeline.context();
+                let unknown = [&l, &r]
+                    .into_iter()
+                    .filter(|i| !is_defined(i, context))
+                    .map(|i| i.label())
+                    .collect::<Vec<_>>();
+                if !unknown.is_empty() {
+                    let message = format!("unknown function: {}", unknown.join(", "));
+                    sink.emit(EngineEvent::Error(&message));
+                    return None;
+                }
+
+                let (lhs, rhs) = self.generic_applications(&l, &r);
+                let assertion = self.assert(lhs.clone(), rhs.clone());
+                sink.emit(EngineEvent::Equivalence {
+                    lhs: &lhs,
+                    rhs: &rhs,
+                    assertion: &assertion,
+                });
+                None
+            }
 
-                None => println!("{0} = {0}", i),
-            },
+            Command::Info(i) => {
+                sink.emit(EngineEvent::Info {
+                    name: &i,
+                    function: self.timeline.context().get(&i),
+                });
+                None
+            }
 
             Command::Global => {
-                self.context.for_each(|_i, f| println!("{}", f));
+                let mut functions = Vec::new();
+                self.timeline.context().for_each(|_, f| functions.push(f));
+                functions.sort_by(|l, r| l.name().label().cmp(r.name().label()));
+                sink.emit(EngineEvent::Global(functions));
+                None
             }
 
             Command::Unlambda(e) => {
-                match &self.display_style {
-                    DisplayStyle::LazyK => println!("{}", ExprLazyKStyle(&e)),
-                    DisplayStyle::Ecmascript => println!("{}", ExprECMAScriptStyle(&e)),
+                let result = e.clone().unlambda();
+                sink.emit(EngineEvent::Unlambda {
+                    input: &e,
+                    result: &result,
+                });
+                None
+            }
+
+            Command::History => {
+                sink.emit(EngineEvent::History {
+                    done: self.timeline.done(),
+                    undone: self.timeline.undone(),
+                });
+                None
+            }
+
+            Command::Undo(n) => {
+                let n = self.timeline.undo(n);
+                let undone = self.timeline.undone();
+                let mut changes = undone[undone.len() - n..].to_vec();
+                changes.reverse();
+                sink.emit(EngineEvent::Undone(&changes));
+                sink.emit(EngineEvent::ContextChanged(self.timeline.context()));
+                None
+            }
+
+            Command::Redo(n) => {
+                let n = self.timeline.redo(n);
+                let done = self.timeline.done();
+                sink.emit(EngineEvent::Redone(&done[done.len() - n..]));
+                sink.emit(EngineEvent::ContextChanged(self.timeline.context()));
+                None
+            }
+
+            Command::Diff(n) => {
+                sink.emit(EngineEvent::Diff(&self.timeline.diff(n)));
+                None
+            }
+
+            // 簡約列の先頭や末尾だけを表示するコマンドはまだ実行できない
+            Command::EvalHead(n, _) => {
+                let message = format!("`!{}` is not implemented yet", n);
+                sink.emit(EngineEvent::Error(&message));
+                None
+            }
+
+            Command::EvalTail(n, _) => {
+                let message = format!("`!-{}` is not implemented yet", n);
+                sink.emit(EngineEvent::Error(&message));
+                None
+            }
+        }
+    }
+
+    /// 両辺を正規形まで簡約して比べる
+    fn assert(&self, lhs: Expr, rhs: Expr) -> Assertion {
+        let (lhs, l_status) = self.normalize(lhs);
+        let (rhs, r_status) = self.normalize(rhs);
+        let status = match l_status {
+            Status::NormalForm => r_status,
+            _ => l_status,
+        };
+        Assertion { lhs, rhs, status }
+    }
+
+    /// 2 つの関数を、どちらの本体にも現れない同じシンボルの列に適用した式を作る
+    ///
+    /// 引数の個数は本体の先頭のラムダ抽象も数えた多い方に合わせる (η 展開)
+    fn generic_applications(&self, l: &Ident, r: &Ident) -> (Expr, Expr) {
+        let context = self.timeline.context();
+        let mut used = HashSet::new();
+        let mut arity = 0;
+        for f in [l, r].into_iter().filter_map(|i| context.get(i)) {
+            for clause in f.clauses() {
+                for e in clause.patterns().iter().chain([clause.body()]) {
+                    used.extend(e.symbols().into_iter().cloned());
                 }
-                println!("== {}", e.unlambda());
             }
 
-            _ => panic!("not implemented"),
+            let mut n = f.arity();
+            let mut body = f.body();
+            while let Expr::Lambda { body: inner, .. } = body {
+                n += 1;
+                body = inner;
+            }
+            arity = arity.max(n);
+        }
+
+        let mut lhs = Expr::Variable(l.clone());
+        let mut rhs = Expr::Variable(r.clone());
+        let mut names = ('a'..='z')
+            .map(String::from)
+            .chain((0..).map(|n| format!("A{}", n)))
+            .map(|label| Ident::new(&label))
+            .filter(|i| !used.contains(i));
+        for _ in 0..arity {
+            let arg = Expr::Symbol(names.next().unwrap());
+            lhs = Expr::a(lhs, arg.clone());
+            rhs = Expr::a(rhs, arg);
+        }
+        (lhs, rhs)
+    }
+
+    /// ラムダ抽象の本体も含めて式を正規形まで簡約する、途中の式は出来事として送らない
+    fn normalize(&self, e: Expr) -> (Expr, Status) {
+        normalize(e, self.timeline.context(), &self.limits)
+    }
+
+    /// 式を strategy に従って最大 limit ステップ簡約する
+    fn reduce(
+        &self,
+        kind: ReduceKind,
+        strategy: Strategy,
+        e: Expr,
+        limit: usize,
+        sink: &mut dyn EventSink,
+    ) -> Reduction {
+        sink.emit(EngineEvent::Reduce {
+            kind,
+            input: &e,
+            strategy,
+        });
+
+        let profile = kind == ReduceKind::Stats || self.show_stats;
+        let watchdog = self.limits.start();
+        let mut profiler = profile.then(|| Profiler::start(&e));
+        let mut last = None;
+
+        let mut steps = EvalSteps::with_strategy(e, self.timeline.context(), strategy);
+        let mut status = Status::StepLimit;
+        for _ in 0..limit {
+            let Some(e) = steps.next() else {
+                status = Status::NormalForm;
+                break;
+            };
+            if let Err(err) = watchdog.check(&e) {
+                status = Status::Exceeded(err);
+                break;
+            }
+            if let Some(profiler) = profiler.as_mut() {
+                profiler.record(steps.redex(), &e);
+            }
+            sink.emit(EngineEvent::Step(&e));
+            last = Some(e);
+        }
+
+        // ちょうど上限のステップ数で正規形に達したのかどうかを確かめる
+        // 確かめるための 1 ステップも式の大きさや時間の上限を超えないか調べる
+        if status == Status::StepLimit {
+            match steps.next() {
+                None => status = Status::NormalForm,
+                Some(e) => {
+                    if let Err(err) = watchdog.check(&e) {
+                        status = Status::Exceeded(err);
+                    }
+                }
+            }
+        }
+        // 組み込みの関数の結果が上限を超えて止まったのなら、正規形ではない
+        if let (Status::NormalForm, Some(err)) = (&status, steps.exceeded()) {
+            status = Status::Exceeded(err.clone());
+        }
+        if let (Status::NormalForm, Some(name)) = (&status, steps.stuck()) {
+            status = Status::NoMatch(name);
+        }
+
+        let reduction = Reduction {
+            last,
+            status,
+            stats: profiler.map(Profiler::finish),
+            strategy,
+        };
+        sink.emit(EngineEvent::Reduced(&reduction));
+        reduction
+    }
+}
+
+/// 関数、組み込みの関数またはプリミティブとして定義されていれば真
+fn is_defined(id: &Ident, context: &Context) -> bool {
+    context.get(id).is_some()
+        || context.builtin(id).is_some()
+        || Primitive::lookup(&Expr::Variable(id.clone()), context).is_some()
+}
+
+/// ラムダ抽象の本体も含めて式を正規形まで簡約し、最後の式と止めた理由を返す
+///
+/// 正規形に 0 引数の関数が残っていれば、本体に置き換えてさらに簡約する
+pub fn normalize(e: Expr, context: &Context, limits: &Limits) -> (Expr, Status) {
+    let watchdog = limits.start();
+    let mut remaining = step_limit();
+
+    let mut last = e;
+    loop {
+        let mut steps = EvalSteps::with_strategy(last.clone(), context, Strategy::Full);
+        loop {
+            let Some(e) = steps.next() else {
+                if let Some(err) = steps.exceeded() {
+                    return (last, Status::Exceeded(err.clone()));
+                }
+                break;
+            };
+            if remaining == 0 {
+                return (last, Status::StepLimit);
+            }
+            remaining -= 1;
+            if let Err(err) = watchdog.check(&e) {
+                return (e, Status::Exceeded(err));
+            }
+            last = e;
+        }
+
+        // 定数の展開も 1 ステップとして数え、循環する定義でも止まるようにする
+        let Some(e) = last.expand_constants(context) else {
+            return (last, Status::NormalForm);
+        };
+        if remaining == 0 {
+            return (last, Status::StepLimit);
+        }
+        remaining -= 1;
+        if let Err(err) = watchdog.check(&e) {
+            return (e, Status::Exceeded(err));
         }
+        last = e;
     }
 }
@@ -0,0 +1,515 @@
+//! Engine の出来事を 1 コマンドにつき 1 行の JSON として出力する
+
+use std::io::Write;
+
+use serde_json::{json, Value};
+
+use crate::command::Command;
+use crate::config::{display_style, DisplayStyle};
+use crate::expression::display::ecmascript::ECMAScriptStyle as ExprECMAScriptStyle;
+use crate::expression::display::lazy_k::LazyKStyle as ExprLazyKStyle;
+use crate::expression::Expr;
+use crate::format::format_command;
+use crate::function::Func;
+use crate::history::Change;
+use crate::stats::Stats;
+
+use super::{EngineEvent, EventSink, ReduceKind, Status};
+
+pub struct JsonOutput<W: Write> {
+    writer: W,
+    display_style: DisplayStyle,
+
+    /// 式の文字列に加えて構文木も出力する
+    ast: bool,
+
+    /// 実行中の簡約の出力、簡約を終えたら書き出す
+    reduction: Option<(ReduceKind, Value, Vec<Value>)>,
+}
+
+impl<W: Write> JsonOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            display_style: display_style(),
+            ast: false,
+            reduction: None,
+        }
+    }
+
+    pub fn ast(&mut self, enabled: bool) {
+        self.ast = enabled;
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn print(&mut self, value: Value) {
+        writeln!(self.writer, "{}", value).expect("出力に失敗しました");
+    }
+
+    /// 定義の変更を、定義か削除かの区別と印字したコマンドのオブジェクトにする
+    fn change(&self, change: &Change) -> Value {
+        let kind = match change.command {
+            Command::Del(_) => "delete",
+            Command::Data(_) => "data",
+            _ => "define",
+        };
+        json!({
+            "kind": kind,
+            "name": change.name().label(),
+            "command": format_command(&change.command, &self.display_style),
+        })
+    }
+
+    /// 式を JSON にする、構文木を出力する設定なら文字列と構文木の組にする
+    fn expr(&self, e: &Expr) -> Value {
+        let printed = match self.display_style {
+            DisplayStyle::LazyK => ExprLazyKStyle(e).to_string(),
+            DisplayStyle::Ecmascript => ExprECMAScriptStyle(e).to_string(),
+        };
+
+        if self.ast {
+            json!({ "expr": printed, "ast": ast(e) })
+        } else {
+            json!(printed)
+        }
+    }
+}
+
+impl<W: Write> EventSink for JsonOutput<W> {
+    fn emit(&mut self, event: EngineEvent) {
+        match event {
+            EngineEvent::Reduce { kind, input, .. } => {
+                self.reduction = Some((kind, self.expr(input), Vec::new()));
+            }
+
+            EngineEvent::Step(e) => {
+                if let Some((ReduceKind::Eval, _, _)) = &self.reduction {
+                    let step = self.expr(e);
+                    if let Some((_, _, steps)) = &mut self.reduction {
+                        steps.push(step);
+                    }
+                }
+            }
+
+            EngineEvent::Reduced(reduction) => {
+                let Some((kind, input, steps)) = self.reduction.take() else {
+                    return;
+                };
+
+                let command = match kind {
+                    ReduceKind::Eval => "eval",
+                    ReduceKind::EvalLast => "eval_last",
+                    ReduceKind::Stats => "stats",
+                };
+                let result = match &reduction.last {
+                    Some(e) => self.expr(e),
+                    None => input.clone(),
+                };
+
+                let mut output = json!({
+                    "command": command,
+                    "input": input,
+                    "result": result,
+                    "status": reduction.status.name(),
+                    "strategy": reduction.strategy.name(),
+                });
+                if kind == ReduceKind::Eval {
+                    output["steps"] = json!(steps);
+                }
+                match &reduction.status {
+                    Status::Exceeded(err) => output["error"] = json!(err.to_string()),
+                    Status::NoMatch(name) => output["stuck"] = json!(name.label()),
+                    _ => (),
+                }
+                if let Some(s) = &reduction.stats {
+                    output["stats"] = stats(s);
+                }
+                self.print(output);
+            }
+
+            EngineEvent::Asserted(assertion) => {
+                let mut output = json!({
+                    "command": "assert",
+                    "lhs": self.expr(&assertion.lhs),
+                    "rhs": self.expr(&assertion.rhs),
+                    "passed": assertion.passed(),
+                    "status": assertion.status.name(),
+                });
+                if let Status::Exceeded(err) = &assertion.status {
+                    output["error"] = json!(err.to_string());
+                }
+                self.print(output);
+            }
+
+            EngineEvent::Equivalence {
+                lhs,
+                rhs,
+                assertion,
+            } => {
+                let mut output = json!({
+                    "command": "equiv",
+                    "lhs": { "input": self.expr(lhs), "result": self.expr(&assertion.lhs) },
+                    "rhs": { "input": self.expr(rhs), "result": self.expr(&assertion.rhs) },
+                    "equivalent": assertion.passed(),
+                    "status": assertion.status.name(),
+                });
+                if let Status::Exceeded(err) = &assertion.status {
+                    output["error"] = json!(err.to_string());
+                }
+                self.print(output);
+            }
+
+            EngineEvent::Unlambda { input, result } => {
+                let output = json!({
+                    "command": "unlambda",
+                    "input": self.expr(input),
+                    "result": self.expr(result),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::Defined(f) => {
+                let output =
+                    json!({ "command": "define", "function": func(f, &self.display_style) });
+                self.print(output);
+            }
+
+            EngineEvent::Deleted(i) => {
+                self.print(json!({ "command": "delete", "name": i.label() }))
+            }
+
+            EngineEvent::Info { name, function } => {
+                let output = json!({
+                    "command": "info",
+                    "name": name.label(),
+                    "function": function.map(|f| func(f, &self.display_style)),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::Global(functions) => {
+                let output = json!({
+                    "command": "global",
+                    "functions": functions
+                        .iter()
+                        .map(|f| func(f, &self.display_style))
+                        .collect::<Vec<_>>(),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::History { done, undone } => {
+                let output = json!({
+                    "command": "history",
+                    "done": done.iter().map(|c| self.change(c)).collect::<Vec<_>>(),
+                    "undone": undone.iter().rev().map(|c| self.change(c)).collect::<Vec<_>>(),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::Undone(changes) => {
+                let output = json!({
+                    "command": "undo",
+                    "changes": changes.iter().map(|c| self.change(c)).collect::<Vec<_>>(),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::Redone(changes) => {
+                let output = json!({
+                    "command": "redo",
+                    "changes": changes.iter().map(|c| self.change(c)).collect::<Vec<_>>(),
+                });
+                self.print(output);
+            }
+
+            EngineEvent::Diff(diff) => {
+                let changes = diff
+                    .iter()
+                    .map(|(name, before, after)| {
+                        json!({
+                            "name": name.label(),
+                            "before": before.as_ref().map(|f| func(f, &self.display_style)),
+                            "after": after.as_ref().map(|f| func(f, &self.display_style)),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                self.print(json!({ "command": "diff", "changes": changes }));
+            }
+
+            EngineEvent::Error(message) => self.print(json!({ "error": message })),
+
+            EngineEvent::Command(_) | EngineEvent::ContextChanged(_) => (),
+        }
+    }
+}
+
+/// 関数定義を名前・引数・本体のオブジェクトにする
+///
+/// パターンで定義した関数は、節ごとのパターンと本体の列にする
+fn func(f: &Func, style: &DisplayStyle) -> Value {
+    let expr = |e: &Expr| match style {
+        DisplayStyle::LazyK => ExprLazyKStyle(e).to_string(),
+        DisplayStyle::Ecmascript => ExprECMAScriptStyle(e).to_string(),
+    };
+
+    if let ([clause], Some(params)) = (f.clauses(), f.clauses()[0].params()) {
+        return json!({
+            "name": f.name().label(),
+            "params": params.iter().map(|i| i.label()).collect::<Vec<_>>(),
+            "body": expr(clause.body()),
+        });
+    }
+
+    let clauses = f
+        .clauses()
+        .iter()
+        .map(|c| {
+            json!({
+                "params": c.patterns().iter().map(expr).collect::<Vec<_>>(),
+                "body": expr(c.body()),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "name": f.name().label(), "clauses": clauses })
+}
+
+fn stats(stats: &Stats) -> Value {
+    // 適用回数の多い順に並べる
+    let mut applications = stats.applications.iter().collect::<Vec<_>>();
+    applications.sort_by(|(l_name, l_count), (r_name, r_count)| {
+        r_count
+            .cmp(l_count)
+            .then(l_name.label().cmp(r_name.label()))
+    });
+
+    json!({
+        "steps": stats.steps,
+        "time_ms": stats.elapsed.as_secs_f64() * 1000.0,
+        "max_size": stats.max_size,
+        "max_depth": stats.max_depth,
+        "substitutions": stats.substitutions,
+        "renames": stats.renames,
+        "lambda_applications": stats.lambda_applications,
+        "function_applications": applications
+            .into_iter()
+            .map(|(name, count)| json!({ "name": name.label(), "count": count }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// 式の構文木を JSON にする
+///
+/// 深い式でもスタックを溢れさせないよう、後行順に値を積み上げて組み立てる
+fn ast(e: &Expr) -> Value {
+    enum Frame<'a> {
+        Visit(&'a Expr),
+        Apply,
+        Lambda(&'a str),
+    }
+
+    let mut frames = vec![Frame::Visit(e)];
+    let mut values: Vec<Value> = Vec::new();
+
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Visit(Expr::Variable(i)) => {
+                values.push(json!({ "type": "variable", "name": i.label() }))
+            }
+            Frame::Visit(Expr::Symbol(i)) => {
+                values.push(json!({ "type": "symbol", "name": i.label() }))
+            }
+            Frame::Visit(Expr::Apply { lhs, rhs }) => {
+                frames.push(Frame::Apply);
+                frames.push(Frame::Visit(rhs));
+                frames.push(Frame::Visit(lhs));
+            }
+            Frame::Visit(Expr::Lambda { param, body }) => {
+                frames.push(Frame::Lambda(param.label()));
+                frames.push(Frame::Visit(body));
+            }
+            Frame::Apply => {
+                let rhs = values.pop().unwrap();
+                let lhs = values.pop().unwrap();
+                values.push(json!({ "type": "apply", "lhs": lhs, "rhs": rhs }));
+            }
+            Frame::Lambda(param) => {
+                let body = values.pop().unwrap();
+                values.push(json!({ "type": "lambda", "param": param, "body": body }));
+            }
+        }
+    }
+
+    values.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::engine::Engine;
+    use crate::evaluate::EvalSteps;
+    use crate::format::parse_line;
+
+    #[test]
+    fn test_ast() {
+        let e = Expr::a(Expr::l("x".into(), Expr::v("x")), Expr::s("a"));
+
+        assert_eq!(
+            ast(&e),
+            json!({
+                "type": "apply",
+                "lhs": {
+                    "type": "lambda",
+                    "param": "x",
+                    "body": { "type": "variable", "name": "x" },
+                },
+                "rhs": { "type": "symbol", "name": "a" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let mut output = JsonOutput::new(Vec::new());
+        let mut engine = Engine::new(Context::default());
+        engine.run(parse_line("```skk:a").unwrap(), &mut output);
+        engine.run(parse_line("? k").unwrap(), &mut output);
+
+        let lines = String::from_utf8(output.into_inner()).unwrap();
+        let lines = lines
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                json!({
+                    "command": "eval",
+                    "input": "```skk:a",
+                    "steps": ["``k:a`k:a", ":a"],
+                    "result": ":a",
+                    "status": "normal_form",
+                    "strategy": "normal",
+                }),
+                json!({
+                    "command": "info",
+                    "name": "k",
+                    "function": { "name": "k", "params": ["x", "y"], "body": "x" },
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assert_cyclic_constants() {
+        let mut output = JsonOutput::new(Vec::new());
+        let mut engine = Engine::new(Context::new());
+        for line in ["A = B", "B = A", "LOOP = ^x.LOOP", "== A :x", "== LOOP :x"] {
+            engine.run(parse_line(line).unwrap(), &mut output);
+        }
+
+        // 循環する定数の展開もステップ数の上限で止まる
+        let lines = String::from_utf8(output.into_inner()).unwrap();
+        let statuses = lines
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .filter(|v| v["command"] == "assert")
+            .map(|v| (v["passed"].clone(), v["status"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                (json!(false), json!("step_limit")),
+                (json!(false), json!("step_limit")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_after_last_step() {
+        // 1 ステップごとに `:a を 1 つ加えて大きくなる式
+        let w = Expr::l(
+            "x".into(),
+            Expr::a(Expr::a("x".into(), "x".into()), ":a".into()),
+        );
+        let e = Expr::a(w.clone(), w);
+
+        let context = Context::new();
+        let mut steps = EvalSteps::new(e.clone(), &context);
+        let size = steps.nth(99).unwrap().size();
+
+        // 100 ステップ目までは上限に収まり、正規形かどうか確かめる 101 ステップ目で超える
+        let mut output = JsonOutput::new(Vec::new());
+        let mut engine = Engine::new(Context::new());
+        engine.limits.size = size;
+        engine.run(Command::EvalLast(e), &mut output);
+
+        let line = String::from_utf8(output.into_inner()).unwrap();
+        let output = serde_json::from_str::<Value>(&line).unwrap();
+        assert_eq!(output["status"], "size_limit");
+        assert_eq!(
+            output["error"],
+            format!("term size limit exceeded: {} nodes > {}", size + 2, size)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_to_church_size_limit() {
+        // 上限を超える Church 数への変換は止まったままにせず、大きさの上限を超えたと報告する
+        let n = Expr::s(&(crate::config::size_limit() + 1).to_string());
+        let mut output = JsonOutput::new(Vec::new());
+        let mut engine = Engine::new(Context::new());
+        engine.run(
+            Command::EvalLast(Expr::a("TO_CHURCH".into(), n)),
+            &mut output,
+        );
+
+        let line = String::from_utf8(output.into_inner()).unwrap();
+        let output = serde_json::from_str::<Value>(&line).unwrap();
+        assert_eq!(output["status"], "size_limit");
+        assert_eq!(
+            output["error"],
+            format!(
+                "term size limit exceeded: {} nodes > {}",
+                2 * crate::config::size_limit() + 5,
+                crate::config::size_limit()
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_implemented() {
+        let mut output = JsonOutput::new(Vec::new());
+        let mut engine = Engine::new(Context::default());
+        engine.run(parse_line("!2 ```skk:a").unwrap(), &mut output);
+        engine.run(parse_line("!-2 ```skk:a").unwrap(), &mut output);
+
+        let lines = String::from_utf8(output.into_inner()).unwrap();
+        let outputs = lines
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outputs,
+            vec![
+                json!({ "error": "`!2` is not implemented yet" }),
+                json!({ "error": "`!-2` is not implemented yet" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_func() {
+        let f = Func::new("k".into(), vec!["x".into(), "y".into()], "x".into());
+
+        assert_eq!(
+            func(&f, &DisplayStyle::LazyK),
+            json!({ "name": "k", "params": ["x", "y"], "body": "x" })
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! Engine の出来事を人が読むテキストとして表示する
+
+use std::fmt::Display;
+use std::io::{IsTerminal, Stderr, Write};
+
+use crate::command::display::ecmascript::ECMAScriptStyle as CommandECMAScriptStyle;
+use crate::command::display::lazy_k::LazyKStyle as CommandLazyKStyle;
+use crate::command::Command;
+use crate::config::{color, display_style, fold_definitions, DisplayStyle, Strategy};
+use crate::context::Context;
+use crate::expression::display::ecmascript::ECMAScriptStyle as ExprECMAScriptStyle;
+use crate::expression::display::fold::Folder;
+use crate::expression::display::lazy_k::LazyKStyle as ExprLazyKStyle;
+use crate::expression::Expr;
+use crate::function::display::ecmascript::ECMAScriptStyle as FuncECMAScriptStyle;
+use crate::function::display::lazy_k::LazyKStyle as FuncLazyKStyle;
+use crate::function::Func;
+use crate::history::Change;
+
+use super::{EngineEvent, EventSink, ReduceKind, Status};
+
+pub struct TextOutput<W: Write, E: Write = Stderr> {
+    writer: W,
+
+    /// 上限を超えたなどのエラーを書き出す先、既定では標準エラー出力
+    errors: E,
+
+    /// エラーの見出しを色付けする
+    color_errors: bool,
+
+    display_style: DisplayStyle,
+
+    /// 指定されていれば、この行幅に収まるよう式を改行・インデントする
+    line_width: Option<usize>,
+
+    /// 定義済み関数の本体と一致する部分式を関数名に置き換えて表示する
+    folder: Option<Folder>,
+
+    /// 途中の式や入力の式を表示せず、結果だけを表示する
+    quiet: bool,
+
+    /// 実行中の簡約の種類
+    kind: Option<ReduceKind>,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(writer: W, context: &Context) -> Self {
+        let color_errors = color().enabled(std::io::stderr().is_terminal());
+        let mut output = TextOutput::with_errors(writer, std::io::stderr(), context);
+        output.color_errors = color_errors;
+        output
+    }
+}
+
+impl<W: Write, E: Write> TextOutput<W, E> {
+    /// エラーを標準エラー出力の代わりに errors に書き出す
+    pub fn with_errors(writer: W, errors: E, context: &Context) -> Self {
+        Self {
+            writer,
+            errors,
+            color_errors: color().enabled(false),
+            display_style: display_style(),
+            line_width: None,
+            folder: fold_definitions().then(|| Folder::new(context)),
+            quiet: false,
+            kind: None,
+        }
+    }
+
+    pub fn line_width(&mut self, width: Option<usize>) {
+        self.line_width = width;
+    }
+
+    pub fn quiet(&mut self, enabled: bool) {
+        self.quiet = enabled;
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// 出力先とエラーの書き出し先を返す
+    pub fn into_parts(self) -> (W, E) {
+        (self.writer, self.errors)
+    }
+
+    fn print(&mut self, line: impl Display) {
+        writeln!(self.writer, "{}", line).expect("出力に失敗しました");
+    }
+
+    /// エラーを書き出す、設定に応じて見出しを色付けする
+    fn error(&mut self, err: &impl Display) {
+        let result = if self.color_errors {
+            writeln!(self.errors, "\x1b[31merror:\x1b[0m {}", err)
+        } else {
+            writeln!(self.errors, "error: {}", err)
+        };
+        result.expect("出力に失敗しました");
+    }
+
+    fn command(&self, command: &Command) -> String {
+        match &self.display_style {
+            DisplayStyle::LazyK => CommandLazyKStyle(command).to_string(),
+            DisplayStyle::Ecmascript => CommandECMAScriptStyle(command).to_string(),
+        }
+    }
+
+    fn func(&self, f: &Func) -> String {
+        match &self.display_style {
+            DisplayStyle::LazyK => FuncLazyKStyle(f).to_string(),
+            DisplayStyle::Ecmascript => FuncECMAScriptStyle(f).to_string(),
+        }
+    }
+
+    /// 取り消した、またはやり直した変更を表示する
+    fn changes(&mut self, action: &str, changes: &[Change]) {
+        if changes.is_empty() {
+            self.print(format!("nothing to {}", action));
+        }
+        for change in changes {
+            let line = format!("{}: {}", action, self.command(&change.command));
+            self.print(line);
+        }
+    }
+
+    /// 入力の式を表示する、quiet なら何もしない
+    fn echo(&mut self, e: &Expr) {
+        if !self.quiet {
+            let line = self.show("", e);
+            self.print(line);
+        }
+    }
+
+    /// 簡約の最後の式を表示する
+    fn result(&mut self, e: &Expr) {
+        if self.quiet {
+            let line = self.show("", e);
+            self.print(line);
+        } else {
+            self.print("→ ...");
+            let line = self.show("→ ", e);
+            self.print(line);
+        }
+    }
+
+    /// 式を表示スタイルに従って印字し、先頭に prefix を付ける
+    ///
+    /// 行幅が指定されていれば改行し、2 行目以降は prefix の幅だけ字下げする
+    fn show(&self, prefix: &str, e: &Expr) -> String {
+        let indent = prefix.chars().count();
+
+        let folded;
+        let e = match &self.folder {
+            Some(folder) => {
+                folded = folder.fold(e);
+                &folded
+            }
+            None => e,
+        };
+
+        let printed = match (&self.display_style, self.line_width) {
+            (DisplayStyle::LazyK, None) => ExprLazyKStyle(e).to_string(),
+            (DisplayStyle::Ecmascript, None) => ExprECMAScriptStyle(e).to_string(),
+            (DisplayStyle::LazyK, Some(width)) => {
+                ExprLazyKStyle(e).pretty(width.saturating_sub(indent))
+            }
+            (DisplayStyle::Ecmascript, Some(width)) => {
+                ExprECMAScriptStyle(e).pretty(width.saturating_sub(indent))
+            }
+        };
+
+        format!(
+            "{}{}",
+            prefix,
+            printed.replace('\n', &format!("\n{}", " ".repeat(indent)))
+        )
+    }
+}
+
+impl<W: Write, E: Write> EventSink for TextOutput<W, E> {
+    fn emit(&mut self, event: EngineEvent) {
+        match event {
+            EngineEvent::Command(command) => {
+                if !self.quiet {
+                    let line = self.command(command);
+                    self.print(line);
+                }
+            }
+
+            EngineEvent::Reduce {
+                kind,
+                input,
+                strategy,
+            } => {
+                self.kind = Some(kind);
+                if strategy != Strategy::Normal && !self.quiet {
+                    self.print(format!("strategy: {}", strategy.name()));
+                }
+                if kind != ReduceKind::Eval {
+                    self.echo(input);
+                }
+            }
+
+            EngineEvent::Step(e) => {
+                if self.kind == Some(ReduceKind::Eval) && !self.quiet {
+                    let line = self.show("→ ", e);
+                    self.print(line);
+                }
+            }
+
+            EngineEvent::Reduced(reduction) => {
+                if let Some(e) = &reduction.last {
+                    match self.kind {
+                        Some(ReduceKind::Eval) if self.quiet => {
+                            let line = self.show("", e);
+                            self.print(line);
+                        }
+                        Some(ReduceKind::Eval) => (),
+                        _ => self.result(e),
+                    }
+                }
+                match &reduction.status {
+                    Status::Exceeded(err) => self.error(err),
+                    Status::NoMatch(name) => {
+                        self.print(format!("no clause matched: {}", name.label()))
+                    }
+                    _ => (),
+                }
+                if let Some(stats) = &reduction.stats {
+                    self.print(format!("strategy: {}", reduction.strategy.name()));
+                    self.print(stats);
+                }
+                self.kind = None;
+            }
+
+            EngineEvent::Asserted(assertion) => {
+                if assertion.passed() {
+                    self.print("ok");
+                } else {
+                    self.print("assertion failed");
+                    let line = self.show(" left: ", &assertion.lhs);
+                    self.print(line);
+                    let line = self.show("right: ", &assertion.rhs);
+                    self.print(line);
+                }
+                if let Status::Exceeded(err) = &assertion.status {
+                    self.error(err);
+                }
+            }
+
+            EngineEvent::Equivalence {
+                lhs,
+                rhs,
+                assertion,
+            } => {
+                if assertion.passed() {
+                    self.print("equivalent on generic arguments");
+                } else {
+                    self.print("not equivalent");
+                    let prefix = format!("{} → ", self.show("", lhs));
+                    let line = self.show(&prefix, &assertion.lhs);
+                    self.print(line);
+                    let prefix = format!("{} → ", self.show("", rhs));
+                    let line = self.show(&prefix, &assertion.rhs);
+                    self.print(line);
+                }
+                if let Status::Exceeded(err) = &assertion.status {
+                    self.error(err);
+                }
+            }
+
+            EngineEvent::Unlambda { input, result } => {
+                self.echo(input);
+                let prefix = if self.quiet { "" } else { "== " };
+                let line = self.show(prefix, result);
+                self.print(line);
+            }
+
+            EngineEvent::ContextChanged(context) => {
+                if self.folder.is_some() {
+                    self.folder = Some(Folder::new(context));
+                }
+            }
+
+            EngineEvent::Info { name, function } => match function {
+                Some(f) => {
+                    let line = self.func(f);
+                    self.print(line);
+                }
+                None => self.print(format!("{0} = {0}", name)),
+            },
+
+            EngineEvent::Global(functions) => {
+                for f in functions {
+                    self.print(f);
+                }
+            }
+
+            EngineEvent::History { done, undone } => {
+                if done.is_empty() && undone.is_empty() {
+                    self.print("no changes");
+                }
+                for (n, change) in done.iter().enumerate() {
+                    let line = format!("{}: {}", n + 1, self.command(&change.command));
+                    self.print(line);
+                }
+                for change in undone.iter().rev() {
+                    let line = format!("undone: {}", self.command(&change.command));
+                    self.print(line);
+                }
+            }
+
+            EngineEvent::Undone(changes) => self.changes("undo", changes),
+
+            EngineEvent::Redone(changes) => self.changes("redo", changes),
+
+            EngineEvent::Diff(diff) => {
+                if diff.is_empty() {
+                    self.print("no changes");
+                }
+                // 節が複数ある関数は、節ごとの行に印を付ける
+                for (_, before, after) in diff {
+                    for (sign, f) in [("-", before), ("+", after)] {
+                        if let Some(f) = f {
+                            for line in self.func(f).lines() {
+                                self.print(format!("{} {}", sign, line));
+                            }
+                        }
+                    }
+                }
+            }
+
+            EngineEvent::Error(message) => self.error(&message),
+
+            EngineEvent::Defined(_) | EngineEvent::Deleted(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::format::parse_line;
+
+    fn run(context: Context, quiet: bool, lines: &[&str]) -> String {
+        let mut output = TextOutput::new(Vec::new(), &context);
+        output.quiet(quiet);
+
+        let mut engine = Engine::new(context);
+        for line in lines {
+            engine.run(parse_line(line).unwrap(), &mut output);
+        }
+
+        String::from_utf8(output.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(
+            run(Context::default(), false, &["```skk:a"]),
+            "```skk:a\n→ ``k:a`k:a\n→ :a\n"
+        );
+        assert_eq!(run(Context::default(), true, &["```skk:a"]), ":a\n");
+    }
+
+    #[test]
+    fn test_error_output() {
+        // エラーは出力とは別の書き出し先に書く
+        let context = Context::default();
+        let mut output = TextOutput::with_errors(Vec::new(), Vec::new(), &context);
+        let mut engine = Engine::new(context);
+        engine.limits.size = 5;
+        engine.run(parse_line("```skk:a").unwrap(), &mut output);
+
+        let (out, err) = output.into_parts();
+        assert_eq!(String::from_utf8(out).unwrap(), "```skk:a\n");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "error: term size limit exceeded: 7 nodes > 5\n"
+        );
+    }
+
+    #[test]
+    fn test_not_implemented() {
+        let context = Context::default();
+        let mut output = TextOutput::with_errors(Vec::new(), Vec::new(), &context);
+        let mut engine = Engine::new(context);
+        engine.run(parse_line("!2 ```skk:a").unwrap(), &mut output);
+
+        let (out, err) = output.into_parts();
+        assert_eq!(String::from_utf8(out).unwrap(), "!2 ```skk:a\n");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "error: `!2` is not implemented yet\n"
+        );
+    }
+
+    #[test]
+    fn test_whnf() {
+        assert_eq!(
+            run(Context::default(), false, &[":whnf ``k:a`ik"]),
+            ":whnf ``k:a`ik\nstrategy: whnf\n→ :a\n"
+        );
+        assert_eq!(
+            run(Context::default(), true, &[":hnf ``k^x.``ix`ik:a"]),
+            "^x.`x`ik\n"
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(
+            run(
+                Context::default(),
+                true,
+                &["== ``ADD 2 3 5", "== ``ADD 2 3 6"]
+            ),
+            "ok\n\
+             assertion failed\n \
+             left: ^f.^x.`f`f`f`f`fx\n\
+             right: ^f.^x.`f`f`f`f`f`fx\n"
+        );
+    }
+
+    #[test]
+    fn test_equiv() {
+        let lines = [
+            "``Kxy = x",
+            ":equiv K k",
+            ":equiv TRUE k",
+            "``Fxy = y",
+            ":equiv F k",
+        ];
+        assert_eq!(
+            run(Context::default(), true, &lines),
+            "equivalent on generic arguments\n\
+             equivalent on generic arguments\n\
+             not equivalent\n\
+             ``F:a:b → :b\n\
+             ``k:a:b → :a\n"
+        );
+    }
+
+    #[test]
+    fn test_equiv_unknown() {
+        let context = Context::default();
+        let mut output = TextOutput::with_errors(Vec::new(), Vec::new(), &context);
+        output.quiet(true);
+        let mut engine = Engine::new(context);
+        engine.run(parse_line(":equiv F k").unwrap(), &mut output);
+        engine.run(parse_line(":equiv F G").unwrap(), &mut output);
+
+        let (out, err) = output.into_parts();
+        assert_eq!(String::from_utf8(out).unwrap(), "");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "error: unknown function: F\nerror: unknown function: F, G\n"
+        );
+    }
+
+    #[test]
+    fn test_patterns() {
+        let lines = [
+            "`f:ZERO = :Z",
+            "`f`:SUCCn = n",
+            "`f`i`:SUCC:ZERO",
+            "`f:ONE",
+            ":diff 2",
+        ];
+        assert_eq!(
+            run(Context::default(), false, &lines),
+            "`f:ZERO = :Z\n\
+             `f`:SUCCn = n\n\
+             `f`i`:SUCC:ZERO\n\
+             → `f`:SUCC:ZERO\n\
+             → :ZERO\n\
+             `f:ONE\n\
+             no clause matched: f\n\
+             :diff 2\n\
+             + `f:ZERO = :Z\n\
+             + `f`:SUCCn = n\n"
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        let lines = [
+            "data OPTION = NONE | SOME x",
+            "```CASE_OPTION`SOME:a:NONE^x.x",
+            ":history",
+        ];
+        assert_eq!(
+            run(Context::new(), true, &lines),
+            ":a\n1: data OPTION = NONE | SOME x\n"
+        );
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let lines = [
+            "`ix = x", "`ix = :a", ":undo", "? i", ":redo", ":history", ":diff",
+        ];
+        assert_eq!(
+            run(Context::new(), true, &lines),
+            "undo: `ix = :a\n\
+             `ix = x\n\
+             redo: `ix = :a\n\
+             1: `ix = x\n\
+             2: `ix = :a\n\
+             - `ix = x\n\
+             + `ix = :a\n"
+        );
+
+        assert_eq!(
+            run(Context::new(), true, &[":undo", ":history"]),
+            "nothing to undo\nno changes\n"
+        );
+    }
+
+    #[test]
+    fn test_define_and_info() {
+        assert_eq!(
+            run(Context::new(), true, &["`ix = x", "? i", "? j"]),
+            "`ix = x\nj = j\n"
+        );
+    }
+}
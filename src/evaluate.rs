@@ -1,6 +1,12 @@
+use crate::builtin::Builtin;
+use crate::config::Strategy;
 use crate::context::Context;
-use crate::expression::Expr;
 use crate::expression::Expr::*;
+use crate::expression::{Expr, Node};
+use crate::function::Func;
+use crate::identifier::Ident;
+use crate::limit::{LimitExceeded, Limits};
+use crate::primitive::Primitive;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct EvalSteps<'a> {
@@ -8,6 +14,89 @@ pub struct EvalSteps<'a> {
     stack: Stack<'a>,
     context: &'a Context,
     step: Step,
+    redex: Option<Redex>,
+    position: Option<RedexPosition>,
+    exceeded: Option<LimitExceeded>,
+    strategy: Strategy,
+
+    /// Some なら、この部分木は仮引数を束縛するラムダ抽象で、本体をスタックの唯一の要素として持つ
+    binder: Option<Ident>,
+}
+
+/// 簡約された redex の頭部
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redex {
+    /// 定義済み関数の適用
+    Func(Ident),
+
+    /// ラムダ抽象の適用、仮引数の識別子を持つ
+    Lambda(Ident),
+}
+
+/// 簡約した redex の、簡約する前の式の中での位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedexPosition {
+    /// 根から redex を含む部分式までに辿った引数の番号、最初の引数が 0
+    ///
+    /// ラムダ抽象の本体を辿った場合も 0 になる
+    pub path: Vec<usize>,
+
+    /// redex が消費した引数の個数
+    pub arity: usize,
+}
+
+impl RedexPosition {
+    /// 根から redex を含む部分式までに辿った部分式を、根から順に返す
+    ///
+    /// 経路が式の形と合わなければ None を返す
+    pub fn frames<'e>(&self, expr: &'e Expr) -> Option<Vec<&'e Expr>> {
+        let mut frames = vec![expr];
+        let mut current = expr;
+        for &n in &self.path {
+            let (_, args) = spine(current);
+            current = match current {
+                Lambda { body, .. } if n == 0 => body,
+                _ => args.get(n).copied()?,
+            };
+            frames.push(current);
+        }
+        Some(frames)
+    }
+
+    /// 簡約する前の式から redex の部分式を取り出す
+    pub fn locate<'e>(&self, expr: &'e Expr) -> Option<&'e Expr> {
+        let mut redex = *self.frames(expr)?.last()?;
+        let (_, args) = spine(redex);
+        for _ in self.arity..args.len() {
+            let Apply { lhs, .. } = redex else {
+                return None;
+            };
+            redex = lhs;
+        }
+        (self.arity <= args.len()).then_some(redex)
+    }
+}
+
+/// 式を頭部と引数の列に分解する
+fn spine(expr: &Expr) -> (&Expr, Vec<&Expr>) {
+    let mut args = Vec::new();
+    let mut head = expr;
+    while let Apply { lhs, rhs } = head {
+        args.push(rhs.as_ref());
+        head = lhs;
+    }
+    args.reverse();
+    (head, args)
+}
+
+/// 簡約の 1 ステップの結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalStep {
+    /// 簡約した後の式
+    pub expr: Expr,
+
+    /// 簡約した redex の頭部
+    pub redex: Option<Redex>,
 }
 
 /// 簡約のステップ
@@ -21,106 +110,416 @@ enum Step {
 }
 
 impl EvalSteps<'_> {
-    pub fn new(expr: Expr, context: &Context) -> EvalSteps {
+    pub fn new(expr: Expr, context: &Context) -> EvalSteps<'_> {
+        EvalSteps::with_strategy(expr, context, Strategy::Normal)
+    }
+
+    pub fn with_strategy(expr: Expr, context: &Context, strategy: Strategy) -> EvalSteps<'_> {
         EvalSteps {
             expr,
             stack: Stack::new(),
             context,
             step: Step::LeftTree,
+            redex: None,
+            position: None,
+            exceeded: None,
+            strategy,
+            binder: None,
         }
     }
 
-    pub fn eval_last(&mut self, limit: usize) -> (Option<Expr>, bool) {
+    /// 直前のステップで簡約した redex の頭部
+    pub fn redex(&self) -> Option<&Redex> {
+        self.redex.as_ref()
+    }
+
+    /// 直前のステップで簡約した redex の、簡約する前の式の中での位置
+    pub fn redex_position(&self) -> Option<&RedexPosition> {
+        self.position.as_ref()
+    }
+
+    /// 組み込みの関数の結果が上限を超えるために簡約を続けられなかったなら、その上限
+    ///
+    /// このとき next は正規形に達していなくても None を返す
+    pub fn exceeded(&self) -> Option<&LimitExceeded> {
+        self.exceeded.as_ref()
+    }
+
+    /// 引数を簡約し終えてもどの節のパターンにも一致しなかった関数の適用が残っていれば、その関数の名前を返す
+    ///
+    /// 簡約を終えた部分木だけを調べるので、途中で捨てた適用や辿らなかった引数の中の適用は数えない
+    pub fn stuck(&self) -> Option<Ident> {
+        let mut pending = vec![self];
+        while let Some(steps) = pending.pop() {
+            if steps.step == Step::Done {
+                if let Some(Native::Rules(f)) = steps.strict_native() {
+                    if steps.args_done(f.arity()) {
+                        return Some(f.name().clone());
+                    }
+                }
+            }
+            pending.extend(steps.stack.0.iter());
+        }
+        None
+    }
+
+    /// 簡約を 1 ステップ進め、簡約した後の式と redex の頭部を返す
+    ///
+    /// 正規形に達していれば None を返す
+    pub fn next_step(&mut self) -> Option<EvalStep> {
+        let expr = self.next()?;
+        Some(EvalStep {
+            expr,
+            redex: self.redex.clone(),
+        })
+    }
+
+    /// 最大 limit ステップ簡約し、最後の式とステップ数の上限に達したかどうかを返す
+    ///
+    /// 各ステップの式は limits で見張り、上限を超えたらその時点で簡約をやめる
+    pub fn eval_last(
+        &mut self,
+        limit: usize,
+        limits: Limits,
+    ) -> Result<(Option<Expr>, bool), LimitExceeded> {
         assert!(0 < limit);
 
+        let watchdog = limits.start();
         if let Some(mut e) = self.next() {
+            watchdog.check(&e)?;
             for _ in 0..limit - 1 {
                 if let Some(next) = self.next() {
+                    watchdog.check(&next)?;
                     e = next;
                 } else {
-                    return (Some(e), false);
+                    return self.exceeded.clone().map_or(Ok((Some(e), false)), Err);
                 }
             }
 
             // TODO: ここの true は嘘をつくことがある、peekable で先読みして正しい結果を返すように変える
-            (Some(e), true)
+            Ok((Some(e), true))
         } else {
-            (None, false)
+            self.exceeded.clone().map_or(Ok((None, false)), Err)
         }
     }
 
+    /// 簡約途中の式全体を組み立てる
     fn expr(&self) -> Expr {
-        let mut expr = self.expr.clone();
+        enum Frame<'s, 'a> {
+            Visit(&'s EvalSteps<'a>),
+            Build(&'s EvalSteps<'a>),
+        }
+
+        let mut frames = vec![Frame::Visit(self)];
+        let mut done: Vec<Expr> = Vec::new();
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(steps) => {
+                    frames.push(Frame::Build(steps));
+                    // スタックの末尾が最初の引数なので、先頭から積めば最初の引数から組み立てられる
+                    for arg in steps.stack.0.iter() {
+                        frames.push(Frame::Visit(arg));
+                    }
+                }
 
-        for arg in self.stack.all() {
-            expr = Expr::a(expr, arg.expr());
+                Frame::Build(steps) => {
+                    let args = done.split_off(done.len() - steps.stack.len());
+                    if let Some(param) = &steps.binder {
+                        let body = args.into_iter().next().unwrap();
+                        done.push(Expr::l(param.clone(), body));
+                        continue;
+                    }
+
+                    let mut expr = steps.expr.clone();
+                    for arg in args {
+                        expr = Expr::a(expr, arg);
+                    }
+                    done.push(expr);
+                }
+            }
         }
 
-        expr
+        done.pop().unwrap()
     }
 }
 
 impl Iterator for EvalSteps<'_> {
     type Item = Expr;
 
+    /// 深い式でもスタックを溢れさせないよう、簡約を試みる部分木を親のスタックから取り出しながら辿る
     fn next(&mut self) -> Option<Self::Item> {
-        match self.step {
-            Step::LeftTree => self.left_tree(),
-            Step::RightTree(n) => self.right_tree(n),
-            Step::Done => None,
+        // 根から簡約を試みている部分木までの経路、親のスタック上の位置と取り出した部分木の組
+        let mut path: Vec<(usize, EvalSteps)> = Vec::new();
+        let mut redex = None;
+        let mut exceeded = None;
+
+        loop {
+            let current = match path.last_mut() {
+                Some((_, steps)) => steps,
+                None => &mut *self,
+            };
+
+            match current.step {
+                Step::LeftTree => match current.left_tree() {
+                    Some((r, arity)) => {
+                        let path = path.iter().map(|(n, _)| *n).collect();
+                        redex = Some((r, RedexPosition { path, arity }));
+                        break;
+                    }
+                    None if current.exceeded.is_some() => {
+                        exceeded = current.exceeded.take();
+                        break;
+                    }
+                    None => match (current.strict_native(), current.strategy) {
+                        // 組み込みの関数の引数はどの戦略でも簡約してから適用する
+                        // 引数を簡約し終えても適用できなかったなら、残りの引数へ進む
+                        (Some(native), _) if current.args_done(native.arity()) => {
+                            current.step = Step::RightTree(native.arity())
+                        }
+                        (Some(_), _) | (None, Strategy::Normal) => {
+                            current.step = Step::RightTree(0)
+                        }
+                        (None, Strategy::Full) => {
+                            current.enter_lambda();
+                            current.step = Step::RightTree(0);
+                        }
+                        (None, Strategy::WeakHead) => current.step = Step::Done,
+                        // 引数は辿らず、ラムダ抽象の本体だけを辿る
+                        (None, Strategy::Head) => {
+                            current.enter_lambda();
+                            current.step = match current.binder {
+                                Some(_) => Step::RightTree(0),
+                                None => Step::Done,
+                            };
+                        }
+                    },
+                },
+
+                // スタックの n 番目の枝を取り出し、その枝の簡約を試みる
+                // n がスタックの長さを超えているなら、もう簡約するべきものは何も無い
+                Step::RightTree(n) => match current.stack.take(n) {
+                    Some(child) => path.push((n, child)),
+                    None => current.step = Step::Done,
+                },
+
+                // n 番目の枝が簡約済みなら、親に戻して n+1 番目の枝へ進む
+                Step::Done => match path.pop() {
+                    Some((n, child)) => {
+                        let parent = match path.last_mut() {
+                            Some((_, steps)) => steps,
+                            None => &mut *self,
+                        };
+                        parent.stack.put(n, child);
+                        parent.step = match parent.strict_native() {
+                            // 組み込みの関数の引数をすべて簡約し終えたら適用を試みる
+                            Some(native) if n + 1 == native.arity() => Step::LeftTree,
+                            _ => Step::RightTree(n + 1),
+                        };
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        // 取り出した部分木を元に戻す
+        while let Some((n, child)) = path.pop() {
+            let parent = match path.last_mut() {
+                Some((_, steps)) => steps,
+                None => &mut *self,
+            };
+            parent.stack.put(n, child);
+        }
+
+        if exceeded.is_some() {
+            self.exceeded = exceeded;
+            return None;
         }
+        redex.map(|(redex, position)| {
+            self.redex = Some(redex);
+            self.position = Some(position);
+            self.expr()
+        })
     }
 }
 
-impl EvalSteps<'_> {
-    fn left_tree(&mut self) -> Option<Expr> {
-        while let Apply { lhs, rhs } = self.expr.clone() {
-            self.expr = *lhs;
-            self.stack.push(EvalSteps::new(*rhs, self.context));
+impl Drop for EvalSteps<'_> {
+    fn drop(&mut self) {
+        // 入れ子になった EvalSteps を再帰的に drop しないよう、平らに並べてから捨てる
+        let mut pending = std::mem::take(&mut self.stack.0);
+        while let Some(mut steps) = pending.pop() {
+            pending.append(&mut steps.stack.0);
         }
+    }
+}
 
-        let maybe_next = self
-            .expr
-            .arity(&self.context)
-            .filter(|a| *a >= 1 || self.stack.len() >= 1)
-            .and_then(|a| self.stack.pop(a))
-            .and_then(|args| {
-                self.expr
-                    .apply(&self.context, args.iter().map(|arg| arg.expr()).collect())
-            })
-            .map(|expr| {
-                self.expr = expr;
-                self.expr()
-            });
+impl<'a> EvalSteps<'a> {
+    /// 同じ Context と簡約戦略で部分木を簡約する
+    fn child(&self, expr: Expr) -> EvalSteps<'a> {
+        EvalSteps::with_strategy(expr, self.context, self.strategy)
+    }
 
-        match maybe_next {
-            Some(expr) => Some(expr),
+    /// 引数を待っていないラムダ抽象なら、本体を簡約できるようスタックに積む
+    fn enter_lambda(&mut self) {
+        if !self.stack.is_empty() || self.binder.is_some() {
+            return;
+        }
 
-            None => {
-                self.step = Step::RightTree(0);
-                self.next()
+        let expr = self.expr.take();
+        match expr.open_lambda(self.context) {
+            Ok((param, body)) => {
+                let body = self.child(body);
+                self.stack.push(body);
+                self.binder = Some(param);
             }
+            Err(expr) => self.expr = expr,
+        }
+    }
+
+    /// 頭部が組み込みの関数かパターンで定義した関数なら、それを返す
+    fn native(&self) -> Option<Native<'a>> {
+        let Variable(id) = &self.expr else {
+            return None;
+        };
+        if let Some(func) = self.context.get(id).filter(|f| f.has_patterns()) {
+            return Some(Native::Rules(func));
+        }
+        if let Some(builtin) = self.context.builtin(id) {
+            return Some(Native::Builtin(builtin));
         }
+        Primitive::lookup(&self.expr, self.context).map(Native::Primitive)
     }
 
-    fn right_tree(&mut self, n: usize) -> Option<Expr> {
-        match self.stack.nth(n) {
-            // スタックの n 番目の枝を取得し、その枝の簡約を試みる
-            Some(step) => match step.next() {
-                Some(_) => Some(self.expr()),
+    /// 引数の揃った、引数を簡約してから適用する組み込みの関数の適用なら、その関数を返す
+    fn strict_native(&self) -> Option<Native<'a>> {
+        if self.binder.is_some() {
+            return None;
+        }
+        self.native()
+            .filter(|n| n.is_strict() && n.arity() <= self.stack.len())
+    }
 
-                // n 番目の枝が簡約済みなら、n+1 番目の枝へ進む
-                None => {
-                    self.step = Step::RightTree(n + 1);
-                    self.next()
-                }
-            },
+    /// 先頭から n 個の引数をすべて簡約し終えていれば真
+    fn args_done(&self, n: usize) -> bool {
+        let len = self.stack.len();
+        self.stack.0[len - n..]
+            .iter()
+            .all(|arg| arg.step == Step::Done)
+    }
+
+    /// 組み込みの関数を引数に適用する (δ 簡約)、引数が足りないか適用できなければ None を返す
+    ///
+    /// 結果が上限を超えるなら、その上限を exceeded に残して None を返す
+    ///
+    /// 簡約した redex の頭部と、消費した引数の個数を返す
+    fn delta(&mut self, native: Native) -> Option<(Redex, usize)> {
+        let arity = native.arity();
+        let len = self.stack.len();
+        if len < arity || (native.is_strict() && !self.args_done(arity)) {
+            return None;
+        }
 
-            // n がスタックの長さを超えているなら、もう簡約するべきものは何も無い
-            None => {
-                self.step = Step::Done;
-                self.next()
+        let args = self.stack.0[len - arity..]
+            .iter()
+            .rev()
+            .map(|arg| arg.expr())
+            .collect::<Vec<_>>();
+        let expr = match native.apply(&args) {
+            Ok(expr) => expr?,
+            Err(e) => {
+                self.exceeded = Some(e);
+                return None;
             }
+        };
+        self.stack.pop(arity);
+
+        let redex = Redex::Func(native.name());
+        self.expr = expr;
+        self.redex = Some(redex.clone());
+        Some((redex, arity))
+    }
+
+    /// 頭部の redex を 1 ステップ簡約する、簡約できなければ None を返す
+    ///
+    /// 簡約した redex の頭部と、消費した引数の個数を返す
+    fn left_tree(&mut self) -> Option<(Redex, usize)> {
+        while let Apply { .. } = self.expr {
+            let Node::Apply(lhs, rhs) = self.expr.take().into_node() else {
+                unreachable!()
+            };
+            self.expr = lhs;
+            let rhs = self.child(rhs);
+            self.stack.push(rhs);
+        }
+
+        if let Some(native) = self.native() {
+            return self.delta(native);
+        }
+
+        let args = self
+            .expr
+            .arity(self.context)
+            .filter(|a| *a >= 1 || !self.stack.is_empty())
+            .and_then(|a| self.stack.pop(a))?;
+
+        let arity = args.len();
+        let expr = self
+            .expr
+            .apply(self.context, args.iter().map(|arg| arg.expr()).collect())?;
+
+        let redex = match &self.expr {
+            Variable(id) => Redex::Func(id.clone()),
+            Lambda { param, .. } => Redex::Lambda(param.clone()),
+            _ => unreachable!(),
+        };
+        self.expr = expr;
+        self.redex = Some(redex.clone());
+        Some((redex, arity))
+    }
+}
+
+// ========================================================================== //
+
+/// Context に登録された組み込みの関数と整数のプリミティブ
+///
+/// パターンで定義した関数も、引数を簡約してから節を選ぶので同じように扱う
+#[derive(Debug, Clone, Copy)]
+enum Native<'a> {
+    Builtin(&'a Builtin),
+    Primitive(Primitive),
+    Rules(&'a Func),
+}
+
+impl Native<'_> {
+    fn name(&self) -> Ident {
+        match self {
+            Native::Builtin(b) => b.name().clone(),
+            Native::Primitive(p) => Ident::new(p.name()),
+            Native::Rules(f) => f.name().clone(),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Native::Builtin(b) => b.arity(),
+            Native::Primitive(p) => p.arity(),
+            Native::Rules(f) => f.arity(),
+        }
+    }
+
+    fn is_strict(&self) -> bool {
+        match self {
+            Native::Builtin(b) => b.is_strict(),
+            Native::Primitive(p) => p.is_strict(),
+            Native::Rules(_) => true,
+        }
+    }
+
+    fn apply(&self, args: &[Expr]) -> Result<Option<Expr>, LimitExceeded> {
+        match self {
+            Native::Builtin(b) => Ok(b.apply(args)),
+            Native::Primitive(p) => p.apply(args),
+            Native::Rules(f) => Ok(f.apply(args.to_vec())),
         }
     }
 }
@@ -139,7 +538,7 @@ impl<'a> Stack<'a> {
         self.0.push(expr);
     }
 
-    fn pop(&mut self, n: usize) -> Option<Vec<EvalSteps>> {
+    fn pop(&mut self, n: usize) -> Option<Vec<EvalSteps<'_>>> {
         let length = self.len();
 
         if length >= n {
@@ -149,16 +548,14 @@ impl<'a> Stack<'a> {
         }
     }
 
-    fn all(&self) -> Vec<EvalSteps> {
-        let mut all = self.0.clone();
-        all.reverse();
-        all
-    }
-
     fn len(&self) -> usize {
         self.0.len()
     }
 
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// 末尾から数えて n 番目の要素を取得する
     fn nth(&mut self, n: usize) -> Option<&mut EvalSteps<'a>> {
         let len = self.0.len();
@@ -168,6 +565,21 @@ impl<'a> Stack<'a> {
             self.0.get_mut(len - n - 1)
         }
     }
+
+    /// 末尾から数えて n 番目の要素を取り出す、代わりに空の EvalSteps を置いておく
+    fn take(&mut self, n: usize) -> Option<EvalSteps<'a>> {
+        self.nth(n).map(|steps| {
+            let placeholder = EvalSteps::new(Expr::Symbol(Ident::empty()), steps.context);
+            std::mem::replace(steps, placeholder)
+        })
+    }
+
+    /// take で取り出した要素を元の位置に戻す
+    fn put(&mut self, n: usize, steps: EvalSteps<'a>) {
+        if let Some(slot) = self.nth(n) {
+            *slot = steps;
+        }
+    }
 }
 
 // ========================================================================== //
@@ -195,6 +607,21 @@ mod tests {
         Context::from(vec![i, k, s, _true, _false])
     }
 
+    #[test]
+    fn test_next_step() {
+        let context = setup();
+        let mut steps = EvalSteps::new(Expr::a("i".into(), ":a".into()), &context);
+
+        assert_eq!(
+            steps.next_step(),
+            Some(EvalStep {
+                expr: ":a".into(),
+                redex: Some(Redex::Func("i".into())),
+            })
+        );
+        assert_eq!(steps.next_step(), None);
+    }
+
     #[test]
     fn test_eval_steps_lambda_i() {
         let context = Context::new();
@@ -221,6 +648,265 @@ mod tests {
         assert_eq!(steps.next(), None);
     }
 
+    #[test]
+    fn test_eval_steps_full() {
+        let context = setup();
+
+        // ^x.`^y.y x
+        let expr = Expr::l(
+            "x".into(),
+            Expr::a(Expr::l("y".into(), "y".into()), "x".into()),
+        );
+        let mut steps = EvalSteps::new(expr.clone(), &context);
+        assert_eq!(steps.next(), None);
+
+        let mut steps = EvalSteps::with_strategy(expr, &context, Strategy::Full);
+        assert_eq!(steps.next(), Some(Expr::l("x".into(), "x".into())));
+        assert_eq!(steps.next(), None);
+
+        // 引数の中のラムダ抽象の本体も簡約する: `:a ^x.`ix
+        let expr = Expr::a(
+            ":a".into(),
+            Expr::l("x".into(), Expr::a("i".into(), "x".into())),
+        );
+        let mut steps = EvalSteps::with_strategy(expr, &context, Strategy::Full);
+        assert_eq!(
+            steps.next(),
+            Some(Expr::a(":a".into(), Expr::l("x".into(), "x".into())))
+        );
+        assert_eq!(steps.next(), None);
+    }
+
+    #[test]
+    fn test_eval_steps_head() {
+        let context = setup();
+
+        // ``:a `ik ^x.`ix は頭部が :a なので、弱頭部正規形でも頭部正規形でも簡約しない
+        let expr = Expr::a(
+            Expr::a(":a".into(), Expr::a("i".into(), "k".into())),
+            Expr::l("x".into(), Expr::a("i".into(), "x".into())),
+        );
+        let mut steps = EvalSteps::with_strategy(expr.clone(), &context, Strategy::WeakHead);
+        assert_eq!(steps.next(), None);
+        let mut steps = EvalSteps::with_strategy(expr, &context, Strategy::Head);
+        assert_eq!(steps.next(), None);
+
+        // ^x.``ix`ik は弱頭部正規形だが、頭部正規形では本体の頭部を簡約する
+        let expr = Expr::l(
+            "x".into(),
+            Expr::a(
+                Expr::a("i".into(), "x".into()),
+                Expr::a("i".into(), "k".into()),
+            ),
+        );
+        let mut steps = EvalSteps::with_strategy(expr.clone(), &context, Strategy::WeakHead);
+        assert_eq!(steps.next(), None);
+        let mut steps = EvalSteps::with_strategy(expr, &context, Strategy::Head);
+        assert_eq!(
+            steps.next(),
+            Some(Expr::l(
+                "x".into(),
+                Expr::a("x".into(), Expr::a("i".into(), "k".into()))
+            ))
+        );
+        assert_eq!(steps.next(), None);
+    }
+
+    #[test]
+    fn test_eval_steps_patterns() {
+        let mut context = setup();
+        context.update(Func::with_patterns(
+            "f".into(),
+            vec![":ZERO".into()],
+            ":Z".into(),
+        ));
+        context.update(Func::with_patterns(
+            "f".into(),
+            vec![Expr::a(":SUCC".into(), "n".into())],
+            "n".into(),
+        ));
+        context.update(Func::with_patterns(
+            "g".into(),
+            vec![":A".into()],
+            ":a".into(),
+        ));
+        context.update(Func::new("g".into(), vec!["x".into()], ":k".into()));
+
+        // 引数を簡約してから節を選ぶ: `f`i`:SUCC:ZERO
+        let expr = Expr::a(
+            "f".into(),
+            Expr::a("i".into(), Expr::a(":SUCC".into(), ":ZERO".into())),
+        );
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(
+            steps.next(),
+            Some(Expr::a("f".into(), Expr::a(":SUCC".into(), ":ZERO".into())))
+        );
+        assert_eq!(steps.next(), Some(":ZERO".into()));
+        assert_eq!(steps.next(), None);
+        assert_eq!(steps.stuck(), None);
+
+        // どの節にも一致しなければ簡約できない
+        let mut steps = EvalSteps::new(Expr::a("f".into(), ":ONE".into()), &context);
+        assert_eq!(steps.next(), None);
+        assert_eq!(steps.stuck(), Some("f".into()));
+
+        // 一致しなかった適用を捨てて正規形に達したなら行き詰まりではない: `g`f:ONE
+        let expr = Expr::a("g".into(), Expr::a("f".into(), ":ONE".into()));
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.next(), Some(":k".into()));
+        assert_eq!(steps.next(), None);
+        assert_eq!(steps.stuck(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_eval_steps_primitive() {
+        let context = setup();
+
+        // ``INT_ADD ``INT_MUL :2 :3 `i:4
+        let expr = Expr::a(
+            Expr::a(
+                "INT_ADD".into(),
+                Expr::a(Expr::a("INT_MUL".into(), ":2".into()), ":3".into()),
+            ),
+            Expr::a("i".into(), ":4".into()),
+        );
+        let steps = EvalSteps::new(expr.clone(), &context).collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            vec![
+                Expr::a(
+                    Expr::a("INT_ADD".into(), ":6".into()),
+                    Expr::a("i".into(), ":4".into())
+                ),
+                Expr::a(Expr::a("INT_ADD".into(), ":6".into()), ":4".into()),
+                Expr::s("10"),
+            ]
+        );
+
+        // 弱頭部正規形でも引数をリテラルまで簡約する
+        let steps = EvalSteps::with_strategy(expr, &context, Strategy::WeakHead);
+        assert_eq!(steps.last(), Some(Expr::s("10")));
+
+        // 引数がリテラルにならなければ適用しない
+        let expr = Expr::a(
+            Expr::a("INT_ADD".into(), ":a".into()),
+            Expr::a("i".into(), ":4".into()),
+        );
+        let steps = EvalSteps::new(expr, &context).collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            vec![Expr::a(Expr::a("INT_ADD".into(), ":a".into()), ":4".into())]
+        );
+    }
+
+    #[test]
+    fn test_eval_steps_builtin() {
+        let mut context = setup();
+        // 引数が :a なら :YES、そうでなければ :NO
+        context.register(
+            Builtin::new("IS_A".into(), 1, |args| match &args[0] {
+                Symbol(id) => Some(Expr::s(if id.label() == "a" { "YES" } else { "NO" })),
+                _ => None,
+            })
+            .strict(),
+        );
+        // 引数を簡約せずに返す
+        context.register(Builtin::new("LAZY".into(), 1, |args| Some(args[0].clone())));
+
+        let expr = Expr::a("IS_A".into(), Expr::a("i".into(), ":a".into()));
+        let steps = EvalSteps::new(expr, &context).collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            vec![Expr::a("IS_A".into(), ":a".into()), Expr::s("YES")]
+        );
+
+        let expr = Expr::a("LAZY".into(), Expr::a("i".into(), ":a".into()));
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.next(), Some(Expr::a("i".into(), ":a".into())));
+        assert_eq!(steps.redex(), Some(&Redex::Func("LAZY".into())));
+
+        // 適用できなければ引数を簡約したところで止まる
+        let expr = Expr::a("IS_A".into(), Expr::a("i".into(), "x".into()));
+        let steps = EvalSteps::new(expr, &context).collect::<Vec<_>>();
+        assert_eq!(steps, vec![Expr::a("IS_A".into(), "x".into())]);
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_eval_steps_church_conversion() {
+        let context = Context::default();
+
+        // `FROM_CHURCH ``ADD 2 3
+        let expr = Expr::a(
+            "FROM_CHURCH".into(),
+            Expr::a(Expr::a("ADD".into(), "2".into()), "3".into()),
+        );
+        let steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.last(), Some(Expr::s("5")));
+
+        let expr = Expr::a("TO_CHURCH".into(), ":2".into());
+        let steps = EvalSteps::new(expr, &context);
+        assert_eq!(
+            steps.last(),
+            Some(Expr::l(
+                "f".into(),
+                Expr::l(
+                    "x".into(),
+                    Expr::a("f".into(), Expr::a("f".into(), "x".into()))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_eval_steps_church_size_limit() {
+        let context = Context::default();
+
+        // 上限を超える Church 数は作らず、上限を超えたことを残して止まる
+        let n = Expr::s(&(crate::config::size_limit() + 1).to_string());
+        let mut steps = EvalSteps::new(Expr::a("TO_CHURCH".into(), n.clone()), &context);
+        assert_eq!(steps.next(), None);
+        assert!(matches!(steps.exceeded(), Some(LimitExceeded::Size { .. })));
+
+        // 引数の簡約の途中で超えても同じ
+        let expr = Expr::a("TO_CHURCH".into(), Expr::a("i".into(), n));
+        let mut steps = EvalSteps::new(expr, &context);
+        assert!(steps.next().is_some());
+        assert_eq!(steps.next(), None);
+        assert!(matches!(
+            steps.eval_last(10, Limits::from_config()),
+            Err(LimitExceeded::Size { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "integers"))]
+    fn test_eval_steps_integers_disabled() {
+        let context = Context::default();
+
+        // integers feature が無効なら INT_ADD はただの変数
+        let expr = Expr::a(Expr::a("INT_ADD".into(), ":2".into()), ":3".into());
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.next(), None);
+        assert_eq!(steps.exceeded(), None);
+    }
+
+    #[test]
+    fn test_eval_steps_full_shadowing() {
+        let context = setup();
+
+        // ^k.``TRUE :a k の TRUE は k に束縛されない k を参照する
+        let expr = Expr::l(
+            "k".into(),
+            Expr::a(Expr::a("TRUE".into(), ":a".into()), "k".into()),
+        );
+        let steps = EvalSteps::with_strategy(expr, &context, Strategy::Full);
+        assert_eq!(steps.last(), Some(Expr::l("K".into(), "K".into())));
+    }
+
     #[test]
     fn test_eval_steps_lambda_k_2() {
         let context = Context::new();
@@ -470,6 +1156,35 @@ mod tests {
         assert_eq!(steps.next(), None);
     }
 
+    #[test]
+    fn test_redex() {
+        let context = setup();
+
+        // ``k`i:a`^x.x:b
+        let expr = Expr::a(
+            Expr::a("k".into(), Expr::a("i".into(), ":a".into())),
+            Expr::a(Expr::l("x".into(), "x".into()), ":b".into()),
+        );
+
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.redex(), None);
+
+        assert_eq!(steps.next(), Some(Expr::a("i".into(), ":a".into())));
+        assert_eq!(steps.redex(), Some(&Redex::Func("k".into())));
+
+        assert_eq!(steps.next(), Some(":a".into()));
+        assert_eq!(steps.redex(), Some(&Redex::Func("i".into())));
+
+        let expr = Expr::a(
+            ":f".into(),
+            Expr::a(Expr::l("x".into(), "x".into()), ":b".into()),
+        );
+
+        let mut steps = EvalSteps::new(expr, &context);
+        assert_eq!(steps.next(), Some(Expr::a(":f".into(), ":b".into())));
+        assert_eq!(steps.redex(), Some(&Redex::Lambda("x".into())));
+    }
+
     #[test]
     fn test_stack_pop() {
         let context = Context::new();
@@ -504,27 +1219,6 @@ mod tests {
         assert_eq!(stack.pop(1), None);
     }
 
-    #[test]
-    fn test_stack_all() {
-        let context = Context::new();
-        let stack = Stack(vec![
-            EvalSteps::new(Expr::v("x"), &context),
-            EvalSteps::new(Expr::v("y"), &context),
-            EvalSteps::new(Expr::v("z"), &context),
-        ]);
-        assert_eq!(
-            stack.all(),
-            vec![
-                EvalSteps::new(Expr::v("z"), &context),
-                EvalSteps::new(Expr::v("y"), &context),
-                EvalSteps::new(Expr::v("x"), &context),
-            ]
-        );
-
-        let stack0 = Stack(vec![]);
-        assert_eq!(stack0.all(), vec![]);
-    }
-
     #[test]
     fn test_stack_nth() {
         let context = Context::new();
@@ -549,6 +1243,14 @@ mod tests {
         assert_eq!(stack.nth(3), None);
     }
 
+    fn no_limits() -> Limits {
+        Limits {
+            size: usize::MAX,
+            depth: usize::MAX,
+            time: None,
+        }
+    }
+
     #[test]
     fn test_eval_last_1() {
         let context = setup();
@@ -556,7 +1258,7 @@ mod tests {
         let expr = ":a".into();
         let mut steps = EvalSteps::new(expr, &context);
 
-        assert_eq!(steps.eval_last(42), (None, false));
+        assert_eq!(steps.eval_last(42, no_limits()), Ok((None, false)));
     }
 
     #[test]
@@ -572,7 +1274,10 @@ mod tests {
         );
         let mut steps = EvalSteps::new(expr, &context);
 
-        assert_eq!(steps.eval_last(42), (Some(":a".into()), false));
+        assert_eq!(
+            steps.eval_last(42, no_limits()),
+            Ok((Some(":a".into()), false))
+        );
     }
 
     #[test]
@@ -589,8 +1294,96 @@ mod tests {
         let mut steps = EvalSteps::new(expr, &context);
 
         assert_eq!(
-            steps.eval_last(3),
-            (Some(Expr::a("i".into(), ":a".into())), true)
+            steps.eval_last(3, no_limits()),
+            Ok((Some(Expr::a("i".into(), ":a".into())), true))
         );
     }
+
+    #[test]
+    fn test_eval_last_limits() {
+        let context = setup();
+
+        // ``s``sii```sii``sii は簡約するほど式が大きくなるので、大きさの上限で止まる
+        let omega = Expr::a(Expr::a("s".into(), "i".into()), "i".into());
+        let expr = Expr::a(
+            Expr::a("s".into(), omega.clone()),
+            Expr::a(omega.clone(), omega),
+        );
+        let mut steps = EvalSteps::new(expr, &context);
+        let limits = Limits {
+            size: 20,
+            ..no_limits()
+        };
+
+        assert!(matches!(
+            steps.eval_last(1000, limits),
+            Err(LimitExceeded::Size { limit: 20, .. })
+        ));
+    }
+
+    #[test]
+    fn test_redex_position() {
+        let context = setup();
+
+        // ``:f`i:a``k:b:c
+        let expr = Expr::a(
+            Expr::a(":f".into(), Expr::a("i".into(), ":a".into())),
+            Expr::a(Expr::a("k".into(), ":b".into()), ":c".into()),
+        );
+        let mut steps = EvalSteps::new(expr.clone(), &context);
+
+        let next = steps.next().unwrap();
+        let position = steps.redex_position().unwrap().clone();
+        assert_eq!(
+            position,
+            RedexPosition {
+                path: vec![0],
+                arity: 1
+            }
+        );
+        assert_eq!(
+            position.locate(&expr),
+            Some(&Expr::a("i".into(), ":a".into()))
+        );
+
+        steps.next().unwrap();
+        let position = steps.redex_position().unwrap();
+        assert_eq!(position.path, vec![1]);
+        assert_eq!(
+            position.frames(&next),
+            Some(vec![
+                &next,
+                &Expr::a(Expr::a("k".into(), ":b".into()), ":c".into())
+            ])
+        );
+        assert_eq!(
+            position.locate(&next),
+            Some(&Expr::a(Expr::a("k".into(), ":b".into()), ":c".into()))
+        );
+
+        // 余った引数は redex に含めない
+        let redex = Expr::a(Expr::a("k".into(), ":a".into()), ":b".into());
+        let expr = Expr::a(redex.clone(), ":c".into());
+        let mut steps = EvalSteps::new(expr.clone(), &context);
+        steps.next().unwrap();
+        assert_eq!(steps.redex_position().unwrap().locate(&expr), Some(&redex));
+    }
+
+    #[test]
+    fn test_eval_steps_deep() {
+        let context = setup();
+        let n = 1_000_000;
+
+        // `:f`:f`:f...`:f`i:a は最も内側の `i:a だけが簡約できる
+        let mut expr = Expr::a("i".into(), ":a".into());
+        for _ in 0..n {
+            expr = Expr::a(":f".into(), expr);
+        }
+        let mut steps = EvalSteps::new(expr, &context);
+
+        let e = steps.next().unwrap();
+        assert_eq!(e.depth(), n + 1);
+        assert_eq!(steps.redex(), Some(&Redex::Func("i".into())));
+        assert_eq!(steps.next(), None);
+    }
 }
@@ -1,12 +1,20 @@
+mod alpha;
 mod apply;
 pub mod display;
 mod free_vars;
+mod measure;
 mod substitute;
 mod unlambda;
 
+pub use free_vars::free_vars;
+pub use substitute::substitution_counts;
+
 use crate::identifier::Ident;
 
-#[derive(Clone, Debug, PartialEq)]
+/// ラムダ式
+///
+/// 非常に深い式でもネイティブのスタックを溢れさせないよう、
+/// `Clone`, `PartialEq`, `Debug`, `Drop` は再帰を使わずに実装している
 pub enum Expr {
     /// 変数
     Variable(Ident),
@@ -23,6 +31,7 @@ pub enum Expr {
 
 impl Expr {
     /// 変数を作る
+    #[allow(dead_code)]
     pub fn v(label: &str) -> Expr {
         Expr::Variable(Ident::new(label))
     }
@@ -50,6 +59,157 @@ impl Expr {
     }
 }
 
+/// 部分式を値として取り出した式
+///
+/// `Expr` は `Drop` を実装しているため、パターンマッチで部分式を move できない
+/// 値として分解したい場合は `Expr::into_node` を使う
+pub enum Node {
+    Variable(Ident),
+    Symbol(Ident),
+    Apply(Expr, Expr),
+    Lambda(Ident, Expr),
+}
+
+impl Expr {
+    /// 式を分解して部分式を取り出す
+    pub fn into_node(mut self) -> Node {
+        match &mut self {
+            Expr::Variable(id) => Node::Variable(std::mem::replace(id, Ident::empty())),
+            Expr::Symbol(id) => Node::Symbol(std::mem::replace(id, Ident::empty())),
+            Expr::Apply { lhs, rhs } => Node::Apply(lhs.take(), rhs.take()),
+            Expr::Lambda { param, body } => {
+                Node::Lambda(std::mem::replace(param, Ident::empty()), body.take())
+            }
+        }
+    }
+
+    /// 式を取り出し、代わりに空のシンボルを置いておく
+    pub(crate) fn take(&mut self) -> Expr {
+        std::mem::replace(self, Expr::Symbol(Ident::empty()))
+    }
+
+    /// 変数やシンボルでない部分式を取り出して pending に積む
+    fn take_children(&mut self, pending: &mut Vec<Expr>) {
+        match self {
+            Expr::Apply { lhs, rhs } => {
+                if !lhs.is_atom() {
+                    pending.push(lhs.take());
+                }
+                if !rhs.is_atom() {
+                    pending.push(rhs.take());
+                }
+            }
+            Expr::Lambda { body, .. } => {
+                if !body.is_atom() {
+                    pending.push(body.take());
+                }
+            }
+            Expr::Variable(_) | Expr::Symbol(_) => {}
+        }
+    }
+
+    fn is_atom(&self) -> bool {
+        matches!(self, Expr::Variable(_) | Expr::Symbol(_))
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        // 部分式を取り出してから捨てることで、再帰的に drop が呼ばれないようにする
+        // 取り出された式は部分式を全て取り出された後に捨てられるので、その drop は何もしない
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.take_children(&mut pending);
+        }
+    }
+}
+
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        enum Frame<'a> {
+            Visit(&'a Expr),
+            Apply,
+            Lambda(&'a Ident),
+        }
+
+        let mut frames = vec![Frame::Visit(self)];
+        let mut done: Vec<Expr> = Vec::new();
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(Expr::Variable(id)) => done.push(Expr::Variable(id.clone())),
+                Frame::Visit(Expr::Symbol(id)) => done.push(Expr::Symbol(id.clone())),
+                Frame::Visit(Expr::Apply { lhs, rhs }) => {
+                    frames.push(Frame::Apply);
+                    frames.push(Frame::Visit(rhs));
+                    frames.push(Frame::Visit(lhs));
+                }
+                Frame::Visit(Expr::Lambda { param, body }) => {
+                    frames.push(Frame::Lambda(param));
+                    frames.push(Frame::Visit(body));
+                }
+
+                Frame::Apply => {
+                    let rhs = done.pop().unwrap();
+                    let lhs = done.pop().unwrap();
+                    done.push(Expr::a(lhs, rhs));
+                }
+                Frame::Lambda(param) => {
+                    let body = done.pop().unwrap();
+                    done.push(Expr::l(param.clone(), body));
+                }
+            }
+        }
+
+        done.pop().unwrap()
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = vec![(self, other)];
+
+        while let Some(pair) = pending.pop() {
+            match pair {
+                (Expr::Variable(l), Expr::Variable(r)) | (Expr::Symbol(l), Expr::Symbol(r)) => {
+                    if l != r {
+                        return false;
+                    }
+                }
+                (Expr::Apply { lhs: ll, rhs: lr }, Expr::Apply { lhs: rl, rhs: rr }) => {
+                    pending.push((lr, rr));
+                    pending.push((ll, rl));
+                }
+                (
+                    Expr::Lambda {
+                        param: lp,
+                        body: lb,
+                    },
+                    Expr::Lambda {
+                        param: rp,
+                        body: rb,
+                    },
+                ) => {
+                    if lp != rp {
+                        return false;
+                    }
+                    pending.push((lb, rb));
+                }
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl std::fmt::Debug for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expr({})", self)
+    }
+}
+
 impl From<&str> for Expr {
     fn from(s: &str) -> Self {
         match s.chars().nth(0) {
@@ -59,3 +219,42 @@ impl From<&str> for Expr {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `^x.``...```:a:a:a...:a` のように n 段入れ子になった式
+    fn deep(n: usize) -> Expr {
+        let mut expr: Expr = ":a".into();
+        for _ in 0..n {
+            expr = Expr::a(expr, ":a".into());
+        }
+        for _ in 0..n {
+            expr = Expr::a(":a".into(), expr);
+        }
+        Expr::l("x".into(), expr)
+    }
+
+    #[test]
+    fn test_deep_expr() {
+        let expr = deep(1_000_000);
+        let cloned = expr.clone();
+
+        assert_eq!(expr, cloned);
+        assert_ne!(expr, deep(999_999));
+        assert_eq!(expr.size(), 4_000_002);
+        assert_eq!(expr.depth(), 2_000_002);
+    }
+
+    #[test]
+    fn test_into_node() {
+        match Expr::a("x".into(), ":a".into()).into_node() {
+            Node::Apply(lhs, rhs) => {
+                assert_eq!(lhs, "x".into());
+                assert_eq!(rhs, ":a".into());
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::expression::Expr;
+use crate::identifier::Ident;
+
+impl Expr {
+    /// 束縛変数の名前の違いを無視して比較する (α 同値)
+    ///
+    /// 自由変数は名前が一致するときに限り等しい
+    pub fn alpha_eq(&self, other: &Expr) -> bool {
+        enum Frame<'a> {
+            Compare(&'a Expr, &'a Expr),
+            Unbind,
+        }
+
+        // 外側から順に並べた、対応する束縛変数の組
+        let mut binders: Vec<(&Ident, &Ident)> = Vec::new();
+        let mut frames = vec![Frame::Compare(self, other)];
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Compare(Expr::Variable(l), Expr::Variable(r)) => {
+                    let l_index = binders.iter().rposition(|(b, _)| *b == l);
+                    let r_index = binders.iter().rposition(|(_, b)| *b == r);
+                    match (l_index, r_index) {
+                        (Some(li), Some(ri)) if li == ri => (),
+                        (None, None) if l == r => (),
+                        _ => return false,
+                    }
+                }
+                Frame::Compare(Expr::Symbol(l), Expr::Symbol(r)) => {
+                    if l != r {
+                        return false;
+                    }
+                }
+                Frame::Compare(
+                    Expr::Apply { lhs: ll, rhs: lr },
+                    Expr::Apply { lhs: rl, rhs: rr },
+                ) => {
+                    frames.push(Frame::Compare(lr, rr));
+                    frames.push(Frame::Compare(ll, rl));
+                }
+                Frame::Compare(
+                    Expr::Lambda {
+                        param: lp,
+                        body: lb,
+                    },
+                    Expr::Lambda {
+                        param: rp,
+                        body: rb,
+                    },
+                ) => {
+                    binders.push((lp, rp));
+                    frames.push(Frame::Unbind);
+                    frames.push(Frame::Compare(lb, rb));
+                }
+                Frame::Unbind => {
+                    binders.pop();
+                }
+                Frame::Compare(_, _) => return false,
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpha_eq() {
+        // ^x.^y.x == ^a.^b.a
+        let l = Expr::l("x".into(), Expr::l("y".into(), "x".into()));
+        let r = Expr::l("a".into(), Expr::l("b".into(), "a".into()));
+        assert!(l.alpha_eq(&r));
+
+        // ^x.^y.x != ^x.^y.y
+        let r = Expr::l("x".into(), Expr::l("y".into(), "y".into()));
+        assert!(!l.alpha_eq(&r));
+
+        // ^x.^x.x == ^a.^b.b
+        let l = Expr::l("x".into(), Expr::l("x".into(), "x".into()));
+        let r = Expr::l("a".into(), Expr::l("b".into(), "b".into()));
+        assert!(l.alpha_eq(&r));
+    }
+
+    #[test]
+    fn test_alpha_eq_free_variables() {
+        // 自由変数は名前で比較する: ^x.`xy != ^y.`yy
+        let l = Expr::l("x".into(), Expr::a("x".into(), "y".into()));
+        let r = Expr::l("y".into(), Expr::a("y".into(), "y".into()));
+        assert!(!l.alpha_eq(&r));
+
+        assert!(Expr::a("f".into(), ":a".into()).alpha_eq(&Expr::a("f".into(), ":a".into())));
+        assert!(!Expr::s("a").alpha_eq(&Expr::v("a")));
+    }
+}
@@ -1,5 +1,9 @@
-use super::Expr;
+use std::collections::HashSet;
+
+use super::free_vars::free_vars;
+use super::{Expr, Node};
 use crate::context::Context;
+use crate::identifier::Ident;
 
 impl Expr {
     pub fn arity(&self, context: &Context) -> Option<usize> {
@@ -13,16 +17,62 @@ impl Expr {
     /// TODO: `Option<T>` ではなく `Result<T, E>` を返すのが適切かも
     pub fn apply(&self, context: &Context, args: Vec<Expr>) -> Option<Expr> {
         match self {
-            Expr::Lambda { param, body } => Some(body.clone().substitute(&param, &args[0])),
+            Expr::Lambda { param, body } => Some(body.clone().substitute(param, &args[0])),
 
-            Expr::Variable(id) => match context.get(&id) {
-                Some(func) => Some(func.apply(args)),
-                None => None,
-            },
+            Expr::Variable(id) => context.get(id).and_then(|func| func.apply(args)),
 
             _ => None,
         }
     }
+
+    /// ラムダ抽象を仮引数と本体に分ける、ラムダ抽象でなければ None を返す
+    ///
+    /// 本体を簡約するとき、定義済み関数の本体に現れる関数名が仮引数に束縛されないよう、
+    /// 仮引数が定義済みの関数と同じ名前なら衝突しない名前に付け替える
+    pub fn open_lambda(self, context: &Context) -> Result<(Ident, Expr), Expr> {
+        if !matches!(self, Expr::Lambda { .. }) {
+            return Err(self);
+        }
+        let Node::Lambda(param, body) = self.into_node() else {
+            unreachable!()
+        };
+
+        if context.get(&param).is_none() {
+            return Ok((param, body));
+        }
+
+        let mut vars = free_vars(&body)
+            .into_iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+        context.for_each(|name, _| {
+            vars.insert(name.clone());
+        });
+
+        let renamed = param.new_name(&vars);
+        let body = body.substitute(&param, &Expr::Variable(renamed.clone()));
+        Ok((renamed, body))
+    }
+
+    /// 自由変数として現れる 0 引数の関数を本体で置き換える、置き換えるものが無ければ None を返す
+    pub fn expand_constants(&self, context: &Context) -> Option<Expr> {
+        let mut names = free_vars(self)
+            .into_iter()
+            .filter(|id| context.arity(id) == Some(0))
+            .cloned()
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return None;
+        }
+        names.sort_by(|l, r| l.label().cmp(r.label()));
+
+        let mut expr = self.clone();
+        for name in names {
+            let body = context.get(&name)?.body();
+            expr = expr.substitute(&name, body);
+        }
+        Some(expr)
+    }
 }
 
 #[cfg(test)]
@@ -30,6 +80,43 @@ mod tests {
     use super::*;
     use crate::function::Func;
 
+    #[test]
+    fn test_open_lambda() {
+        let context = Context::from(vec![Func::new("k".into(), vec![], ":a".into())]);
+
+        assert_eq!(
+            Expr::l("x".into(), Expr::a("k".into(), "x".into())).open_lambda(&context),
+            Ok(("x".into(), Expr::a("k".into(), "x".into())))
+        );
+        assert_eq!(
+            Expr::l("k".into(), Expr::a("k".into(), "K".into())).open_lambda(&context),
+            Ok(("K0".into(), Expr::a("K0".into(), "K".into())))
+        );
+        assert_eq!(Expr::s("a").open_lambda(&context), Err(Expr::s("a")));
+    }
+
+    #[test]
+    fn test_expand_constants() {
+        let context = Context::from(vec![
+            Func::new("C".into(), vec![], ":c".into()),
+            Func::new("i".into(), vec!["x".into()], "x".into()),
+        ]);
+
+        // ^C.`C C の C は束縛されているので置き換えない、i は 1 引数なので置き換えない
+        let expr = Expr::a(
+            Expr::a("i".into(), "C".into()),
+            Expr::l("C".into(), "C".into()),
+        );
+        assert_eq!(
+            expr.expand_constants(&context),
+            Some(Expr::a(
+                Expr::a("i".into(), ":c".into()),
+                Expr::l("C".into(), "C".into())
+            ))
+        );
+        assert_eq!(Expr::v("i").expand_constants(&context), None);
+    }
+
     #[test]
     fn test_arity() {
         let f0 = Func::new("F0".into(), vec![], ":a".into());
@@ -1,8 +1,9 @@
 pub mod ecmascript;
+pub mod fold;
 pub mod lazy_k;
+pub mod pretty;
 
 use crate::expression::Expr;
-use regex::Regex;
 use std::fmt::Display;
 
 impl Display for Expr {
@@ -46,58 +47,60 @@ impl Display for Token<'_> {
         }
     }
 }
-
+/// 大文字・数字・`_` だけからなる識別子かどうか
+/// `^[0-9A-Z_]+$` にマッチする識別子かどうか
+///
+/// トークンごとに呼ばれるので正規表現はコンパイルせずに文字を直接調べる
 fn is_upper_ident(s: &str) -> bool {
-    let regex_upper_ident: Regex = Regex::new(r"^[A-Z_]+$").unwrap();
-    regex_upper_ident.is_match(s)
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || c.is_uppercase() || c == '_')
 }
 
+fn ident_token(ident: Ident) -> Token {
+    let (Ident::Variable(label) | Ident::Symbol(label)) = ident;
+    if is_upper_ident(label) {
+        Token::UpperIdent(ident)
+    } else {
+        Token::LowerIdent(ident)
+    }
+}
+
+/// 式をトークンの列に変換する、`to_string` が末尾から取り出すので逆順に並べて返す
+///
+/// 深い式でもスタックを溢れさせないよう、明示的なスタックを使って前順に辿る
 fn tokens<'a>(expr: &'a Expr) -> Vec<Token<'a>> {
-    match expr {
-        Expr::Variable(i) => {
-            let label = i.label();
-            if is_upper_ident(label) {
-                vec![Token::UpperIdent(Ident::Variable(label))]
-            } else {
-                vec![Token::LowerIdent(Ident::Variable(label))]
-            }
-        }
+    let mut tokens = Vec::new();
+    let mut pending = vec![expr];
 
-        Expr::Symbol(i) => {
-            let label = i.label();
-            if is_upper_ident(label) {
-                vec![Token::UpperIdent(Ident::Symbol(label))]
-            } else {
-                vec![Token::LowerIdent(Ident::Symbol(label))]
-            }
-        }
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Variable(i) => tokens.push(ident_token(Ident::Variable(i.label()))),
 
-        Expr::Apply { lhs, rhs } => {
-            let mut lhs = tokens(lhs);
-            let mut rhs = tokens(rhs);
-            rhs.append(&mut lhs);
-            rhs.push(Token::Apply);
-            rhs
-        }
+            Expr::Symbol(i) => tokens.push(ident_token(Ident::Symbol(i.label()))),
 
-        Expr::Lambda { param, body } => {
-            let mut body = tokens(body);
-            body.push(Token::Dot);
-            let label = param.label();
-            if is_upper_ident(label) {
-                body.push(Token::UpperIdent(Ident::Variable(label)));
-            } else {
-                body.push(Token::LowerIdent(Ident::Variable(label)));
+            Expr::Apply { lhs, rhs } => {
+                tokens.push(Token::Apply);
+                pending.push(rhs);
+                pending.push(lhs);
+            }
+
+            Expr::Lambda { param, body } => {
+                tokens.push(Token::Lambda);
+                tokens.push(ident_token(Ident::Variable(param.label())));
+                tokens.push(Token::Dot);
+                pending.push(body);
             }
-            body.push(Token::Lambda);
-            body
         }
     }
+
+    tokens.reverse();
+    tokens
 }
 
 fn to_string(tokens: &mut Vec<Token>) -> String {
     let mut str = String::new();
-    while tokens.len() > 0 {
+    while !tokens.is_empty() {
         match tokens.len() {
             1 => {
                 let t = tokens.pop().unwrap();
@@ -143,6 +146,23 @@ fn test_to_string() {
     assert_eq!(Expr::a("X".into(), ":Y".into()).to_string(), "`X:Y");
     assert_eq!(Expr::a(":X".into(), "Y".into()).to_string(), "`:X Y");
     assert_eq!(Expr::a(":X".into(), ":Y".into()).to_string(), "`:X:Y");
+
+    // 数字を含む識別子も大文字の識別子と同じく区切りのスペースが必要
+    assert_eq!(Expr::a("X".into(), "42".into()).to_string(), "`X 42");
+    assert_eq!(Expr::a("X0".into(), "Y".into()).to_string(), "`X0 Y");
+    assert_eq!(Expr::a("42".into(), "x".into()).to_string(), "`42x");
+}
+
+#[test]
+fn test_to_string_deep() {
+    let n = 1_000_000;
+
+    let mut expr: Expr = ":a".into();
+    for _ in 0..n {
+        expr = Expr::a(":f".into(), expr);
+    }
+
+    assert_eq!(expr.to_string(), format!("{}:a", "`:f".repeat(n)));
 }
 
 #[test]
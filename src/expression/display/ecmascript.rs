@@ -1,3 +1,4 @@
+use super::pretty::Doc;
 use crate::expression::Expr;
 use std::fmt::Display;
 
@@ -6,98 +7,173 @@ pub struct ECMAScriptStyle<'a>(pub &'a Expr);
 
 impl Display for ECMAScriptStyle<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", AnotherExpr::new(self.0).to_string())
-    }
-}
+        enum Frame<'a> {
+            Visit(&'a Expr),
+            Text(&'static str),
+        }
 
-enum AnotherExpr<'a> {
-    Variable(&'a str),
-    Symbol(&'a str),
-    Apply(Box<AnotherExpr<'a>>, Vec<AnotherExpr<'a>>),
-    Lambda(Vec<&'a str>, Box<AnotherExpr<'a>>),
-}
+        // 深い式でもスタックを溢れさせないよう、印字する順に明示的なスタックに積む
+        let mut frames = vec![Frame::Visit(self.0)];
+        while let Some(frame) = frames.pop() {
+            let e = match frame {
+                Frame::Text(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+                Frame::Visit(e) => e,
+            };
 
-impl<'a> AnotherExpr<'a> {
-    fn new(e: &'a Expr) -> AnotherExpr<'a> {
-        match e {
-            Expr::Variable(i) => {
-                let label = i.label();
-                AnotherExpr::Variable(label)
-            }
+            match e {
+                Expr::Variable(i) => f.write_str(i.label())?,
 
-            Expr::Symbol(i) => {
-                let label = i.label();
-                AnotherExpr::Symbol(label)
-            }
+                Expr::Symbol(i) => write!(f, ":{}", i.label())?,
+
+                Expr::Apply { .. } => {
+                    let (callee, args) = spine(e);
 
-            Expr::Apply { lhs, rhs } => {
-                let e1 = AnotherExpr::new(lhs);
-                let e2 = AnotherExpr::new(rhs);
-                match e1 {
-                    AnotherExpr::Apply(e1, mut es) => {
-                        es.push(e2);
-                        AnotherExpr::Apply(e1, es)
+                    frames.push(Frame::Text(")"));
+                    for (n, arg) in args.iter().rev().enumerate() {
+                        if n > 0 {
+                            frames.push(Frame::Text(", "));
+                        }
+                        frames.push(Frame::Visit(arg));
                     }
-                    _ => AnotherExpr::Apply(Box::new(e1), vec![e2]),
-                }
-            }
+                    frames.push(Frame::Text("("));
 
-            Expr::Lambda { param, body } => {
-                let param = param.label();
-                let body = AnotherExpr::new(body);
-                match body {
-                    AnotherExpr::Lambda(mut params, body) => {
-                        params.push(param);
-                        AnotherExpr::Lambda(params, body)
+                    if is_atom(callee) {
+                        frames.push(Frame::Visit(callee));
+                    } else {
+                        frames.push(Frame::Text(")"));
+                        frames.push(Frame::Visit(callee));
+                        frames.push(Frame::Text("("));
                     }
-                    _ => AnotherExpr::Lambda(vec![&param], Box::new(body)),
+                }
+
+                Expr::Lambda { .. } => {
+                    let (params, body) = params(e);
+                    write!(f, "{} ", params)?;
+                    frames.push(Frame::Visit(body));
                 }
             }
         }
+
+        Ok(())
     }
+}
 
-    fn to_string(&self) -> String {
-        match self {
-            AnotherExpr::Variable(label) => format!("{}", label),
+impl ECMAScriptStyle<'_> {
+    /// 行幅 width に収まるように改行・インデントを加えて印字する
+    pub fn pretty(&self, width: usize) -> String {
+        doc(self.0).render(width)
+    }
+}
 
-            AnotherExpr::Symbol(label) => format!(":{}", label),
+/// 式をプリティプリンタ用の `Doc` に変換する
+///
+/// 1 行に収まる場合は `Display` と同じ文字列になる
+pub fn doc(expr: &Expr) -> Doc {
+    enum Frame<'a> {
+        Visit(&'a Expr),
+        Apply(bool, usize),
+        Lambda(String),
+    }
 
-            AnotherExpr::Apply(e, args) => {
-                let args = args
-                    .iter()
-                    .map(|arg| arg.to_string())
-                    .collect::<Vec<_>>()
-                    .join(", ");
+    // 部分式の Doc を done に積み、親の Frame でまとめる
+    let mut frames = vec![Frame::Visit(expr)];
+    let mut done: Vec<Doc> = Vec::new();
 
-                match **e {
-                    AnotherExpr::Variable(label) | AnotherExpr::Symbol(label) => {
-                        format!("{}({})", label, args)
-                    }
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Visit(e @ (Expr::Variable(_) | Expr::Symbol(_))) => {
+                done.push(Doc::text(ECMAScriptStyle(e).to_string()))
+            }
 
-                    _ => {
-                        format!("({})({})", e.to_string(), args)
-                    }
+            Frame::Visit(e @ Expr::Apply { .. }) => {
+                let (callee, args) = spine(e);
+                frames.push(Frame::Apply(is_atom(callee), args.len()));
+                for arg in args.iter().rev() {
+                    frames.push(Frame::Visit(arg));
                 }
+                frames.push(Frame::Visit(callee));
+            }
+
+            Frame::Visit(e @ Expr::Lambda { .. }) => {
+                let (params, body) = params(e);
+                frames.push(Frame::Lambda(params));
+                frames.push(Frame::Visit(body));
             }
 
-            AnotherExpr::Lambda(params, body) => {
-                if params.len() == 1 {
-                    return format!("{} => {}", params[0], body.to_string());
+            Frame::Apply(atomic, n) => {
+                let args = done.split_off(done.len() - n);
+                let callee = done.pop().unwrap();
+                let callee = if atomic {
+                    callee
                 } else {
-                    format!(
-                        "({}) => {}",
-                        params
-                            .iter()
-                            .map(|arg| arg.to_string())
-                            .rev()
-                            .collect::<Vec<_>>()
-                            .join(", "),
-                        body.to_string()
-                    )
+                    Doc::Concat(vec![Doc::text("("), callee, Doc::text(")")])
+                };
+
+                let mut body = vec![Doc::SoftLine];
+                for (n, arg) in args.into_iter().enumerate() {
+                    if n > 0 {
+                        body.push(Doc::text(","));
+                        body.push(Doc::Line);
+                    }
+                    body.push(arg);
                 }
+
+                done.push(Doc::group(Doc::Concat(vec![
+                    callee,
+                    Doc::text("("),
+                    Doc::nest(2, Doc::Concat(body)),
+                    Doc::SoftLine,
+                    Doc::text(")"),
+                ])));
+            }
+
+            Frame::Lambda(params) => {
+                let body = done.pop().unwrap();
+                done.push(Doc::group(Doc::Concat(vec![
+                    Doc::text(params),
+                    Doc::nest(2, Doc::Concat(vec![Doc::Line, body])),
+                ])));
             }
         }
     }
+
+    done.pop().unwrap()
+}
+
+fn is_atom(e: &Expr) -> bool {
+    matches!(e, Expr::Variable(_) | Expr::Symbol(_))
+}
+
+/// 関数適用の連鎖 `f(x, y, z)` を、左端の関数と引数の列に分ける
+fn spine(e: &Expr) -> (&Expr, Vec<&Expr>) {
+    let mut args = Vec::new();
+    let mut callee = e;
+    while let Expr::Apply { lhs, rhs } = callee {
+        args.push(rhs.as_ref());
+        callee = lhs;
+    }
+    args.reverse();
+    (callee, args)
+}
+
+/// ラムダ抽象の連鎖 `(x, y) => z` を、仮引数の部分 `(x, y) =>` と本体に分ける
+fn params(e: &Expr) -> (String, &Expr) {
+    let mut params = Vec::new();
+    let mut body = e;
+    while let Expr::Lambda { param, body: b } = body {
+        params.push(param.label());
+        body = b;
+    }
+
+    let params = if params.len() == 1 {
+        format!("{} =>", params[0])
+    } else {
+        format!("({}) =>", params.join(", "))
+    };
+    (params, body)
 }
 
 #[test]
@@ -133,4 +209,62 @@ fn test_display() {
         ECMAScriptStyle(&Expr::a(Expr::l("x".into(), "x".into()), "y".into())).to_string(),
         "(x => x)(y)"
     );
+
+    assert_eq!(
+        ECMAScriptStyle(&Expr::a(":a".into(), "b".into())).to_string(),
+        ":a(b)"
+    );
+}
+
+#[test]
+fn test_pretty() {
+    // s(x => x(:a), x => x(:b), :c)
+    let expr = Expr::a(
+        Expr::a(
+            Expr::a(
+                "s".into(),
+                Expr::l("x".into(), Expr::a("x".into(), ":a".into())),
+            ),
+            Expr::l("x".into(), Expr::a("x".into(), ":b".into())),
+        ),
+        ":c".into(),
+    );
+
+    assert_eq!(
+        ECMAScriptStyle(&expr).pretty(80),
+        "s(x => x(:a), x => x(:b), :c)"
+    );
+    assert_eq!(
+        ECMAScriptStyle(&expr).pretty(20),
+        "s(\n  x => x(:a),\n  x => x(:b),\n  :c\n)"
+    );
+
+    let expr = Expr::a(
+        Expr::l("x".into(), Expr::l("y".into(), "x".into())),
+        "FOO".into(),
+    );
+    assert_eq!(ECMAScriptStyle(&expr).pretty(80), "((x, y) => x)(FOO)");
+    assert_eq!(
+        ECMAScriptStyle(&expr).pretty(12),
+        "((x, y) =>\n  x)(\n  FOO\n)"
+    );
+}
+
+#[test]
+fn test_deep() {
+    // 100 万段入れ子になった関数適用でもスタックを溢れさせない
+    let n = 1_000_000;
+
+    let mut expr: Expr = ":a".into();
+    for _ in 0..n {
+        expr = Expr::a(":f".into(), expr);
+    }
+
+    let expected = format!("{}:a{}", ":f(".repeat(n), ")".repeat(n));
+    assert_eq!(ECMAScriptStyle(&expr).to_string(), expected);
+    assert_eq!(ECMAScriptStyle(&expr).pretty(usize::MAX), expected);
+
+    let pretty = ECMAScriptStyle(&expr).pretty(80);
+    assert!(pretty.len() < expected.len() * 2);
+    assert_eq!(pretty.split_whitespace().collect::<String>(), expected);
 }
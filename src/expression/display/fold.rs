@@ -0,0 +1,450 @@
+//! 式の中に定義済みの 0 引数関数の本体と α 同値な部分式があれば、その関数名に置き換える
+//!
+//! 例えば `^x.^y.x` は `TRUE` と、`^f.^x.``f`fx` は `2` と印字されるようになる
+//! 表示のためだけの変換であり、置き換えた結果を簡約してはならない
+
+use std::collections::{HashMap, HashSet};
+
+use crate::context::Context;
+use crate::expression::Expr;
+use crate::function::Func;
+use crate::identifier::Ident;
+
+/// 束縛変数を de Bruijn index で表した式を行きがけ順に並べたもの、α 同値な式は同じ値になる
+///
+/// 木ではなく列で持つので、深い式でも比較やハッシュの計算が再帰しない
+#[derive(Debug, Hash, PartialEq, Eq)]
+struct Nameless(Vec<Term>);
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+enum Term {
+    Free(Ident),
+    Bound(usize),
+    Symbol(Ident),
+    Apply,
+    Lambda,
+}
+
+impl Nameless {
+    fn new(expr: &Expr) -> Nameless {
+        enum Frame<'a> {
+            Visit(&'a Expr),
+            Leave,
+        }
+
+        let mut terms = Vec::new();
+        let mut binders: Vec<&Ident> = Vec::new();
+
+        let mut frames = vec![Frame::Visit(expr)];
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(Expr::Variable(id)) => {
+                    terms.push(match binders.iter().rev().position(|b| *b == id) {
+                        Some(n) => Term::Bound(n),
+                        None => Term::Free(id.clone()),
+                    })
+                }
+
+                Frame::Visit(Expr::Symbol(id)) => terms.push(Term::Symbol(id.clone())),
+
+                Frame::Visit(Expr::Apply { lhs, rhs }) => {
+                    terms.push(Term::Apply);
+                    frames.push(Frame::Visit(rhs));
+                    frames.push(Frame::Visit(lhs));
+                }
+
+                Frame::Visit(Expr::Lambda { param, body }) => {
+                    terms.push(Term::Lambda);
+                    binders.push(param);
+                    frames.push(Frame::Leave);
+                    frames.push(Frame::Visit(body));
+                }
+
+                Frame::Leave => {
+                    binders.pop();
+                }
+            }
+        }
+
+        Nameless(terms)
+    }
+}
+
+/// 索引を引くかどうかを決めるための、部分式の大きさと束縛変数の情報
+#[derive(Clone, Copy, Debug)]
+struct Measure {
+    size: usize,
+
+    /// 部分式に現れる束縛変数のうち、最も外側で束縛されているものを束縛するラムダ抽象の深さ
+    ///
+    /// 部分式を囲むラムダ抽象の数より小さければ、外側に束縛されている変数を含む
+    outermost: Option<usize>,
+}
+
+/// 式を 1 度だけ辿り、部分式ごとの Measure を行きがけ順に並べる
+///
+/// 適用の lhs は適用の次に、rhs は lhs の部分式をすべて並べた後に並ぶ
+fn measure(expr: &Expr) -> Vec<Measure> {
+    enum Frame<'a> {
+        Visit(&'a Expr),
+        Apply(usize),
+        Lambda(usize, &'a Ident),
+    }
+
+    let mut measures = Vec::new();
+
+    // 変数ごとの、その変数を束縛しているラムダ抽象の深さ
+    let mut levels: HashMap<&Ident, Vec<usize>> = HashMap::new();
+    let mut depth = 0;
+
+    let mut frames = vec![Frame::Visit(expr)];
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Visit(e) => {
+                let i = measures.len();
+                let outermost = match e {
+                    Expr::Variable(id) => levels.get(id).and_then(|l| l.last().copied()),
+                    _ => None,
+                };
+                measures.push(Measure { size: 1, outermost });
+
+                match e {
+                    Expr::Variable(_) | Expr::Symbol(_) => {}
+                    Expr::Apply { lhs, rhs } => {
+                        frames.push(Frame::Apply(i));
+                        frames.push(Frame::Visit(rhs));
+                        frames.push(Frame::Visit(lhs));
+                    }
+                    Expr::Lambda { param, body } => {
+                        levels.entry(param).or_default().push(depth);
+                        depth += 1;
+                        frames.push(Frame::Lambda(i, param));
+                        frames.push(Frame::Visit(body));
+                    }
+                }
+            }
+
+            Frame::Apply(i) => {
+                let lhs = measures[i + 1];
+                let rhs = measures[i + 1 + lhs.size];
+                measures[i] = Measure {
+                    size: 1 + lhs.size + rhs.size,
+                    outermost: [lhs.outermost, rhs.outermost].into_iter().flatten().min(),
+                };
+            }
+
+            Frame::Lambda(i, param) => {
+                depth -= 1;
+                if let Some(l) = levels.get_mut(param) {
+                    l.pop();
+                }
+                let body = measures[i + 1];
+                measures[i] = Measure {
+                    size: 1 + body.size,
+                    outermost: body.outermost,
+                };
+            }
+        }
+    }
+
+    measures
+}
+
+/// 定義済み関数の本体から関数名を引くための索引
+#[derive(Debug)]
+pub struct Folder {
+    names: HashMap<Nameless, Ident>,
+
+    /// 索引に含まれる本体のサイズ、サイズが一致しない部分式は索引を引くまでもない
+    sizes: HashSet<usize>,
+}
+
+impl Folder {
+    /// Context に含まれる 0 引数の関数から索引を作る
+    ///
+    /// 本体が変数やシンボルだけの関数 (別名) は対象にしない
+    /// 本体が α 同値な関数が複数ある場合は、組み込みの定義より利用者が定義した名前、
+    /// 数字だけの名前よりそうでない名前、その中では辞書順で最小の名前を採る
+    pub fn new(context: &Context) -> Folder {
+        let builtin = Context::default();
+        let mut candidates: Vec<(&Ident, &Func)> = Vec::new();
+        context.for_each(|name, func| {
+            if func.arity() > 0 {
+                return;
+            }
+            if let Expr::Variable(_) | Expr::Symbol(_) = func.body() {
+                return;
+            }
+            candidates.push((name, func));
+        });
+        candidates.sort_by_key(|(name, func)| {
+            let numeral = name.label().chars().all(|c| c.is_ascii_digit());
+            (builtin.get(name) == Some(*func), numeral, name.label())
+        });
+
+        let mut names: HashMap<Nameless, Ident> = HashMap::new();
+        let mut sizes = HashSet::new();
+        for (name, func) in candidates {
+            sizes.insert(func.body().size());
+            names
+                .entry(Nameless::new(func.body()))
+                .or_insert_with(|| name.clone());
+        }
+
+        Folder { names, sizes }
+    }
+
+    /// 定義済み関数の本体と α 同値な部分式を、外側から順に関数名に置き換える
+    pub fn fold(&self, expr: &Expr) -> Expr {
+        if self.names.is_empty() {
+            return expr.clone();
+        }
+
+        // Visit の添字 i は measures の中での部分式の位置
+        enum Frame<'a> {
+            Visit(&'a Expr, usize),
+            Apply,
+            Lambda(&'a Ident),
+        }
+
+        let measures = measure(expr);
+        let mut binders: Vec<&Ident> = Vec::new();
+
+        let mut frames = vec![Frame::Visit(expr, 0)];
+        let mut done: Vec<Expr> = Vec::new();
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(e, i) => {
+                    // 関数名と同じ名前の束縛変数の内側では、関数名に置き換えると束縛されてしまう
+                    if let Some(name) = self
+                        .lookup(e, measures[i], binders.len())
+                        .filter(|n| !binders.contains(n))
+                    {
+                        done.push(Expr::Variable(name.clone()));
+                        continue;
+                    }
+
+                    match e {
+                        Expr::Variable(_) | Expr::Symbol(_) => done.push(e.clone()),
+
+                        Expr::Apply { lhs, rhs } => {
+                            let j = i + 1 + measures[i + 1].size;
+                            frames.push(Frame::Apply);
+                            frames.push(Frame::Visit(rhs, j));
+                            frames.push(Frame::Visit(lhs, i + 1));
+                        }
+
+                        Expr::Lambda { param, body } => {
+                            binders.push(param);
+                            frames.push(Frame::Lambda(param));
+                            frames.push(Frame::Visit(body, i + 1));
+                        }
+                    }
+                }
+
+                Frame::Apply => {
+                    let rhs = done.pop().unwrap();
+                    let lhs = done.pop().unwrap();
+                    done.push(Expr::a(lhs, rhs));
+                }
+
+                Frame::Lambda(param) => {
+                    binders.pop();
+                    let body = done.pop().unwrap();
+                    done.push(Expr::l(param.clone(), body));
+                }
+            }
+        }
+
+        done.pop().unwrap()
+    }
+
+    /// depth は部分式を囲むラムダ抽象の数
+    fn lookup(&self, expr: &Expr, measure: Measure, depth: usize) -> Option<&Ident> {
+        if let Expr::Variable(_) | Expr::Symbol(_) = expr {
+            return None;
+        }
+        if !self.sizes.contains(&measure.size) {
+            return None;
+        }
+
+        // 外側のラムダ抽象に束縛されている変数を含む部分式は、関数名に置き換えると意味が変わってしまう
+        if measure.outermost.is_some_and(|l| l < depth) {
+            return None;
+        }
+
+        self.names.get(&Nameless::new(expr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Func;
+
+    fn setup() -> Context {
+        Context::from(vec![
+            Func::new(
+                "TRUE".into(),
+                vec![],
+                Expr::l("x".into(), Expr::l("y".into(), "x".into())),
+            ),
+            Func::new(
+                "FALSE".into(),
+                vec![],
+                Expr::l("x".into(), Expr::l("y".into(), "y".into())),
+            ),
+            Func::new(
+                "0".into(),
+                vec![],
+                Expr::l("f".into(), Expr::l("x".into(), "x".into())),
+            ),
+            Func::new("K".into(), vec![], "k".into()),
+            Func::new("I".into(), vec!["x".into()], "x".into()),
+            Func::new("KI".into(), vec![], Expr::a("k".into(), "i".into())),
+        ])
+    }
+
+    #[test]
+    fn test_fold_alpha_equivalent() {
+        let folder = Folder::new(&setup());
+
+        assert_eq!(
+            folder.fold(&Expr::l("a".into(), Expr::l("b".into(), "a".into()))),
+            "TRUE".into()
+        );
+        assert_eq!(
+            folder.fold(&Expr::a(
+                ":f".into(),
+                Expr::l("p".into(), Expr::l("q".into(), "p".into()))
+            )),
+            Expr::a(":f".into(), "TRUE".into())
+        );
+    }
+
+    #[test]
+    fn test_fold_ambiguous() {
+        let folder = Folder::new(&setup());
+
+        // FALSE と 0 は α 同値、数字だけでない名前を採る
+        let f = Expr::l("x".into(), Expr::l("y".into(), "y".into()));
+        assert_eq!(folder.fold(&f), "FALSE".into());
+
+        // 組み込みの定義より利用者が定義した名前を採る
+        let mut context = Context::default();
+        context.def(Func::new("NO".into(), vec![], f.clone()));
+        assert_eq!(Folder::new(&context).fold(&f), "NO".into());
+        assert_eq!(Folder::new(&Context::default()).fold(&f), "FALSE".into());
+    }
+
+    #[test]
+    fn test_measure() {
+        // ^x.``x:a^y.`xy
+        let expr = Expr::l(
+            "x".into(),
+            Expr::a(
+                Expr::a("x".into(), ":a".into()),
+                Expr::l("y".into(), Expr::a("x".into(), "y".into())),
+            ),
+        );
+        let measures = measure(&expr);
+
+        let sizes = measures.iter().map(|m| m.size).collect::<Vec<_>>();
+        assert_eq!(sizes, expr_sizes(&expr));
+
+        let outermost = measures.iter().map(|m| m.outermost).collect::<Vec<_>>();
+        assert_eq!(
+            outermost,
+            vec![
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(0),
+                None,
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(1)
+            ]
+        );
+    }
+
+    /// 部分式の大きさを行きがけ順に並べる
+    fn expr_sizes(expr: &Expr) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        let mut pending = vec![expr];
+        while let Some(e) = pending.pop() {
+            sizes.push(e.size());
+            match e {
+                Expr::Variable(_) | Expr::Symbol(_) => {}
+                Expr::Apply { lhs, rhs } => {
+                    pending.push(rhs);
+                    pending.push(lhs);
+                }
+                Expr::Lambda { body, .. } => pending.push(body),
+            }
+        }
+        sizes
+    }
+
+    #[test]
+    fn test_fold_deep() {
+        // 100 万段入れ子になった関数適用でもスタックを溢れさせない
+        let n = 1_000_000;
+
+        let mut expr = Expr::l("a".into(), Expr::l("b".into(), "a".into()));
+        for _ in 0..n {
+            expr = Expr::a(":f".into(), expr);
+        }
+        let mut expected: Expr = "TRUE".into();
+        for _ in 0..n {
+            expected = Expr::a(":f".into(), expected);
+        }
+
+        assert_eq!(Folder::new(&setup()).fold(&expr), expected);
+
+        // 深い本体を持つ定義でも索引を作れる
+        let mut context = setup();
+        context.def(Func::new("DEEP".into(), vec![], expr.clone()));
+        let folder = Folder::new(&context);
+
+        assert_eq!(
+            folder.fold(&Expr::a(":g".into(), expr)),
+            Expr::a(":g".into(), "DEEP".into())
+        );
+    }
+
+    #[test]
+    fn test_fold_skips_aliases_and_functions() {
+        let folder = Folder::new(&setup());
+
+        // 本体が変数だけの関数や、引数を取る関数では置き換えない
+        assert_eq!(folder.fold(&"k".into()), "k".into());
+        assert_eq!(
+            folder.fold(&Expr::l("x".into(), "x".into())),
+            Expr::l("x".into(), "x".into())
+        );
+    }
+
+    #[test]
+    fn test_fold_free_variables() {
+        let folder = Folder::new(&setup());
+
+        assert_eq!(folder.fold(&Expr::a("k".into(), "i".into())), "KI".into());
+
+        // ラムダ抽象に束縛された k は定義済み関数の k とは別物
+        let expr = Expr::l("k".into(), Expr::a("k".into(), "i".into()));
+        assert_eq!(folder.fold(&expr), expr);
+
+        // 関数名と同じ名前の束縛変数の内側では置き換えない
+        let expr = Expr::l(
+            "TRUE".into(),
+            Expr::a(
+                "TRUE".into(),
+                Expr::l("x".into(), Expr::l("y".into(), "x".into())),
+            ),
+        );
+        assert_eq!(folder.fold(&expr), expr);
+    }
+}
@@ -1,3 +1,5 @@
+use super::is_upper_ident;
+use super::pretty::Doc;
 use crate::expression::Expr;
 use std::fmt::Display;
 
@@ -10,6 +12,122 @@ impl Display for LazyKStyle<'_> {
     }
 }
 
+impl LazyKStyle<'_> {
+    /// 行幅 width に収まるように改行・インデントを加えて印字する
+    pub fn pretty(&self, width: usize) -> String {
+        doc(self.0).render(width)
+    }
+}
+
+/// 式をプリティプリンタ用の `Doc` に変換する
+///
+/// 1 行に収まる場合は `Display` と同じ文字列になる
+pub fn doc(expr: &Expr) -> Doc {
+    enum Frame<'a> {
+        Visit(&'a Expr),
+        Apply(Vec<Doc>),
+        Lambda(String),
+    }
+
+    // 部分式の Doc を done に積み、親の Frame でまとめる
+    let mut frames = vec![Frame::Visit(expr)];
+    let mut done: Vec<Doc> = Vec::new();
+
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Visit(Expr::Variable(i)) => done.push(Doc::text(i.label())),
+
+            Frame::Visit(Expr::Symbol(i)) => done.push(Doc::text(format!(":{}", i.label()))),
+
+            Frame::Visit(e @ Expr::Apply { .. }) => {
+                let mut args = Vec::new();
+                let mut callee = e;
+                while let Expr::Apply { lhs, rhs } = callee {
+                    args.push(rhs.as_ref());
+                    callee = lhs;
+                }
+                args.reverse();
+
+                let mut separators = Vec::new();
+                let mut prev = callee;
+                for arg in args.iter() {
+                    separators.push(separator(prev, arg));
+                    prev = arg;
+                }
+
+                frames.push(Frame::Apply(separators));
+                for arg in args.iter().rev() {
+                    frames.push(Frame::Visit(arg));
+                }
+                frames.push(Frame::Visit(callee));
+            }
+
+            Frame::Visit(e @ Expr::Lambda { .. }) => {
+                let mut params = String::new();
+                let mut body = e;
+                while let Expr::Lambda { param, body: b } = body {
+                    params.push_str(&format!("^{}.", param.label()));
+                    body = b;
+                }
+
+                frames.push(Frame::Lambda(params));
+                frames.push(Frame::Visit(body));
+            }
+
+            Frame::Apply(separators) => {
+                let args = done.split_off(done.len() - separators.len());
+                let callee = done.pop().unwrap();
+                let backquotes = Doc::text("`".repeat(args.len()));
+
+                let mut body = Vec::new();
+                for (separator, arg) in separators.into_iter().zip(args) {
+                    body.push(separator);
+                    body.push(arg);
+                }
+
+                done.push(Doc::group(Doc::Concat(vec![
+                    backquotes,
+                    callee,
+                    Doc::nest(2, Doc::Concat(body)),
+                ])));
+            }
+
+            Frame::Lambda(params) => {
+                let body = done.pop().unwrap();
+                done.push(Doc::group(Doc::Concat(vec![
+                    Doc::text(params),
+                    Doc::nest(2, Doc::Concat(vec![Doc::SoftLine, body])),
+                ])));
+            }
+        }
+    }
+
+    done.pop().unwrap()
+}
+
+/// 隣り合う 2 つの式の間の区切り
+///
+/// 大文字の識別子の直後に大文字の変数が続く場合のみ、1 行に収まるときでも空白で区切る必要がある
+fn separator(prev: &Expr, next: &Expr) -> Doc {
+    let mut last = prev;
+    loop {
+        match last {
+            Expr::Apply { rhs, .. } => last = rhs,
+            Expr::Lambda { body, .. } => last = body,
+            _ => break,
+        }
+    }
+
+    match (last, next) {
+        (Expr::Variable(i) | Expr::Symbol(i), Expr::Variable(j))
+            if is_upper_ident(i.label()) && is_upper_ident(j.label()) =>
+        {
+            Doc::Line
+        }
+        _ => Doc::SoftLine,
+    }
+}
+
 #[test]
 fn test_display() {
     assert_eq!(LazyKStyle(&"x".into()).to_string(), "x");
@@ -39,3 +157,46 @@ fn test_display() {
         "^x.^y.x"
     );
 }
+
+#[test]
+fn test_pretty() {
+    // ```s^x.`x:a^x.`x:b:c
+    let expr = Expr::a(
+        Expr::a(
+            Expr::a(
+                "s".into(),
+                Expr::l("x".into(), Expr::a("x".into(), ":a".into())),
+            ),
+            Expr::l("x".into(), Expr::a("x".into(), ":b".into())),
+        ),
+        ":c".into(),
+    );
+
+    assert_eq!(LazyKStyle(&expr).pretty(80), "```s^x.`x:a^x.`x:b:c");
+    assert_eq!(
+        LazyKStyle(&expr).pretty(12),
+        "```s\n  ^x.`x:a\n  ^x.`x:b\n  :c"
+    );
+
+    let expr = Expr::a(Expr::a("FOO".into(), "BAR".into()), "BAZ".into());
+    assert_eq!(LazyKStyle(&expr).pretty(80), "``FOO BAR BAZ");
+    assert_eq!(LazyKStyle(&expr).pretty(8), "``FOO\n  BAR\n  BAZ");
+}
+
+#[test]
+fn test_pretty_deep() {
+    // 100 万段入れ子になった関数適用でもスタックを溢れさせない
+    let n = 1_000_000;
+
+    let mut expr: Expr = ":a".into();
+    for _ in 0..n {
+        expr = Expr::a(":f".into(), expr);
+    }
+
+    let expected = format!("{}:a", "`:f".repeat(n));
+    assert_eq!(LazyKStyle(&expr).pretty(usize::MAX), expected);
+
+    let pretty = LazyKStyle(&expr).pretty(80);
+    assert!(pretty.len() < expected.len() * 2);
+    assert_eq!(pretty.split_whitespace().collect::<String>(), expected);
+}
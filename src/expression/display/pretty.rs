@@ -0,0 +1,230 @@
+//! 行幅を考慮して式を改行・インデントするためのプリティプリンタ
+//!
+//! Wadler の "A prettier printer" に倣い、式をいったん `Doc` に変換してから
+//! 指定した行幅に収まるように `Group` ごとに改行するかどうかを決める
+
+/// 印字レイアウトを表現する文書
+#[derive(Clone, Debug, PartialEq)]
+pub enum Doc {
+    /// 改行を含まない文字列
+    Text(String),
+
+    /// 改行、ただし Group が 1 行に収まる場合は空白 1 つ
+    Line,
+
+    /// 改行、ただし Group が 1 行に収まる場合は何も出力しない
+    SoftLine,
+
+    /// 内側の改行の後に指定した幅だけインデントを加える
+    Nest(usize, Box<Doc>),
+
+    /// 1 行に収まるなら内側の改行をすべて平坦化する
+    Group(Box<Doc>),
+
+    /// 連結
+    Concat(Vec<Doc>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+impl Doc {
+    pub fn text(s: impl Into<String>) -> Doc {
+        Doc::Text(s.into())
+    }
+
+    pub fn nest(indent: usize, doc: Doc) -> Doc {
+        Doc::Nest(indent, Box::new(doc))
+    }
+
+    pub fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+
+    /// 1 行あたり width 文字に収まるように印字する
+    ///
+    /// 分割できない Text が width を超える場合はそのままはみ出す
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut column = 0;
+        let mut stack: Vec<(usize, Mode, &Doc)> = vec![(0, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    column += s.chars().count();
+                }
+
+                Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                    if doc == &Doc::Line {
+                        out.push(' ');
+                        column += 1;
+                    }
+                }
+
+                Doc::Line | Doc::SoftLine => {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                    column = indent;
+                }
+
+                Doc::Nest(i, doc) => stack.push((indent + i, mode, doc)),
+
+                // 字下げが行幅に達していれば改行しても収まらないので、それより深い Group は平坦化する
+                // こうしないと深い式では字下げの空白だけで出力が式の大きさの 2 乗に膨らむ
+                Doc::Group(doc) => {
+                    let mode = if mode == Mode::Flat
+                        || indent >= width
+                        || fits(width.saturating_sub(column), (indent, doc), &stack)
+                    {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+
+                Doc::Concat(docs) => {
+                    for doc in docs.iter().rev() {
+                        stack.push((indent, mode, doc));
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl Drop for Doc {
+    fn drop(&mut self) {
+        // 深い式の Doc でも再帰的に drop が呼ばれないよう、内側の Doc を取り出してから捨てる
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut doc) = pending.pop() {
+            doc.take_children(&mut pending);
+        }
+    }
+}
+
+impl Doc {
+    /// 内側の Doc を取り出して pending に積む
+    fn take_children(&mut self, pending: &mut Vec<Doc>) {
+        match self {
+            Doc::Nest(_, doc) | Doc::Group(doc) => {
+                pending.push(std::mem::replace(doc.as_mut(), Doc::SoftLine))
+            }
+            Doc::Concat(docs) => pending.append(docs),
+            Doc::Text(_) | Doc::Line | Doc::SoftLine => {}
+        }
+    }
+}
+
+/// Group を平坦化したとき、次の改行までが残りの幅に収まるかを判定する
+fn fits(mut rest: usize, group: (usize, &Doc), stack: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = stack.iter().copied();
+    let mut local: Vec<(usize, Mode, &Doc)> = vec![(group.0, Mode::Flat, group.1)];
+
+    loop {
+        let (indent, mode, doc) = match local.pop().or_else(|| stack.next_back()) {
+            Some(item) => item,
+            None => return true,
+        };
+
+        match doc {
+            Doc::Text(s) => {
+                let len = s.chars().count();
+                if len > rest {
+                    return false;
+                }
+                rest -= len;
+            }
+
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+
+            Doc::Line => {
+                if rest == 0 {
+                    return false;
+                }
+                rest -= 1;
+            }
+
+            Doc::SoftLine => {}
+
+            Doc::Nest(i, doc) => local.push((indent + i, mode, doc)),
+
+            Doc::Group(doc) => local.push((indent, mode, doc)),
+
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    local.push((indent, mode, doc));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words() -> Doc {
+        Doc::group(Doc::Concat(vec![
+            Doc::text("foo("),
+            Doc::nest(
+                2,
+                Doc::Concat(vec![
+                    Doc::SoftLine,
+                    Doc::text("bar,"),
+                    Doc::Line,
+                    Doc::text("baz"),
+                ]),
+            ),
+            Doc::SoftLine,
+            Doc::text(")"),
+        ]))
+    }
+
+    #[test]
+    fn test_render_flat() {
+        assert_eq!(words().render(80), "foo(bar, baz)");
+        assert_eq!(words().render(13), "foo(bar, baz)");
+    }
+
+    #[test]
+    fn test_render_break() {
+        assert_eq!(words().render(12), "foo(\n  bar,\n  baz\n)");
+    }
+
+    #[test]
+    fn test_render_deep_indent() {
+        // 字下げが行幅に達した Group は改行しない
+        let mut doc = Doc::text("x");
+        for _ in 0..4 {
+            doc = Doc::group(Doc::Concat(vec![
+                Doc::text("f("),
+                Doc::nest(2, Doc::Concat(vec![Doc::SoftLine, doc])),
+                Doc::SoftLine,
+                Doc::text(")"),
+            ]));
+        }
+
+        assert_eq!(doc.render(4), "f(\n  f(\n    f(f(x))\n  )\n)");
+    }
+
+    #[test]
+    fn test_render_nested_group() {
+        let doc = Doc::group(Doc::Concat(vec![
+            Doc::text("abc"),
+            Doc::nest(2, Doc::Concat(vec![Doc::Line, words()])),
+        ]));
+
+        assert_eq!(doc.render(80), "abc foo(bar, baz)");
+        assert_eq!(doc.render(16), "abc\n  foo(bar, baz)");
+        assert_eq!(doc.render(10), "abc\n  foo(\n    bar,\n    baz\n  )");
+    }
+}
@@ -1,34 +1,76 @@
 use crate::expression::Expr;
 use crate::identifier::Ident;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub type FreeVars<'a> = HashSet<&'a Ident>;
 
-pub fn free_vars(expr: &Expr) -> FreeVars {
+pub fn free_vars(expr: &Expr) -> FreeVars<'_> {
+    enum Frame<'a> {
+        Visit(&'a Expr),
+
+        /// ラムダ抽象の本体を辿り終えたら束縛を外す
+        Unbind(&'a Ident),
+    }
+
     let mut vars: FreeVars = HashSet::new();
-    free_vars_impl(expr, &mut vars);
+
+    // 現在の位置を囲むラムダ抽象に束縛されている変数と、その束縛の数
+    let mut bound: HashMap<&Ident, usize> = HashMap::new();
+
+    let mut frames = vec![Frame::Visit(expr)];
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Visit(Expr::Variable(id)) => {
+                if !bound.contains_key(id) {
+                    vars.insert(id);
+                }
+            }
+            Frame::Visit(Expr::Symbol(_)) => {}
+            Frame::Visit(Expr::Apply { lhs, rhs }) => {
+                frames.push(Frame::Visit(rhs));
+                frames.push(Frame::Visit(lhs));
+            }
+            Frame::Visit(Expr::Lambda { param, body }) => {
+                *bound.entry(param).or_insert(0) += 1;
+                frames.push(Frame::Unbind(param));
+                frames.push(Frame::Visit(body));
+            }
+
+            Frame::Unbind(param) => {
+                if let Some(n) = bound.get_mut(param) {
+                    *n -= 1;
+                    if *n == 0 {
+                        bound.remove(param);
+                    }
+                }
+            }
+        }
+    }
+
     vars
 }
 
-fn free_vars_impl<'a>(expr: &'a Expr, vars: &mut FreeVars<'a>) {
-    match expr {
-        Expr::Variable(id) => {
-            vars.insert(id);
-        }
-        Expr::Symbol(_) => {}
-        Expr::Apply { lhs, rhs } => {
-            free_vars_impl(lhs.as_ref(), vars);
-            free_vars_impl(rhs.as_ref(), vars);
-        }
-        Expr::Lambda { param, body } => {
-            let mut body_vars: FreeVars = HashSet::new();
-            free_vars_impl(body.as_ref(), &mut body_vars);
-            for var in body_vars {
-                if var != param {
-                    vars.insert(var);
+impl Expr {
+    /// 式に現れるシンボル
+    pub fn symbols(&self) -> HashSet<&Ident> {
+        let mut symbols = HashSet::new();
+
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Variable(_) => {}
+                Expr::Symbol(id) => {
+                    symbols.insert(id);
                 }
+                Expr::Apply { lhs, rhs } => {
+                    pending.push(rhs);
+                    pending.push(lhs);
+                }
+                Expr::Lambda { body, .. } => pending.push(body),
             }
         }
+
+        symbols
     }
 }
 
@@ -36,6 +78,17 @@ fn free_vars_impl<'a>(expr: &'a Expr, vars: &mut FreeVars<'a>) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_symbols() {
+        let expr = Expr::l(
+            "x".into(),
+            Expr::a(":a".into(), Expr::a("x".into(), ":b".into())),
+        );
+        let a = Ident::new("a");
+        let b = Ident::new("b");
+        assert_eq!(expr.symbols(), HashSet::from([&a, &b]));
+    }
+
     #[test]
     fn test_free_vars() {
         assert_eq!(
@@ -0,0 +1,69 @@
+use crate::expression::Expr;
+
+impl Expr {
+    /// 式を構成するノードの数
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            size += 1;
+            match expr {
+                Expr::Variable(_) | Expr::Symbol(_) => {}
+                Expr::Apply { lhs, rhs } => {
+                    pending.push(lhs);
+                    pending.push(rhs);
+                }
+                Expr::Lambda { body, .. } => pending.push(body),
+            }
+        }
+        size
+    }
+
+    /// 式の木の深さ、変数やシンボルだけからなる式の深さは 1
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut pending = vec![(self, 1)];
+        while let Some((expr, d)) = pending.pop() {
+            depth = depth.max(d);
+            match expr {
+                Expr::Variable(_) | Expr::Symbol(_) => {}
+                Expr::Apply { lhs, rhs } => {
+                    pending.push((lhs, d + 1));
+                    pending.push((rhs, d + 1));
+                }
+                Expr::Lambda { body, .. } => pending.push((body, d + 1)),
+            }
+        }
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(Expr::v("x").size(), 1);
+        assert_eq!(Expr::s("x").size(), 1);
+        assert_eq!(Expr::a("x".into(), "y".into()).size(), 3);
+        assert_eq!(
+            Expr::l("x".into(), Expr::a("x".into(), "y".into())).size(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        assert_eq!(Expr::v("x").depth(), 1);
+        assert_eq!(Expr::a("x".into(), "y".into()).depth(), 2);
+        assert_eq!(
+            Expr::a(
+                "x".into(),
+                Expr::l("y".into(), Expr::a("y".into(), "z".into()))
+            )
+            .depth(),
+            4
+        );
+    }
+}
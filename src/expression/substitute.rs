@@ -1,11 +1,24 @@
+use std::cell::Cell;
 use std::collections::HashSet;
 
 use crate::expression::free_vars::{free_vars, FreeVars};
-use crate::expression::Expr;
+use crate::expression::{Expr, Node};
 use crate::identifier::Ident;
 
 type BoundVars = HashSet<Ident>;
 
+thread_local! {
+    static SUBSTITUTIONS: Cell<usize> = const { Cell::new(0) };
+    static RENAMES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// このスレッドでこれまでに行った置換と α 変換の回数
+///
+/// 値は単調に増加する、計測したい区間の前後の差を取って使う
+pub fn substitution_counts() -> (usize, usize) {
+    (SUBSTITUTIONS.get(), RENAMES.get())
+}
+
 impl Expr {
     /// 指定した識別子を別の式で置き換えた新しい式を得る
     ///
@@ -45,82 +58,114 @@ impl Expr {
     /// );
     /// ```
     pub fn substitute(self, param: &Ident, arg: &Expr) -> Expr {
-        let mut vars: BoundVars = HashSet::new();
-        let free_vars = free_vars(&arg);
-        self.substitute_impl(param, arg, &free_vars, &mut vars)
+        SUBSTITUTIONS.set(SUBSTITUTIONS.get() + 1);
+
+        let free_vars = free_vars(arg);
+        self.substitute_impl(param, arg, &free_vars)
     }
 
-    fn substitute_impl<'a>(
-        self,
-        param: &Ident,
-        arg: &Expr,
-        free_vars: &FreeVars,
-        bound_vars: &mut BoundVars,
-    ) -> Expr {
-        match self {
-            Expr::Variable(id) => {
-                if &id == param {
-                    arg.clone()
-                } else {
-                    Expr::Variable(id)
-                }
-            }
+    /// 深い式でもスタックを溢れさせないよう、明示的なスタックを使って木を辿る
+    fn substitute_impl(self, param: &Ident, arg: &Expr, free_vars: &FreeVars) -> Expr {
+        enum Frame {
+            Visit(Expr),
+            Apply,
 
-            Expr::Symbol(_) => self,
+            /// 仮引数と、それを bound_vars に新たに加えたかどうか
+            Lambda(Ident, bool),
+        }
 
-            Expr::Apply { lhs, rhs } => Expr::Apply {
-                lhs: Box::new(lhs.substitute_impl(param, arg, free_vars, &mut bound_vars.clone())),
-                rhs: Box::new(rhs.substitute_impl(param, arg, free_vars, &mut bound_vars.clone())),
-            },
+        // 根から現在の位置までの経路上で束縛されている変数
+        let mut bound_vars: BoundVars = HashSet::new();
 
-            Expr::Lambda { param: p, body } => {
-                if &p == param {
-                    Expr::Lambda { param: p, body }
-                } else if free_vars.contains(&p) {
-                    let new_param: Ident = p.new_name(bound_vars);
-                    bound_vars.insert(new_param.clone());
+        let mut frames = vec![Frame::Visit(self)];
+        let mut done: Vec<Expr> = Vec::new();
 
-                    let mut new_body = body.clone();
-                    new_body.rename_var(&p, &new_param);
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(expr) => match expr.into_node() {
+                    Node::Variable(id) => {
+                        if &id == param {
+                            done.push(arg.clone());
+                        } else {
+                            done.push(Expr::Variable(id));
+                        }
+                    }
+
+                    Node::Symbol(id) => done.push(Expr::Symbol(id)),
 
-                    Expr::Lambda {
-                        param: new_param,
-                        body: Box::new(new_body.substitute_impl(param, arg, free_vars, bound_vars)),
+                    Node::Apply(lhs, rhs) => {
+                        frames.push(Frame::Apply);
+                        frames.push(Frame::Visit(rhs));
+                        frames.push(Frame::Visit(lhs));
                     }
-                } else {
-                    bound_vars.insert(p.clone());
-                    Expr::Lambda {
-                        param: p,
-                        body: Box::new(body.substitute_impl(param, arg, free_vars, bound_vars)),
+
+                    Node::Lambda(p, body) => {
+                        if &p == param {
+                            done.push(Expr::l(p, body));
+                        } else if free_vars.contains(&p) {
+                            let new_param: Ident = p.new_name(&bound_vars);
+                            RENAMES.set(RENAMES.get() + 1);
+                            let inserted = bound_vars.insert(new_param.clone());
+
+                            let mut new_body = body;
+                            new_body.rename_var(&p, &new_param);
+
+                            frames.push(Frame::Lambda(new_param, inserted));
+                            frames.push(Frame::Visit(new_body));
+                        } else {
+                            let inserted = bound_vars.insert(p.clone());
+                            frames.push(Frame::Lambda(p, inserted));
+                            frames.push(Frame::Visit(body));
+                        }
                     }
+                },
+
+                Frame::Apply => {
+                    let rhs = done.pop().unwrap();
+                    let lhs = done.pop().unwrap();
+                    done.push(Expr::a(lhs, rhs));
+                }
+
+                Frame::Lambda(p, inserted) => {
+                    if inserted {
+                        bound_vars.remove(&p);
+                    }
+                    let body = done.pop().unwrap();
+                    done.push(Expr::l(p, body));
                 }
             }
         }
+
+        done.pop().unwrap()
     }
 
     /// 式の中の自由変数を別の識別子に置き換える
     fn rename_var(&mut self, old: &Ident, new: &Ident) {
-        match self {
-            Expr::Variable(id) => {
-                if id == old {
-                    *id = new.clone();
+        let mut pending = vec![self];
+
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Variable(id) => {
+                    if id == old {
+                        *id = new.clone();
+                    }
                 }
-            }
 
-            Expr::Symbol(_) => {}
+                Expr::Symbol(_) => {}
 
-            Expr::Apply { lhs, rhs } => {
-                lhs.rename_var(old, new);
-                rhs.rename_var(old, new);
-            }
+                Expr::Apply { lhs, rhs } => {
+                    pending.push(rhs);
+                    pending.push(lhs);
+                }
 
-            Expr::Lambda { param, body } => {
-                if param != old {
-                    body.rename_var(old, new);
+                Expr::Lambda { param, body } => {
+                    if param != old {
+                        pending.push(body);
+                    }
+                    // 自由変数としての old のみ new に置き換えたい
+                    // old が束縛変数の識別子と一致する場合、そのラムダ抽象の中に自由変数としての old は
+                    // 存在しないことが確定するので、その時点で再起を打ち切っていい
                 }
-                // 自由変数としての old のみ new に置き換えたい
-                // old が束縛変数の識別子と一致する場合、そのラムダ抽象の中に自由変数としての old は
-                // 存在しないことが確定するので、その時点で再起を打ち切っていい
             }
         }
     }
@@ -152,6 +197,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_substitution_counts() {
+        let (substitutions, renames) = substitution_counts();
+
+        // ^y.`xy [x := y] => ^Y.`yY
+        Expr::l("y".into(), Expr::a("x".into(), "y".into())).substitute(&"x".into(), &"y".into());
+
+        assert_eq!(substitution_counts(), (substitutions + 1, renames + 1));
+    }
+
     #[test]
     /// ラムダ抽象の中で束縛されている変数は置換されない
     fn test_rename_var_1() {
@@ -1,34 +1,90 @@
 use crate::expression::free_vars::free_vars;
-use crate::expression::Expr;
+use crate::expression::{Expr, Node};
 use crate::identifier::Ident;
 
+/// unlambda の途中で残っている仕事
+enum Frame {
+    /// 式を変換する、仮引数があればそれを取り除くように変換する
+    Visit(Expr, Option<Ident>),
+
+    /// 変換済みの 2 つの式から適用を作る、仮引数を取り除いている最中なら S コンビネータを挟む
+    Apply(bool),
+
+    /// 変換済みの式から、さらに仮引数を取り除く
+    Abstract(Ident),
+}
+
 impl Expr {
     pub fn unlambda(self) -> Expr {
-        match self {
-            Expr::Variable(_) => self,
-            Expr::Symbol(_) => self,
-            Expr::Apply { lhs, rhs } => Expr::a(lhs.unlambda(), rhs.unlambda()),
-            Expr::Lambda { param, body } => body.unlambda_(&param),
+        // 深い式でもスタックを溢れさせないよう、明示的なスタックを使って木を辿る
+        let mut frames = vec![Frame::Visit(self, None)];
+        let mut done: Vec<Expr> = Vec::new();
+
+        while let Some(frame) = frames.pop() {
+            match frame {
+                Frame::Visit(expr, None) => match expr.into_node() {
+                    Node::Variable(id) => done.push(Expr::Variable(id)),
+                    Node::Symbol(id) => done.push(Expr::Symbol(id)),
+                    Node::Apply(lhs, rhs) => {
+                        frames.push(Frame::Apply(false));
+                        frames.push(Frame::Visit(rhs, None));
+                        frames.push(Frame::Visit(lhs, None));
+                    }
+                    Node::Lambda(param, body) => frames.push(Frame::Visit(body, Some(param))),
+                },
+
+                Frame::Visit(expr, Some(param)) => unlambda_(expr, param, &mut frames, &mut done),
+
+                Frame::Apply(s) => {
+                    let rhs = done.pop().unwrap();
+                    let lhs = done.pop().unwrap();
+                    if s {
+                        done.push(Expr::a(Expr::a("s".into(), lhs), rhs));
+                    } else {
+                        done.push(Expr::a(lhs, rhs));
+                    }
+                }
+
+                Frame::Abstract(param) => {
+                    let expr = done.pop().unwrap();
+                    frames.push(Frame::Visit(expr, Some(param)));
+                }
+            }
         }
+
+        done.pop().unwrap()
     }
+}
 
-    fn unlambda_(self, param: &Ident) -> Expr {
-        match self {
-            Expr::Variable(id) if &id == param => "i".into(),
-            Expr::Variable(id) => Expr::a("k".into(), Expr::Variable(id)),
-            Expr::Symbol(_) => Expr::a("k".into(), self),
-            Expr::Apply { .. } if !free_vars(&self).contains(param) => Expr::a("k".into(), self),
-            Expr::Apply { lhs, rhs } => match rhs.as_ref() {
-                Expr::Variable(id) if id == param && !free_vars(lhs.as_ref()).contains(param) => {
-                    *lhs
-                }
-                _ => Expr::a(
-                    Expr::a("s".into(), lhs.unlambda_(param)),
-                    rhs.unlambda_(param),
-                ),
-            },
-            Expr::Lambda { param: inner, body } => body.unlambda_(&inner).unlambda_(param),
+/// 式から仮引数 param を取り除く
+fn unlambda_(expr: Expr, param: Ident, frames: &mut Vec<Frame>, done: &mut Vec<Expr>) {
+    match &expr {
+        Expr::Variable(id) if id == &param => return done.push("i".into()),
+        Expr::Variable(_) | Expr::Symbol(_) => return done.push(Expr::a("k".into(), expr)),
+        Expr::Apply { .. } if !free_vars(&expr).contains(&param) => {
+            return done.push(Expr::a("k".into(), expr))
         }
+        _ => {}
+    }
+
+    match expr.into_node() {
+        Node::Apply(lhs, rhs) => match &rhs {
+            Expr::Variable(id) if id == &param && !free_vars(&lhs).contains(&param) => {
+                done.push(lhs)
+            }
+            _ => {
+                frames.push(Frame::Apply(true));
+                frames.push(Frame::Visit(rhs, Some(param.clone())));
+                frames.push(Frame::Visit(lhs, Some(param)));
+            }
+        },
+
+        Node::Lambda(inner, body) => {
+            frames.push(Frame::Abstract(param));
+            frames.push(Frame::Visit(body, Some(inner)));
+        }
+
+        Node::Variable(_) | Node::Symbol(_) => unreachable!(),
     }
 }
 
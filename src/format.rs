@@ -0,0 +1,190 @@
+//! 定義ファイルを正規の書式に整形する
+//!
+//! 印字と再パースの往復で式が変化しないことを保証する
+//! すなわち、任意の `Command` について `parse_line(&format_command(&c, style)) == Ok(c)` が成り立つ
+//! 識別子の形は `Ident` が作る時点で 1 文字の小文字、または大文字・数字・`_` からなる列に制限しているので、
+//! `foo` のようにどちらのスタイルでもパースできない識別子を含む `Command` はそもそも作れない
+
+use crate::command::display::ecmascript::ECMAScriptStyle;
+use crate::command::display::lazy_k::LazyKStyle;
+use crate::command::Command;
+use crate::config::DisplayStyle;
+use crate::expression::display::ecmascript::ECMAScriptStyle as ECMAScriptExprStyle;
+use crate::expression::display::lazy_k::LazyKStyle as LazyKExprStyle;
+use crate::expression::Expr;
+use crate::parser::command::ecmascript::parse_command as parse_ecmascript_style_command;
+use crate::parser::command::lazy_k::parse_command as parse_lazy_k_style_command;
+use crate::runner::{format_expectation, parse_expectation};
+
+/// 1 行分のコマンドをパースする
+///
+/// Lazy K スタイルでのパースを試み、失敗したら ECMAScript スタイルでパースする
+pub fn parse_line(line: &str) -> Result<Command, String> {
+    parse_lazy_k_style_command(line).or_else(|_err| parse_ecmascript_style_command(line))
+}
+
+/// 1 行分のコマンドを指定したスタイルでパースする
+pub fn parse_command(line: &str, style: &DisplayStyle) -> Result<Command, String> {
+    match style {
+        DisplayStyle::LazyK => parse_lazy_k_style_command(line),
+        DisplayStyle::Ecmascript => parse_ecmascript_style_command(line),
+    }
+}
+
+/// 式を指定したスタイルで印字する
+pub fn format_expr(expr: &Expr, style: &DisplayStyle) -> String {
+    match style {
+        DisplayStyle::LazyK => LazyKExprStyle(expr).to_string(),
+        DisplayStyle::Ecmascript => ECMAScriptExprStyle(expr).to_string(),
+    }
+}
+
+/// コマンドを指定したスタイルの正規の書式で印字する
+pub fn format_command(command: &Command, style: &DisplayStyle) -> String {
+    match style {
+        DisplayStyle::LazyK => LazyKStyle(command).to_string(),
+        DisplayStyle::Ecmascript => ECMAScriptStyle(command).to_string(),
+    }
+}
+
+/// 定義ファイルの内容全体を整形する
+///
+/// 各行をコマンドまたは期待値としてパースし、指定したスタイルで印字し直す
+/// 空行はそのまま残し、行末の空白は取り除く
+pub fn format_source(source: &str, style: &DisplayStyle) -> Result<String, String> {
+    let mut formatted = String::new();
+
+    for (n, line) in source.lines().enumerate() {
+        if let Some(expectation) = parse_expectation(line) {
+            let expectation = expectation.map_err(|e| format!("line {}: {}", n + 1, e))?;
+            formatted.push_str(&format_expectation(&expectation, style));
+        } else if !line.trim().is_empty() {
+            let command = parse_line(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            formatted.push_str(&format_command(&command, style));
+        }
+        formatted.push('\n');
+    }
+
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Func;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    const LABELS: [&str; 8] = ["x", "y", "i", "X", "FOO", "X0", "42", "A_B"];
+
+    fn random_expr(rng: &mut StdRng, depth: usize) -> Expr {
+        let label = *LABELS.choose(rng).unwrap();
+
+        match if depth == 0 { 0 } else { rng.gen_range(0..4) } {
+            0 => Expr::v(label),
+            1 => Expr::s(label),
+            2 => Expr::a(random_expr(rng, depth - 1), random_expr(rng, depth - 1)),
+            _ => Expr::l(label.into(), random_expr(rng, depth - 1)),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_expr() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..300 {
+            let command = Command::Eval(random_expr(&mut rng, 6));
+
+            for style in [DisplayStyle::LazyK, DisplayStyle::Ecmascript] {
+                let printed = format_command(&command, &style);
+                assert_eq!(parse_line(&printed).as_ref(), Ok(&command), "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_pretty() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..300 {
+            let expr = random_expr(&mut rng, 6);
+            let width = rng.gen_range(1..40);
+
+            let printed = LazyKExprStyle(&expr).pretty(usize::MAX);
+            assert_eq!(printed, LazyKExprStyle(&expr).to_string());
+            let printed = LazyKExprStyle(&expr).pretty(width);
+            assert_eq!(parse_line(&printed), Ok(Command::Eval(expr.clone())));
+
+            let printed = ECMAScriptExprStyle(&expr).pretty(usize::MAX);
+            assert_eq!(printed, ECMAScriptExprStyle(&expr).to_string());
+            let printed = ECMAScriptExprStyle(&expr).pretty(width);
+            assert_eq!(parse_line(&printed), Ok(Command::Eval(expr.clone())));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_func() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..300 {
+            let arity = rng.gen_range(0..4);
+            let params = LABELS
+                .choose_multiple(&mut rng, arity)
+                .map(|&label| label.into())
+                .collect();
+            let body = random_expr(&mut rng, 4);
+            let command = Command::Update(Func::new("F".into(), params, body));
+
+            for style in [DisplayStyle::LazyK, DisplayStyle::Ecmascript] {
+                let printed = format_command(&command, &style);
+                assert_eq!(parse_line(&printed).as_ref(), Ok(&command), "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_source() {
+        let source = "```sxyz = ``xz`yz\n\n  TRUE =   ^x.^y.x  \n? s\n";
+
+        assert_eq!(
+            format_source(source, &DisplayStyle::LazyK),
+            Ok("```sxyz = ``xz`yz\n\nTRUE = ^x.^y.x\n? s\n".to_string())
+        );
+
+        assert_eq!(
+            format_source(source, &DisplayStyle::Ecmascript),
+            Ok("s(x, y, z) = x(z, y(z))\n\nTRUE = (x, y) => x\n? s\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_source_expectations() {
+        let source = "``k:a:b\n->  :a\nsteps:1\n";
+
+        assert_eq!(
+            format_source(source, &DisplayStyle::Ecmascript),
+            Ok("k(:a, :b)\n→ :a\nsteps: 1\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_source_idempotent() {
+        let source = "s(x, y, z) = x(z, y(z))\nFOO = ``s:X:Y\n! `FOO X0\n";
+
+        for style in [DisplayStyle::LazyK, DisplayStyle::Ecmascript] {
+            let once = format_source(source, &style).unwrap();
+            let twice = format_source(&once, &style).unwrap();
+            assert_eq!(once, twice);
+        }
+    }
+
+    #[test]
+    fn test_format_source_error() {
+        assert_eq!(
+            format_source("`ix = x\nf=g h=i\n", &DisplayStyle::LazyK)
+                .map_err(|e| e.starts_with("line 2:")),
+            Err(true)
+        );
+    }
+}
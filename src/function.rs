@@ -6,16 +6,39 @@ use crate::identifier::Ident;
 /// 定義済み関数を表現する
 ///
 /// 関数とラムダ抽象はよく似ているが、関数が 0 以上の arity を持つ点で異なる
+/// 仮引数にシンボルやシンボルの適用のパターンを書いた節を複数持つこともでき、定義した順に試す
 #[derive(Debug, Clone, PartialEq)]
 pub struct Func {
     name: Ident,
-    params: Vec<Ident>,
+
+    /// 1 つ以上の節、どの節も同じ個数の仮引数を持つ
+    clauses: Vec<Clause>,
+}
+
+/// 関数定義の節 `` `f`:SUCC n = body ``
+///
+/// 仮引数のパターンは、変数なら何にでも一致して束縛し、シンボルならそのシンボルだけに一致する
+/// 頭部がシンボルの適用なら、同じシンボルの適用に一致して引数どうしを照合する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    patterns: Vec<Expr>,
     body: Expr,
 }
 
 impl Func {
     pub fn new(name: Ident, params: Vec<Ident>, body: Expr) -> Func {
-        Func { name, params, body }
+        let patterns = params.into_iter().map(Expr::Variable).collect();
+        Func::with_patterns(name, patterns, body)
+    }
+
+    /// パターンを仮引数に持つ 1 つの節からなる関数
+    pub fn with_patterns(name: Ident, patterns: Vec<Expr>, body: Expr) -> Func {
+        Func::from_clauses(name, vec![Clause { patterns, body }])
+    }
+
+    pub fn from_clauses(name: Ident, clauses: Vec<Clause>) -> Func {
+        assert!(!clauses.is_empty());
+        Func { name, clauses }
     }
 
     pub fn name(&self) -> &Ident {
@@ -26,23 +49,176 @@ impl Func {
     ///
     /// 0 以上の整数値を返す
     pub fn arity(&self) -> usize {
-        self.params.len()
+        self.clauses[0].patterns.len()
     }
 
-    /// 関数に引数を与え評価した結果を返す
-    pub fn apply(&self, args: Vec<Expr>) -> Expr {
-        let mut body = self.body.clone();
-        for (param, arg) in self.params.iter().zip(args) {
-            body = body.substitute(param, &arg);
+    /// 関数に引数を与え評価した結果を返す、どの節にも一致しなければ None を返す
+    pub fn apply(&self, args: Vec<Expr>) -> Option<Expr> {
+        self.clauses.iter().find_map(|clause| clause.apply(&args))
+    }
+
+    pub fn clauses(&self) -> &[Clause] {
+        &self.clauses
+    }
+
+    /// 最初の節の本体、引数を取らない関数なら関数の値そのもの
+    pub fn body(&self) -> &Expr {
+        &self.clauses[0].body
+    }
+
+    /// 変数でないパターンを持つ節があれば真
+    ///
+    /// そのような関数は、引数を簡約してから一致する節を選ぶ
+    pub fn has_patterns(&self) -> bool {
+        self.clauses.iter().any(|clause| clause.params().is_none())
+    }
+
+    /// 節を加える、同じ形のパターンの節があればそれを置き換える
+    pub fn add_clauses(&mut self, func: Func) {
+        for clause in func.clauses {
+            let same = self.clauses.iter_mut().find(|c| {
+                c.patterns.len() == clause.patterns.len()
+                    && c.patterns
+                        .iter()
+                        .zip(&clause.patterns)
+                        .all(|(l, r)| same_shape(l, r))
+            });
+            match same {
+                Some(c) => *c = clause,
+                None => self.clauses.push(clause),
+            }
         }
-        body
+    }
+}
+
+impl Clause {
+    pub fn new(patterns: Vec<Expr>, body: Expr) -> Clause {
+        Clause { patterns, body }
     }
 
-    // pub fn params(&self) -> &Vec<Ident> {
-    //     &self.params
-    // }
+    pub fn patterns(&self) -> &[Expr] {
+        &self.patterns
+    }
 
     pub fn body(&self) -> &Expr {
         &self.body
     }
+
+    /// パターンがすべて変数なら、その識別子の列を返す
+    pub fn params(&self) -> Option<Vec<&Ident>> {
+        self.patterns
+            .iter()
+            .map(|p| match p {
+                Expr::Variable(id) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 引数がパターンに一致すれば、束縛した変数を本体に代入した結果を返す
+    fn apply(&self, args: &[Expr]) -> Option<Expr> {
+        let mut bindings = Vec::new();
+        for (pattern, arg) in self.patterns.iter().zip(args) {
+            if !matches(pattern, arg, &mut bindings) {
+                return None;
+            }
+        }
+
+        let mut body = self.body.clone();
+        for (param, arg) in bindings {
+            body = body.substitute(param, arg);
+        }
+        Some(body)
+    }
+}
+
+/// 式がパターンに一致すれば、パターンの変数と式の組を bindings に加えて真を返す
+///
+/// 同じ変数が 2 回現れるパターンは、どちらにも同じ式が来たときだけ一致する
+fn matches<'a>(
+    pattern: &'a Expr,
+    expr: &'a Expr,
+    bindings: &mut Vec<(&'a Ident, &'a Expr)>,
+) -> bool {
+    match (pattern, expr) {
+        (Expr::Variable(id), _) => match bindings.iter().find(|(bound, _)| *bound == id) {
+            Some((_, e)) => *e == expr,
+            None => {
+                bindings.push((id, expr));
+                true
+            }
+        },
+        (Expr::Symbol(l), Expr::Symbol(r)) => l == r,
+        (Expr::Apply { lhs: pl, rhs: pr }, Expr::Apply { lhs: el, rhs: er }) => {
+            matches(pl, el, bindings) && matches(pr, er, bindings)
+        }
+        _ => false,
+    }
+}
+
+/// 変数の名前を除いて同じ形のパターンなら真
+fn same_shape(l: &Expr, r: &Expr) -> bool {
+    match (l, r) {
+        (Expr::Variable(_), Expr::Variable(_)) => true,
+        (Expr::Symbol(l), Expr::Symbol(r)) => l == r,
+        (Expr::Apply { lhs: ll, rhs: lr }, Expr::Apply { lhs: rl, rhs: rr }) => {
+            same_shape(ll, rl) && same_shape(lr, rr)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `f:ZERO = :Z`、`` `f`:SUCC n = n ``
+    fn pred() -> Func {
+        let mut f = Func::with_patterns("f".into(), vec![":ZERO".into()], ":Z".into());
+        f.add_clauses(Func::with_patterns(
+            "f".into(),
+            vec![Expr::a(":SUCC".into(), "n".into())],
+            "n".into(),
+        ));
+        f
+    }
+
+    #[test]
+    fn test_apply_patterns() {
+        let f = pred();
+        assert!(f.has_patterns());
+        assert_eq!(f.apply(vec![":ZERO".into()]), Some(":Z".into()));
+        assert_eq!(
+            f.apply(vec![Expr::a(":SUCC".into(), ":ZERO".into())]),
+            Some(":ZERO".into())
+        );
+        assert_eq!(f.apply(vec![":ONE".into()]), None);
+        assert_eq!(f.apply(vec![Expr::a(":PRED".into(), ":ZERO".into())]), None);
+    }
+
+    #[test]
+    fn test_apply_non_linear() {
+        let eq = Func::with_patterns("EQ".into(), vec!["x".into(), "x".into()], ":T".into());
+        assert_eq!(eq.apply(vec![":a".into(), ":a".into()]), Some(":T".into()));
+        assert_eq!(eq.apply(vec![":a".into(), ":b".into()]), None);
+    }
+
+    #[test]
+    fn test_add_clauses() {
+        let mut f = pred();
+
+        // 同じ形のパターンの節は置き換え、そうでなければ末尾に加える
+        f.add_clauses(Func::with_patterns(
+            "f".into(),
+            vec![Expr::a(":SUCC".into(), "m".into())],
+            ":S".into(),
+        ));
+        f.add_clauses(Func::new("f".into(), vec!["x".into()], "x".into()));
+        assert_eq!(f.clauses().len(), 3);
+        assert_eq!(
+            f.apply(vec![Expr::a(":SUCC".into(), ":ZERO".into())]),
+            Some(":S".into())
+        );
+        assert_eq!(f.apply(vec![":ONE".into()]), Some(":ONE".into()));
+    }
 }
@@ -1,20 +1,28 @@
 pub mod ecmascript;
 pub mod lazy_k;
 
-use crate::function::Func;
+use crate::expression::Expr;
+use crate::function::{Clause, Func};
 use regex::Regex;
 use std::fmt::Display;
 
+/// 節ごとに 1 行ずつ印字する
 impl Display for Func {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut tokens = tokens(self);
-        write!(
-            f,
-            "{}{} = {}",
-            "`".to_string().repeat(self.arity()),
-            to_string(&mut tokens),
-            self.body()
-        )
+        for (n, clause) in self.clauses().iter().enumerate() {
+            if n > 0 {
+                writeln!(f)?;
+            }
+            let mut tokens = tokens(self, clause);
+            write!(
+                f,
+                "{}{} = {}",
+                "`".to_string().repeat(self.arity()),
+                to_string(&mut tokens),
+                clause.body()
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -22,6 +30,9 @@ impl Display for Func {
 enum Token<'a> {
     UpperIdent(&'a str),
     LowerIdent(&'a str),
+
+    /// 変数でないパターン
+    Pattern(String),
 }
 
 impl Display for Token<'_> {
@@ -29,29 +40,26 @@ impl Display for Token<'_> {
         match self {
             Token::UpperIdent(i) => write!(f, "{}", i),
             Token::LowerIdent(i) => write!(f, "{}", i),
+            Token::Pattern(p) => write!(f, "{}", p),
         }
     }
 }
 
-fn tokens<'a>(f: &'a Func) -> Vec<Token<'a>> {
-    let mut tokens = f
-        .params
-        .iter()
-        .map(|i| {
-            let label = i.label();
-            if is_upper_ident(label) {
-                Token::UpperIdent(label)
-            } else {
-                Token::LowerIdent(label)
-            }
-        })
-        .collect::<Vec<_>>();
-
-    let label = f.name.label();
-    if is_upper_ident(label) {
-        tokens.insert(0, Token::UpperIdent(label));
-    } else {
-        tokens.insert(0, Token::LowerIdent(label));
+fn tokens<'a>(f: &'a Func, clause: &'a Clause) -> Vec<Token<'a>> {
+    let ident = |label: &'a str| {
+        if is_upper_ident(label) {
+            Token::UpperIdent(label)
+        } else {
+            Token::LowerIdent(label)
+        }
+    };
+
+    let mut tokens = vec![ident(f.name.label())];
+    for pattern in clause.patterns() {
+        match pattern {
+            Expr::Variable(i) => tokens.push(ident(i.label())),
+            p => tokens.push(Token::Pattern(p.to_string())),
+        }
     }
 
     tokens
@@ -60,7 +68,7 @@ fn tokens<'a>(f: &'a Func) -> Vec<Token<'a>> {
 fn to_string(tokens: &mut Vec<Token>) -> String {
     tokens.reverse();
     let mut str = String::new();
-    while tokens.len() > 0 {
+    while !tokens.is_empty() {
         match tokens.len() {
             1 => {
                 let t = tokens.pop().unwrap();
@@ -71,13 +79,15 @@ fn to_string(tokens: &mut Vec<Token>) -> String {
                 let t1 = tokens.pop().unwrap();
                 let t2 = tokens.pop().unwrap();
 
-                match (t1, &t2) {
-                    (Token::UpperIdent(ident1), Token::UpperIdent(_)) => {
-                        str.push_str(&format!("{} ", ident1));
-                    }
-                    (t1, _) => {
-                        str.push_str(&format!("{}", t1));
-                    }
+                // 大文字の識別子が続くと 1 つの識別子として読まれるので空白で区切る
+                let (s1, s2) = (t1.to_string(), t2.to_string());
+                let upper = |c: Option<char>| {
+                    c.is_some_and(|c| c.is_uppercase() || c.is_ascii_digit() || c == '_')
+                };
+                if upper(s1.chars().last()) && upper(s2.chars().next()) {
+                    str.push_str(&format!("{} ", s1));
+                } else {
+                    str.push_str(&s1);
                 }
 
                 tokens.push(t2);
@@ -88,7 +98,7 @@ fn to_string(tokens: &mut Vec<Token>) -> String {
 }
 
 fn is_upper_ident(s: &str) -> bool {
-    let regex_upper_ident: Regex = Regex::new(r"^[A-Z_]+$").unwrap();
+    let regex_upper_ident: Regex = Regex::new(r"^[0-9A-Z_]+$").unwrap();
     regex_upper_ident.is_match(s)
 }
 
@@ -99,31 +109,52 @@ mod tests {
 
     #[test]
     fn test_to_string_1() {
-        let f = Func {
-            name: "f".into(),
-            params: vec!["x".into(), "y".into()],
-            body: Expr::a("x".into(), "y".into()),
-        };
+        let f = Func::new(
+            "f".into(),
+            vec!["x".into(), "y".into()],
+            Expr::a("x".into(), "y".into()),
+        );
         assert_eq!(f.to_string(), "``fxy = `xy");
     }
 
     #[test]
     fn test_to_string_2() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["X".into(), "Y".into()],
-            body: Expr::a("X".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["X".into(), "Y".into()],
+            Expr::a("X".into(), "Y".into()),
+        );
         assert_eq!(f.to_string(), "``F X Y = `X Y");
     }
 
     #[test]
     fn test_to_string_3() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["x".into(), "Y".into()],
-            body: Expr::a("x".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["x".into(), "Y".into()],
+            Expr::a("x".into(), "Y".into()),
+        );
         assert_eq!(f.to_string(), "``FxY = `xY");
     }
+
+    #[test]
+    fn test_to_string_4() {
+        let f = Func::new(
+            "F0".into(),
+            vec!["X1".into(), "42".into()],
+            Expr::a("X1".into(), "42".into()),
+        );
+        assert_eq!(f.to_string(), "``F0 X1 42 = `X1 42");
+    }
+
+    #[test]
+    fn test_to_string_patterns() {
+        let mut f = Func::with_patterns("F".into(), vec![":ZERO".into(), "X".into()], "X".into());
+        f.add_clauses(Func::with_patterns(
+            "F".into(),
+            vec![Expr::a(":SUCC".into(), "n".into()), "X".into()],
+            "n".into(),
+        ));
+        assert_eq!(f.to_string(), "``F:ZERO X = X\n``F`:SUCCnX = n");
+    }
 }
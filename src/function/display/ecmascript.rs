@@ -5,29 +5,36 @@ use std::fmt::Display;
 #[derive(PartialEq, Debug)]
 pub struct ECMAScriptStyle<'a>(pub &'a Func);
 
+/// 節ごとに 1 行ずつ印字する
 impl Display for ECMAScriptStyle<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0.arity() == 0 {
-            write!(
-                f,
-                "{} = {}",
-                self.0.name.label(),
-                ExprECMAScriptStyle(&self.0.body)
-            )
-        } else {
-            write!(
-                f,
-                "{}({}) = {}",
-                self.0.name.label(),
-                self.0
-                    .params
-                    .iter()
-                    .map(|i| i.label())
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                ExprECMAScriptStyle(&self.0.body)
-            )
+        for (n, clause) in self.0.clauses().iter().enumerate() {
+            if n > 0 {
+                writeln!(f)?;
+            }
+            if self.0.arity() == 0 {
+                write!(
+                    f,
+                    "{} = {}",
+                    self.0.name.label(),
+                    ExprECMAScriptStyle(clause.body())
+                )?;
+            } else {
+                write!(
+                    f,
+                    "{}({}) = {}",
+                    self.0.name.label(),
+                    clause
+                        .patterns()
+                        .iter()
+                        .map(|p| ExprECMAScriptStyle(p).to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    ExprECMAScriptStyle(clause.body())
+                )?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -38,41 +45,51 @@ mod tests {
 
     #[test]
     fn test_to_string_0() {
-        let f = Func {
-            name: "TRUE".into(),
-            params: vec![],
-            body: "k".into(),
-        };
+        let f = Func::new("TRUE".into(), vec![], "k".into());
         assert_eq!(ECMAScriptStyle(&f).to_string(), "TRUE = k");
     }
 
     #[test]
     fn test_to_string_1() {
-        let f = Func {
-            name: "f".into(),
-            params: vec!["x".into(), "y".into()],
-            body: Expr::a("x".into(), "y".into()),
-        };
+        let f = Func::new(
+            "f".into(),
+            vec!["x".into(), "y".into()],
+            Expr::a("x".into(), "y".into()),
+        );
         assert_eq!(ECMAScriptStyle(&f).to_string(), "f(x, y) = x(y)");
     }
 
     #[test]
     fn test_to_string_2() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["X".into(), "Y".into()],
-            body: Expr::a("X".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["X".into(), "Y".into()],
+            Expr::a("X".into(), "Y".into()),
+        );
         assert_eq!(ECMAScriptStyle(&f).to_string(), "F(X, Y) = X(Y)");
     }
 
     #[test]
     fn test_to_string_3() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["x".into(), "Y".into()],
-            body: Expr::a("x".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["x".into(), "Y".into()],
+            Expr::a("x".into(), "Y".into()),
+        );
         assert_eq!(ECMAScriptStyle(&f).to_string(), "F(x, Y) = x(Y)");
     }
+
+    #[test]
+    fn test_to_string_patterns() {
+        let mut f = Func::with_patterns("f".into(), vec![":ZERO".into()], ":Z".into());
+        f.add_clauses(Func::with_patterns(
+            "f".into(),
+            vec![Expr::a(":SUCC".into(), "n".into())],
+            "n".into(),
+        ));
+        assert_eq!(
+            ECMAScriptStyle(&f).to_string(),
+            "f(:ZERO) = :Z\nf(:SUCC(n)) = n"
+        );
+    }
 }
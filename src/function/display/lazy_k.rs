@@ -17,31 +17,31 @@ mod tests {
 
     #[test]
     fn test_to_string_1() {
-        let f = Func {
-            name: "f".into(),
-            params: vec!["x".into(), "y".into()],
-            body: Expr::a("x".into(), "y".into()),
-        };
+        let f = Func::new(
+            "f".into(),
+            vec!["x".into(), "y".into()],
+            Expr::a("x".into(), "y".into()),
+        );
         assert_eq!(LazyKStyle(&f).to_string(), "``fxy = `xy");
     }
 
     #[test]
     fn test_to_string_2() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["X".into(), "Y".into()],
-            body: Expr::a("X".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["X".into(), "Y".into()],
+            Expr::a("X".into(), "Y".into()),
+        );
         assert_eq!(LazyKStyle(&f).to_string(), "``F X Y = `X Y");
     }
 
     #[test]
     fn test_to_string_3() {
-        let f = Func {
-            name: "F".into(),
-            params: vec!["x".into(), "Y".into()],
-            body: Expr::a("x".into(), "Y".into()),
-        };
+        let f = Func::new(
+            "F".into(),
+            vec!["x".into(), "Y".into()],
+            Expr::a("x".into(), "Y".into()),
+        );
         assert_eq!(LazyKStyle(&f).to_string(), "``FxY = `xY");
     }
 }
@@ -1,19 +1,23 @@
-extern crate glob;
-use crate::command::display::ecmascript::ECMAScriptStyle;
-use crate::command::display::lazy_k::LazyKStyle;
 use crate::command::Command;
-use crate::config::{display_style, DisplayStyle};
 use crate::context::Context;
-use crate::parser::command::ecmascript::parse_command as parse_ecmascript_style_command;
-use crate::parser::command::lazy_k::parse_command as parse_lazy_k_style_command;
+use crate::format::parse_line;
+use crate::function::Func;
+use crate::identifier::Ident;
+use crate::runner::parse_expectation;
+#[cfg(feature = "cli")]
 use glob::glob;
+#[cfg(feature = "cli")]
 use home_dir::*;
+#[cfg(feature = "cli")]
 use std::fs::File;
 #[allow(unused_imports)]
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
+#[cfg(feature = "cli")]
 use ulid::Ulid;
 
+#[cfg(feature = "cli")]
+#[allow(clippy::ineffective_open_options)]
 pub fn open_or_create_history_file() -> File {
     let dir = Path::new("~/.tuber")
         .expand_home()
@@ -24,7 +28,7 @@ pub fn open_or_create_history_file() -> File {
         .map(|e| e.unwrap())
         .collect::<Vec<_>>();
 
-    return match file_names.iter().max() {
+    match file_names.iter().max() {
         Some(f) => std::fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -39,50 +43,397 @@ pub fn open_or_create_history_file() -> File {
             let filename = format!("{}.txt", Ulid::new().to_string());
             File::create(dir.join(filename)).expect("ファイルの作成に失敗しました")
         }
-    };
+    }
+}
+
+pub fn rebuild_context(reader: impl Read, context: Option<Context>) -> Context {
+    rebuild_timeline(reader, context).into_context()
 }
 
-pub fn rebuild_context(file: &File, context: Option<Context>) -> Context {
-    let mut context = context.unwrap_or(Context::default());
+/// 履歴を先頭から再生して、定義の変更の履歴を組み立てる
+pub fn rebuild_timeline(reader: impl Read, context: Option<Context>) -> Timeline {
+    let mut timeline = Timeline::new(context.unwrap_or_default());
 
-    for line in std::io::BufReader::new(file).lines() {
+    for line in std::io::BufReader::new(reader).lines() {
         let line = line.unwrap();
         if line.trim().is_empty() {
             continue;
         }
-        let command =
-            parse_lazy_k_style_command(&line).or_else(|_err| parse_ecmascript_style_command(&line));
+        if let Ok(command) = parse_line(&line) {
+            timeline.apply(command);
+        }
+    }
+
+    timeline
+}
+
+/// 定義の変更
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// 定義または削除のコマンド
+    pub command: Command,
+
+    /// 変更した関数の名前と変更する前の定義の組、未定義だったなら None
+    ///
+    /// データ型の宣言は複数の関数をまとめて定義するので、宣言した順に並べる
+    pub before: Vec<(Ident, Option<Func>)>,
+}
+
+impl Change {
+    /// 変更した関数の名前、データ型の宣言なら型の名前
+    pub fn name(&self) -> &Ident {
+        match &self.command {
+            Command::Update(f) => f.name(),
+            Command::Del(i) => i,
+            Command::Data(d) => &d.name,
+            _ => unreachable!("not a change"),
+        }
+    }
+}
+
+/// 定義の変更を記録し、取り消しとやり直しができる Context
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    context: Context,
+
+    /// 適用した変更、古い順
+    done: Vec<Change>,
+
+    /// 取り消した変更、最後に取り消したものが末尾になる
+    undone: Vec<Change>,
+}
+
+impl Timeline {
+    pub fn new(context: Context) -> Self {
+        Timeline {
+            context,
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    pub fn into_context(self) -> Context {
+        self.context
+    }
+
+    pub fn done(&self) -> &[Change] {
+        &self.done
+    }
+
+    pub fn undone(&self) -> &[Change] {
+        &self.undone
+    }
+
+    /// 定義・削除・取り消し・やり直しのコマンドを適用する、それ以外のコマンドは無視する
+    pub fn apply(&mut self, command: Command) {
         match command {
-            Ok(Command::Update(f)) => context.def(f.clone()),
-            Ok(Command::Del(i)) => context.del(&i),
+            Command::Update(_) | Command::Del(_) | Command::Data(_) => {
+                let change = self.change(command);
+                self.done.push(change);
+                self.undone.clear();
+            }
+            Command::Undo(n) => {
+                self.undo(n);
+            }
+            Command::Redo(n) => {
+                self.redo(n);
+            }
             _ => (),
         }
     }
 
-    context
+    /// 定義ファイルの定義や削除を履歴に記録せずに適用する、取り消しの対象にならない
+    ///
+    /// パースできない行があれば、その行番号を含むエラーを返す
+    pub fn overlay(&mut self, path: &Path) -> Result<(), String> {
+        load_definitions(&mut self.context, path)
+    }
+
+    /// 直近の n 個の変更を取り消し、取り消した個数を返す
+    ///
+    /// 取り消した変更は undone の末尾に、新しいものから順に並ぶ
+    pub fn undo(&mut self, n: usize) -> usize {
+        let n = n.min(self.done.len());
+        for _ in 0..n {
+            let change = self.done.pop().unwrap();
+            for (name, func) in change.before.iter().rev() {
+                self.restore(name.clone(), func.clone());
+            }
+            self.undone.push(change);
+        }
+        n
+    }
+
+    /// 直近に取り消した n 個の変更をやり直し、やり直した個数を返す
+    ///
+    /// やり直した変更は done の末尾に、古いものから順に並ぶ
+    pub fn redo(&mut self, n: usize) -> usize {
+        let n = n.min(self.undone.len());
+        for _ in 0..n {
+            let change = self.undone.pop().unwrap();
+            let change = self.change(change.command);
+            self.done.push(change);
+        }
+        n
+    }
+
+    /// 直近の n 個の変更の前後で定義が異なる関数について、変更前と変更後の定義を名前順に返す
+    pub fn diff(&self, n: usize) -> Vec<(Ident, Option<Func>, Option<Func>)> {
+        let mut before = self.context.clone();
+        let mut names = Vec::new();
+        for change in self.done.iter().rev().take(n) {
+            for (name, func) in change.before.iter().rev() {
+                match func {
+                    Some(f) => before.def(f.clone()),
+                    None => before.del(name),
+                }
+                names.push(name.clone());
+            }
+        }
+        names.sort_by(|l, r| l.label().cmp(r.label()));
+        names.dedup();
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let old = before.get(&name).cloned();
+                let new = self.context.get(&name).cloned();
+                (old != new).then_some((name, old, new))
+            })
+            .collect()
+    }
+
+    /// 定義または削除を適用し、変更前の定義とともに返す
+    fn change(&mut self, command: Command) -> Change {
+        let before = match &command {
+            Command::Update(f) => {
+                let before = self.context.get(f.name()).cloned();
+                self.context.update(f.clone());
+                vec![(f.name().clone(), before)]
+            }
+            Command::Del(i) => {
+                let before = self.context.get(i).cloned();
+                self.context.del(i);
+                vec![(i.clone(), before)]
+            }
+            Command::Data(d) => d
+                .funcs()
+                .into_iter()
+                .map(|f| {
+                    let before = self.context.get(f.name()).cloned();
+                    let name = f.name().clone();
+                    self.context.def(f);
+                    (name, before)
+                })
+                .collect(),
+            _ => unreachable!("not a change"),
+        };
+
+        Change { command, before }
+    }
+
+    fn restore(&mut self, name: Ident, func: Option<Func>) {
+        match func {
+            Some(f) => self.context.def(f),
+            None => self.context.del(&name),
+        }
+    }
+}
+
+/// 定義ファイルを読み込んで Context を作る、組み込みの定義の代わりに使う
+///
+/// 定義以外の行は無視する、パースできない行があればその行番号とともにエラーを返す
+pub fn load_prelude(path: &Path) -> Result<Context, String> {
+    let mut context = Context::new();
+    load_definitions(&mut context, path)?;
+    Ok(context)
 }
 
-pub struct Logger<W: Write>(W, DisplayStyle);
+/// 定義ファイルの定義や削除を Context に適用する、空行と期待値の注釈は読み飛ばす
+fn load_definitions(context: &mut Context, path: &Path) -> Result<(), String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    for (n, line) in source.lines().enumerate() {
+        if line.trim().is_empty() || parse_expectation(line).is_some() {
+            continue;
+        }
+        match parse_line(line) {
+            Ok(Command::Update(f)) => context.update(f),
+            Ok(Command::Del(i)) => context.del(&i),
+            Ok(Command::Data(d)) => d.funcs().into_iter().for_each(|f| context.def(f)),
+            Ok(_) => (),
+            Err(e) => return Err(format!("{}: line {}: {}", path.display(), n + 1, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// コマンドを履歴に書き込む
+pub struct Logger<W: Write>(W);
 
 impl<W: Write> Logger<W> {
     pub fn new(writer: W) -> Self {
-        Logger(writer, display_style())
+        Logger(writer)
     }
 
     pub fn push(&mut self, command: &Command) {
-        match &self.1 {
-            DisplayStyle::LazyK => println!("{}", LazyKStyle(command)),
-            DisplayStyle::Ecmascript => println!("{}", ECMAScriptStyle(command)),
-        }
         writeln!(self.0, "{}", command).expect("ログの書き込みに失敗しました");
     }
 }
 
+/// メモリ上に保持する履歴、ファイルシステムを使えない環境で Logger と rebuild_context の代わりに使う
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryHistory(Vec<String>);
+
+impl MemoryHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, command: &Command) {
+        self.0.push(command.to_string());
+    }
+
+    /// 履歴に書き込んだコマンドを古い順に返す
+    pub fn lines(&self) -> &[String] {
+        &self.0
+    }
+
+    /// 履歴の定義と削除を context に順に適用する
+    pub fn rebuild_context(&self, context: Option<Context>) -> Context {
+        rebuild_context(self.0.join("\n").as_bytes(), context)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::command::Command;
-    use crate::function::Func;
+    use ulid::Ulid;
+
+    #[test]
+    fn test_load_prelude() {
+        let path = std::env::temp_dir().join(format!("tuber-prelude-{}.txt", Ulid::new()));
+        std::fs::write(&path, "`ix = x\n\nTRUE = k\n`i:a\n").unwrap();
+
+        let context = load_prelude(&path).unwrap();
+        assert_eq!(context.count(), 2);
+        assert_eq!(context.arity(&"i".into()), Some(1));
+
+        std::fs::write(&path, "`ix = x\n`ix = )\n").unwrap();
+        assert!(load_prelude(&path).unwrap_err().contains(": line 2: "));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_overlay() {
+        let path = std::env::temp_dir().join(format!("tuber-context-{}.txt", Ulid::new()));
+        std::fs::write(&path, "`ix = :a\n\nT = k\n").unwrap();
+
+        let mut timeline = rebuild_timeline("`ix = x\n".as_bytes(), Some(Context::new()));
+        timeline.overlay(&path).unwrap();
+        assert_eq!(
+            timeline.context().get(&"i".into()).unwrap().body(),
+            &":a".into()
+        );
+        assert_eq!(timeline.done().len(), 1);
+
+        std::fs::write(&path, "`ix = x\n`ix = )\n").unwrap();
+        let err = timeline.overlay(&path).unwrap_err();
+        assert!(err.starts_with(&format!("{}: line 2: ", path.display())));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_timeline() {
+        let log = "`ix = x\n`ix = :a\nT = k\n:undo\n:undo\n:redo\nT = T\n";
+        let timeline = rebuild_timeline(log.as_bytes(), Some(Context::new()));
+
+        assert_eq!(
+            timeline.context().get(&"i".into()).unwrap().body(),
+            &":a".into()
+        );
+        assert_eq!(timeline.context().get(&"T".into()), None);
+        assert_eq!(timeline.done().len(), 3);
+        assert!(timeline.undone().is_empty());
+
+        let mut timeline = timeline;
+        assert_eq!(timeline.undo(5), 3);
+        assert_eq!(timeline.context().count(), 0);
+        assert_eq!(timeline.redo(1), 1);
+        assert_eq!(
+            timeline.context().get(&"i".into()).unwrap().body(),
+            &"x".into()
+        );
+    }
+
+    #[test]
+    fn test_timeline_diff() {
+        let log = "`ix = x\nT = k\n`ix = :a\n";
+        let timeline = rebuild_timeline(log.as_bytes(), Some(Context::new()));
+
+        let i = |body: &str| Some(Func::new("i".into(), vec!["x".into()], body.into()));
+        assert_eq!(timeline.diff(1), vec![("i".into(), i("x"), i(":a"))]);
+        assert_eq!(
+            timeline.diff(2),
+            vec![
+                (
+                    "T".into(),
+                    None,
+                    Some(Func::new("T".into(), vec![], "k".into()))
+                ),
+                ("i".into(), i("x"), i(":a")),
+            ]
+        );
+        assert_eq!(timeline.diff(3)[1], ("i".into(), None, i(":a")));
+    }
+
+    #[test]
+    fn test_timeline_data() {
+        let log = "A = :a\ndata OPTION = NONE | SOME x\n";
+        let mut timeline = rebuild_timeline(log.as_bytes(), Some(Context::new()));
+        assert_eq!(timeline.context().count(), 4);
+        assert_eq!(timeline.done().len(), 2);
+        assert_eq!(timeline.done()[1].name(), &"OPTION".into());
+
+        // 宣言で定義した関数はまとめて取り消す
+        assert_eq!(timeline.undo(1), 1);
+        assert_eq!(timeline.context().count(), 1);
+        assert_eq!(timeline.redo(1), 1);
+        assert_eq!(
+            timeline
+                .diff(1)
+                .into_iter()
+                .map(|(name, _, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["CASE_OPTION".into(), "NONE".into(), "SOME".into()]
+        );
+    }
+
+    #[test]
+    fn test_memory_history() {
+        let mut history = MemoryHistory::new();
+        history.push(&Command::Update(Func::new(
+            "i".into(),
+            vec!["x".into()],
+            "x".into(),
+        )));
+        history.push(&Command::Info("i".into()));
+        history.push(&Command::Update(Func::new("T".into(), vec![], "k".into())));
+        history.push(&Command::Del("T".into()));
+        assert_eq!(history.lines(), ["`ix = x", "? i", "T = k", "T = T"]);
+
+        let context = history.rebuild_context(Some(Context::new()));
+        assert_eq!(context.count(), 1);
+        assert_eq!(context.arity(&"i".into()), Some(1));
+    }
 
     #[test]
     fn test_logger() {
@@ -1,12 +1,27 @@
 use std::collections::HashSet;
 
 /// ラムダ式や関数定義における識別子を表現する
+///
+/// 識別子は 1 文字の小文字 (`λ` を除く)、または大文字・数字・`_` からなる列に限る
+/// どちらの表示スタイルでも、印字した識別子がそのままパースし直せるのはこの形だけなので、
+/// `foo` のような小文字の列は作る時点で拒む
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Ident(String);
 
 impl Ident {
+    /// 識別子を作る、識別子の形でなければ panic する
+    ///
+    /// 利用者の入力から作る場合は `str::parse` を使う
     pub fn new(s: &str) -> Ident {
-        Ident(String::from(s))
+        match s.parse() {
+            Ok(ident) => ident,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// 値を取り出した後に残しておく空の識別子、印字もパースもされない
+    pub(crate) fn empty() -> Ident {
+        Ident(String::new())
     }
 
     pub fn new_name(&self, vars: &HashSet<Ident>) -> Ident {
@@ -29,6 +44,27 @@ impl Ident {
     }
 }
 
+impl std::str::FromStr for Ident {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        let valid = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_lowercase() => c != 'λ',
+            (Some(_), _) => s
+                .chars()
+                .all(|c| c.is_uppercase() || c.is_ascii_digit() || c == '_'),
+            (None, _) => false,
+        };
+
+        if valid {
+            Ok(Ident(String::from(s)))
+        } else {
+            Err(format!("invalid identifier: {}", s))
+        }
+    }
+}
+
 impl From<&str> for Ident {
     fn from(s: &str) -> Self {
         Ident::new(s)
@@ -47,6 +83,27 @@ impl AsRef<str> for Ident {
     }
 }
 
+#[test]
+fn test_parse() {
+    assert_eq!("x".parse(), Ok(Ident::new("x")));
+    assert_eq!("FOO".parse(), Ok(Ident::new("FOO")));
+    assert_eq!("42".parse(), Ok(Ident::new("42")));
+    assert_eq!("A_0".parse(), Ok(Ident::new("A_0")));
+
+    // 小文字の列は Lazy K 風の記法では変数の並びと区別できない
+    assert!("foo".parse::<Ident>().is_err());
+    assert!("Foo".parse::<Ident>().is_err());
+    assert!("x0".parse::<Ident>().is_err());
+    assert!("λ".parse::<Ident>().is_err());
+    assert!("".parse::<Ident>().is_err());
+}
+
+#[test]
+#[should_panic(expected = "invalid identifier: foo")]
+fn test_new_rejects_unparsable() {
+    Ident::new("foo");
+}
+
 #[test]
 fn test_new_name() {
     let mut set: HashSet<Ident> = HashSet::new();
@@ -1,8 +1,71 @@
+//! SKI コンビネータ計算とラムダ計算のインタプリタ
+//!
+//! コマンドをパースし、定義済み関数の Context のもとで式を 1 ステップずつ簡約し、
+//! 結果を Lazy K スタイルか ECMAScript スタイルで印字する
+//!
+//! ```
+//! use tuber::{format_expr, parse_command, Command, Context, DisplayStyle, EvalSteps};
+//!
+//! let context = Context::default();
+//! let Ok(Command::Eval(expr)) = parse_command("```skk:a", &DisplayStyle::LazyK) else {
+//!     unreachable!()
+//! };
+//!
+//! let steps = EvalSteps::new(expr, &context)
+//!     .map(|e| format_expr(&e, &DisplayStyle::LazyK))
+//!     .collect::<Vec<_>>();
+//! assert_eq!(steps, ["``k:a`k:a", ":a"]);
+//! ```
+//!
+//! コマンドの実行結果を表示するには `Engine` に `EventSink` を渡す
+//!
+//! ```
+//! use tuber::{parse_line, Context, Engine, TextOutput};
+//!
+//! let context = Context::default();
+//! let mut output = TextOutput::new(Vec::new(), &context);
+//! let mut engine = Engine::new(context);
+//!
+//! engine.run(parse_line("`ix = x").unwrap(), &mut output);
+//! engine.run(parse_line("`i:a").unwrap(), &mut output);
+//! assert_eq!(
+//!     String::from_utf8(output.into_inner()).unwrap(),
+//!     "`ix = x\n`i:a\n→ :a\n"
+//! );
+//! ```
+
+pub mod builtin;
 pub mod command;
 pub mod config;
 pub mod context;
+pub mod data;
+pub mod debugger;
+pub mod engine;
 pub mod evaluate;
 pub mod expression;
+pub mod format;
 pub mod function;
+pub mod history;
 pub mod identifier;
+pub mod limit;
+pub mod lsp;
 pub mod parser;
+pub mod primitive;
+pub mod runner;
+pub mod stats;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use builtin::Builtin;
+pub use command::Command;
+pub use config::DisplayStyle;
+pub use context::Context;
+pub use engine::{
+    Assertion, Engine, EngineEvent, EventSink, JsonOutput, ReduceKind, Reduction, Status,
+    TextOutput,
+};
+pub use evaluate::{EvalStep, EvalSteps, Redex};
+pub use expression::Expr;
+pub use format::{format_command, format_expr, parse_command, parse_line};
+pub use function::{Clause, Func};
+pub use identifier::Ident;
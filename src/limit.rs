@@ -0,0 +1,170 @@
+//! 簡約中の式の大きさ・深さ・経過時間の上限
+//!
+//! ステップ数の上限だけでは、式が指数的に大きくなる簡約でメモリを使い果たしたり、
+//! 深すぎる式を再帰的に辿ってスタックが溢れたりするのを防げない
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use crate::config::{depth_limit, size_limit, time_limit};
+use crate::expression::Expr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    /// 式のノード数が上限を超えた
+    Size { size: usize, limit: usize },
+
+    /// 式の深さが上限を超えた
+    Depth { depth: usize, limit: usize },
+
+    /// 経過時間が上限を超えた
+    Time { elapsed: Duration, limit: Duration },
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Size { size, limit } => {
+                write!(f, "term size limit exceeded: {} nodes > {}", size, limit)
+            }
+            LimitExceeded::Depth { depth, limit } => {
+                write!(f, "term depth limit exceeded: {} > {}", depth, limit)
+            }
+            LimitExceeded::Time { elapsed, limit } => {
+                write!(f, "time limit exceeded: {:?} > {:?}", elapsed, limit)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub size: usize,
+    pub depth: usize,
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    pub fn from_config() -> Self {
+        Limits {
+            size: size_limit(),
+            depth: depth_limit(),
+            time: time_limit(),
+        }
+    }
+
+    /// 計測を始める
+    pub fn start(&self) -> Watchdog {
+        Watchdog {
+            limits: *self,
+            // wasm32-unknown-unknown では Instant が使えないので、時間の上限が無ければ計測しない
+            start: self.time.map(|_| Instant::now()),
+        }
+    }
+}
+
+/// 簡約を始めてからの経過時間と各ステップの式を見張る
+pub struct Watchdog {
+    limits: Limits,
+    start: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn check(&self, expr: &Expr) -> Result<(), LimitExceeded> {
+        let size = expr.size();
+        if size > self.limits.size {
+            return Err(LimitExceeded::Size {
+                size,
+                limit: self.limits.size,
+            });
+        }
+
+        let depth = expr.depth();
+        if depth > self.limits.depth {
+            return Err(LimitExceeded::Depth {
+                depth,
+                limit: self.limits.depth,
+            });
+        }
+
+        if let (Some(limit), Some(start)) = (self.limits.time, self.start) {
+            let elapsed = start.elapsed();
+            if elapsed > limit {
+                return Err(LimitExceeded::Time { elapsed, limit });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::evaluate::EvalSteps;
+
+    fn limits(size: usize, depth: usize) -> Limits {
+        Limits {
+            size,
+            depth,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let watchdog = limits(3, 100).start();
+
+        assert_eq!(watchdog.check(&Expr::a(":a".into(), ":b".into())), Ok(()));
+        assert_eq!(
+            watchdog.check(&Expr::a(Expr::a(":a".into(), ":b".into()), ":c".into())),
+            Err(LimitExceeded::Size { size: 5, limit: 3 })
+        );
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let watchdog = limits(100, 2).start();
+
+        assert_eq!(
+            watchdog.check(&Expr::l("x".into(), Expr::l("y".into(), "x".into()))),
+            Err(LimitExceeded::Depth { depth: 3, limit: 2 })
+        );
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let watchdog = Limits {
+            time: Some(Duration::ZERO),
+            ..limits(100, 100)
+        }
+        .start();
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert!(matches!(
+            watchdog.check(&":a".into()),
+            Err(LimitExceeded::Time { .. })
+        ));
+    }
+
+    #[test]
+    fn test_explosion() {
+        // `^x.``xxx^x.``xxx は簡約のたびに大きくなる
+        let xxx = || {
+            Expr::l(
+                "x".into(),
+                Expr::a(Expr::a("x".into(), "x".into()), "x".into()),
+            )
+        };
+        let expr = Expr::a(xxx(), xxx());
+
+        let context = Context::new();
+        let watchdog = limits(50, 1000).start();
+        let result = EvalSteps::new(expr, &context)
+            .take(1000)
+            .try_for_each(|e| watchdog.check(&e));
+
+        assert!(matches!(result, Err(LimitExceeded::Size { limit: 50, .. })));
+    }
+}
@@ -0,0 +1,737 @@
+//! 定義ファイルのための Language Server Protocol サーバ
+//!
+//! 標準入出力で JSON-RPC のメッセージをやり取りする
+//! 開いている文書は 1 行 1 コマンドの定義ファイルとして扱い、
+//! 組み込みの定義に開いている文書の定義を重ねた Context をもとに補完や定義元への移動を行う
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::command::Command;
+use crate::config::DisplayStyle;
+use crate::context::Context;
+use crate::engine::{normalize, Status};
+use crate::expression::Expr;
+use crate::format::{format_command, parse_command, parse_line};
+use crate::function::{Clause, Func};
+use crate::identifier::Ident;
+use crate::limit::Limits;
+use crate::runner::parse_expectation;
+
+/// メソッドが存在しないことを表す JSON-RPC のエラーコード
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// コードアクションの題名
+const UNLAMBDA: &str = "Unlambda this expression";
+const NORMALIZE: &str = "Evaluate to normal form";
+
+/// 標準入出力でサーバを動かす、exit 通知を受け取るか入力が終わったら戻る
+pub fn serve(input: impl BufRead, mut output: impl Write, context: Context) -> io::Result<()> {
+    let mut server = Server::new(context);
+    let mut input = input;
+
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Content-Length ヘッダの付いたメッセージを 1 つ読む
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// 開いている文書を保持してリクエストに答える
+pub struct Server {
+    /// 組み込みの定義、文書の定義はこれに重ねる
+    base: Context,
+
+    /// URI ごとの文書の内容
+    documents: BTreeMap<String, String>,
+
+    /// クライアントがコードアクションの編集内容を codeAction/resolve で後から求められるなら真
+    resolve_edits: bool,
+
+    /// 正規形を求めるときの式の大きさ・深さ・時間の上限
+    limits: Limits,
+
+    exited: bool,
+}
+
+/// 関数が定義されている場所
+struct Location {
+    uri: String,
+    line: usize,
+    length: usize,
+}
+
+impl Server {
+    pub fn new(base: Context) -> Self {
+        Server {
+            base,
+            documents: BTreeMap::new(),
+            resolve_edits: false,
+            limits: Limits::from_config(),
+            exited: false,
+        }
+    }
+
+    /// メッセージを 1 つ処理し、送り返すレスポンスと通知を返す
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+
+        let result = match method {
+            "initialize" => {
+                self.resolve_edits = params["capabilities"]["textDocument"]["codeAction"]
+                    ["resolveSupport"]["properties"]
+                    .as_array()
+                    .is_some_and(|properties| properties.contains(&json!("edit")));
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "completionProvider": {},
+                        "codeActionProvider": { "resolveProvider": true },
+                    },
+                    "serverInfo": { "name": "tuber" },
+                })
+            }
+            "shutdown" => Value::Null,
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/completion" => self.completion(),
+            "textDocument/codeAction" => self.code_actions(params),
+            "codeAction/resolve" => self.resolve_code_action(params),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method: {}", method),
+                    },
+                })];
+            }
+        };
+
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![self.diagnostics(&uri)]
+            }
+
+            // 同期の方法に Full を指定しているので、最後の変更が文書全体になる
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                vec![self.diagnostics(&uri)]
+            }
+
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+
+            "exit" => {
+                self.exited = true;
+                vec![]
+            }
+
+            _ => vec![],
+        }
+    }
+
+    /// 組み込みの定義に、開いている文書の定義を URI の順に重ねる
+    fn index(&self) -> (Context, HashMap<Ident, Location>) {
+        let mut context = self.base.clone();
+        let mut locations = HashMap::new();
+
+        for (uri, text) in &self.documents {
+            for (n, line) in text.lines().enumerate() {
+                match parse_line(line) {
+                    Ok(Command::Update(f)) => {
+                        locations.insert(
+                            f.name().clone(),
+                            Location {
+                                uri: uri.clone(),
+                                line: n,
+                                length: utf16_len(line),
+                            },
+                        );
+                        context.update(f);
+                    }
+                    Ok(Command::Del(i)) => {
+                        locations.remove(&i);
+                        context.del(&i);
+                    }
+                    Ok(Command::Data(d)) => {
+                        for f in d.funcs() {
+                            locations.insert(
+                                f.name().clone(),
+                                Location {
+                                    uri: uri.clone(),
+                                    line: n,
+                                    length: utf16_len(line),
+                                },
+                            );
+                            context.def(f);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        (context, locations)
+    }
+
+    /// パースできない行をエラーとして報告する
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map(String::as_str).unwrap_or("");
+
+        let diagnostics = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(n, line)| {
+                let err = match parse_expectation(line) {
+                    Some(expectation) => expectation.err()?,
+                    None => parse_line(line).err()?,
+                };
+                Some(json!({
+                    "range": range(n, 0, n, utf16_len(line)),
+                    "severity": 1,
+                    "source": "tuber",
+                    "message": parse_error_message(&err),
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some(name) = self.identifier_at(params) else {
+            return Value::Null;
+        };
+        let (context, _) = self.index();
+        let Some(f) = context.get(&name) else {
+            return Value::Null;
+        };
+
+        let style = self.document_style(params);
+        json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!(
+                    "```\n{}\n```\narity: {}",
+                    format_command(&Command::Update(f.clone()), &style),
+                    f.arity()
+                ),
+            },
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some(name) = self.identifier_at(params) else {
+            return Value::Null;
+        };
+        let (_, locations) = self.index();
+
+        match locations.get(&name) {
+            Some(location) => json!({
+                "uri": location.uri,
+                "range": range(location.line, 0, location.line, location.length),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn completion(&self) -> Value {
+        let (context, _) = self.index();
+
+        let mut functions = Vec::new();
+        context.for_each(|_, f| functions.push(f));
+        functions.sort_by(|l, r| l.name().label().cmp(r.name().label()));
+
+        json!(functions
+            .into_iter()
+            .map(|f| json!({
+                "label": f.name().label(),
+                "kind": 3,
+                "detail": f.to_string(),
+            }))
+            .collect::<Vec<_>>())
+    }
+
+    /// 式や定義の本体を、ラムダ抽象を除去した式や正規形に書き換える
+    ///
+    /// 正規形を求めるのは時間がかかるので、対応しているクライアントには
+    /// アクションが選ばれてから codeAction/resolve で編集内容を求める
+    fn code_actions(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let Some(n) = params["range"]["start"]["line"].as_u64() else {
+            return json!([]);
+        };
+        let n = n as usize;
+
+        let mut actions = Vec::new();
+        let unlambda = |e: &Expr| Some(e.clone().unlambda());
+        if let Some(edit) = self.rewrite_line(uri, n, &unlambda) {
+            actions.push(code_action(UNLAMBDA, edit));
+        }
+
+        if self.resolve_edits {
+            // 書き換える式のある行にだけ出す
+            let identity = |e: &Expr| Some(e.clone());
+            if self
+                .line_command(uri, n)
+                .is_some_and(|c| rewrite(&c, &identity).is_some())
+            {
+                actions.push(json!({
+                    "title": NORMALIZE,
+                    "kind": "refactor.rewrite",
+                    "data": { "uri": uri, "line": n },
+                }));
+            }
+        } else if let Some(edit) = self.normalize_line(uri, n) {
+            actions.push(code_action(NORMALIZE, edit));
+        }
+
+        json!(actions)
+    }
+
+    /// 後回しにしたコードアクションの編集内容を求める、求められなければ編集内容を付けずに返す
+    fn resolve_code_action(&self, action: &Value) -> Value {
+        let mut action = action.clone();
+        let uri = action["data"]["uri"].as_str().unwrap_or_default();
+        let n = action["data"]["line"].as_u64().map(|n| n as usize);
+
+        if action["title"] == NORMALIZE {
+            if let Some(edit) = n.and_then(|n| self.normalize_line(uri, n)) {
+                action["edit"] = edit;
+            }
+        }
+        action
+    }
+
+    /// 行の式を正規形に書き換える編集内容
+    fn normalize_line(&self, uri: &str, n: usize) -> Option<Value> {
+        let (context, _) = self.index();
+        self.rewrite_line(uri, n, &|e: &Expr| normal_form(e, &context, &self.limits))
+    }
+
+    /// 行の式を書き換える編集内容、書き換えられないか行が変わらなければ None を返す
+    fn rewrite_line(&self, uri: &str, n: usize, transform: &Transform) -> Option<Value> {
+        let line = self.documents.get(uri)?.lines().nth(n)?;
+        let command = self.line_command(uri, n)?;
+
+        let rewritten = rewrite(&command, transform)?;
+        let new_text = format_command(&rewritten, &line_style(line));
+        if new_text == line {
+            return None;
+        }
+
+        Some(json!({
+            "changes": {
+                uri: [{
+                    "range": range(n, 0, n, utf16_len(line)),
+                    "newText": new_text,
+                }],
+            },
+        }))
+    }
+
+    /// 文書の n 行目のコマンド
+    fn line_command(&self, uri: &str, n: usize) -> Option<Command> {
+        let line = self.documents.get(uri)?.lines().nth(n)?;
+        parse_line(line).ok()
+    }
+
+    /// カーソル位置の識別子
+    fn identifier_at(&self, params: &Value) -> Option<Ident> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let n = params["position"]["line"].as_u64()? as usize;
+        let column = params["position"]["character"].as_u64()? as usize;
+
+        let line = self.documents.get(uri)?.lines().nth(n)?;
+        identifier_at(line, char_index(line, column))
+    }
+
+    /// 文書の最初の行がどちらのスタイルで書かれているか
+    fn document_style(&self, params: &Value) -> DisplayStyle {
+        params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+            .and_then(|text| text.lines().find(|line| !line.trim().is_empty()))
+            .map(line_style)
+            .unwrap_or(DisplayStyle::LazyK)
+    }
+}
+
+/// 式を書き換える、書き換えられなければ None を返す
+type Transform<'a> = dyn Fn(&Expr) -> Option<Expr> + 'a;
+
+/// コマンドの式、または定義の本体を書き換える
+fn rewrite(command: &Command, transform: &Transform) -> Option<Command> {
+    match command {
+        Command::Eval(e) => Some(Command::Eval(transform(e)?)),
+        Command::Update(f) => {
+            let clauses = f
+                .clauses()
+                .iter()
+                .map(|c| Some(Clause::new(c.patterns().to_vec(), transform(c.body())?)))
+                .collect::<Option<Vec<_>>>()?;
+            Some(Command::Update(Func::from_clauses(
+                f.name().clone(),
+                clauses,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// 編集内容を適用するコードアクション
+fn code_action(title: &str, edit: Value) -> Value {
+    json!({ "title": title, "kind": "refactor.rewrite", "edit": edit })
+}
+
+/// ステップ数や式の大きさ・深さ・時間の上限を超えずに正規形に達すればその式を返す
+///
+/// `==` と同じく、ラムダ抽象の本体と定数も含めて簡約する
+fn normal_form(e: &Expr, context: &Context, limits: &Limits) -> Option<Expr> {
+    match normalize(e.clone(), context, limits) {
+        (e, Status::NormalForm) => Some(e),
+        _ => None,
+    }
+}
+
+/// 行が Lazy K スタイルとしてパースできれば Lazy K スタイル、そうでなければ ECMAScript スタイル
+fn line_style(line: &str) -> DisplayStyle {
+    match parse_command(line, &DisplayStyle::LazyK) {
+        Ok(_) => DisplayStyle::LazyK,
+        Err(_) => DisplayStyle::Ecmascript,
+    }
+}
+
+/// パーサのエラーから、入力中の位置を表すだけの行を取り除く
+fn parse_error_message(err: &str) -> String {
+    err.lines()
+        .filter(|line| !line.starts_with("Parse error at"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// index 文字目を含む識別子を返す、シンボルの一部であれば None を返す
+///
+/// 識別子は 1 文字の英小文字、または英大文字・数字・`_` からなる列である
+fn identifier_at(line: &str, index: usize) -> Option<Ident> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let is_long = |c: &char| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == '_';
+
+    let c = *chars.get(index)?;
+    let (start, end) = if c.is_ascii_lowercase() {
+        (index, index + 1)
+    } else if is_long(&c) {
+        let start = chars[..index]
+            .iter()
+            .rposition(|c| !is_long(c))
+            .map_or(0, |p| p + 1);
+        let end = chars[index..]
+            .iter()
+            .position(|c| !is_long(c))
+            .map_or(chars.len(), |p| index + p);
+        (start, end)
+    } else {
+        return None;
+    };
+
+    if start > 0 && chars[start - 1] == ':' {
+        return None;
+    }
+
+    Some(Ident::new(&chars[start..end].iter().collect::<String>()))
+}
+
+/// UTF-16 での位置を文字の位置に変換する
+fn char_index(line: &str, utf16_column: usize) -> usize {
+    let mut column = 0;
+    for (index, c) in line.chars().enumerate() {
+        if column >= utf16_column {
+            return index;
+        }
+        column += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+fn utf16_len(line: &str) -> usize {
+    line.chars().map(char::len_utf16).sum()
+}
+
+fn range(start_line: usize, start: usize, end_line: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": start_line, "character": start },
+        "end": { "line": end_line, "character": end },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///prelude.txt";
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": text } },
+        }))
+    }
+
+    fn request(server: &mut Server, method: &str, params: Value) -> Value {
+        let mut replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }));
+        replies.pop().unwrap()["result"].take()
+    }
+
+    fn position(line: usize, character: usize) -> Value {
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new(Context::new());
+        let replies = open(&mut server, "`ix = x\n\n`ix = )\n");
+
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["range"], range(2, 0, 2, 7));
+        assert!(!diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("PointerOffset"));
+    }
+
+    #[test]
+    fn test_hover_and_definition() {
+        let mut server = Server::new(Context::new());
+        open(&mut server, "`ix = x\n``FOOxy = `yx\n``FOO:A`i:b\n");
+
+        let hover = request(&mut server, "textDocument/hover", position(2, 3));
+        assert_eq!(
+            hover["contents"]["value"],
+            "```\n``FOOxy = `yx\n```\narity: 2"
+        );
+
+        let definition = request(&mut server, "textDocument/definition", position(2, 8));
+        assert_eq!(definition["range"], range(0, 0, 0, 7));
+
+        // シンボルの名前は識別子ではない
+        let hover = request(&mut server, "textDocument/hover", position(2, 6));
+        assert_eq!(hover, Value::Null);
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut server = Server::new(Context::default());
+        open(&mut server, "``FOOxy = `yx\n");
+
+        let items = request(&mut server, "textDocument/completion", json!({}));
+        let labels = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(labels.contains(&"FOO"));
+        assert!(labels.contains(&"s"));
+    }
+
+    #[test]
+    fn test_code_actions() {
+        let mut server = Server::new(Context::default());
+        open(&mut server, "```skk:a\n`Ix = ^y.`xy\n");
+
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(0, 0, 0, 0) }),
+        );
+        assert_eq!(actions.as_array().unwrap().len(), 1);
+        assert_eq!(actions[0]["title"], "Evaluate to normal form");
+        assert_eq!(actions[0]["edit"]["changes"][URI][0]["newText"], ":a");
+
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(1, 0, 1, 0) }),
+        );
+        assert_eq!(actions[0]["title"], "Unlambda this expression");
+        assert_eq!(actions[0]["edit"]["changes"][URI][0]["newText"], "`Ix = x");
+    }
+
+    #[test]
+    fn test_code_action_normalize_constants() {
+        // `==` と同じく、ラムダ抽象の本体と定数も簡約する
+        let mut server = Server::new(Context::default());
+        open(&mut server, "``ADD 1 1\n");
+
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(0, 0, 0, 0) }),
+        );
+        assert_eq!(actions[0]["title"], "Evaluate to normal form");
+        assert_eq!(
+            actions[0]["edit"]["changes"][URI][0]["newText"],
+            "^f.^x.`f`fx"
+        );
+    }
+
+    #[test]
+    fn test_code_action_resolve() {
+        let mut server = Server::new(Context::default());
+        let capabilities = json!({
+            "textDocument": {
+                "codeAction": { "resolveSupport": { "properties": ["edit"] } },
+            },
+        });
+        request(
+            &mut server,
+            "initialize",
+            json!({ "capabilities": capabilities }),
+        );
+        open(&mut server, "```skk:a\n```sii``sii\n");
+
+        // 正規形はアクションが選ばれてから求める
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(0, 0, 0, 0) }),
+        );
+        assert_eq!(actions.as_array().unwrap().len(), 1);
+        assert_eq!(actions[0]["edit"], Value::Null);
+        let action = request(&mut server, "codeAction/resolve", actions[0].clone());
+        assert_eq!(action["edit"]["changes"][URI][0]["newText"], ":a");
+
+        // 上限までに正規形に達しなければ編集内容を付けない
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(1, 0, 1, 0) }),
+        );
+        let action = request(&mut server, "codeAction/resolve", actions[0].clone());
+        assert_eq!(action["title"], "Evaluate to normal form");
+        assert_eq!(action["edit"], Value::Null);
+
+        // 式の大きさの上限を超えても編集内容を付けない
+        server.limits.size = 5;
+        let actions = request(
+            &mut server,
+            "textDocument/codeAction",
+            json!({ "textDocument": { "uri": URI }, "range": range(0, 0, 0, 0) }),
+        );
+        let action = request(&mut server, "codeAction/resolve", actions[0].clone());
+        assert_eq!(action["edit"], Value::Null);
+    }
+
+    #[test]
+    fn test_serve() {
+        let body = |v: Value| {
+            let s = v.to_string();
+            format!("Content-Length: {}\r\n\r\n{}", s.len(), s)
+        };
+        let input = [
+            body(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })),
+            body(json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" })),
+            body(json!({ "jsonrpc": "2.0", "method": "exit" })),
+        ]
+        .concat();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, Context::new()).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("Content-Length").count(), 2);
+        assert!(output.contains("\"hoverProvider\":true"));
+    }
+}
@@ -1,44 +1,375 @@
-mod command;
-mod config;
-mod context;
-mod engine;
-mod evaluate;
-mod expression;
-mod function;
-mod history;
-mod identifier;
-mod parser;
-
-use clap::Parser;
-
-use engine::Engine;
-use history::{open_or_create_history_file, rebuild_context, Logger};
-use parser::command::ecmascript::parse_command as parse_ecmascript_style_command;
-use parser::command::lazy_k::parse_command as parse_lazy_k_style_command;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+
+use tuber::command::Command;
+use tuber::config::{
+    display_style, line_width, prelude, step_limit, strategy, Config, ConfigArgs, DisplayStyle,
+    OutputFormat, Strategy,
+};
+use tuber::context::Context;
+use tuber::debugger::Debugger;
+use tuber::engine::{Engine, EventSink, JsonOutput, TextOutput};
+use tuber::format::{format_source, parse_line};
+use tuber::history::{
+    load_prelude, open_or_create_history_file, rebuild_context, rebuild_timeline, Logger, Timeline,
+};
+use tuber::lsp;
+use tuber::runner::{run_checks, Check};
 
 /// An interpreter that evaluates λ-calculations step by step.
 #[derive(Parser, Debug)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true
+)]
 struct Args {
+    #[command(subcommand)]
+    subcommand: Option<SubCommand>,
+
     /// コマンド
-    command: String,
+    command: Option<String>,
+
+    /// 簡約の統計情報 (ステップ数、関数ごとの適用回数、式の大きさ、所要時間など) を表示する
+    #[arg(long)]
+    stats: bool,
+
+    /// 表示スタイル (lazy-k | ecmascript)、設定ファイルや環境変数より優先する
+    #[arg(long)]
+    style: Option<DisplayStyle>,
+
+    /// 簡約のステップ数の上限、設定ファイルや環境変数より優先する
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// 簡約戦略 (normal | full)、full ならラムダ抽象の本体も簡約する
+    #[arg(long)]
+    strategy: Option<Strategy>,
+
+    /// 履歴を読まず、書き込みもしない
+    #[arg(long)]
+    no_history: bool,
+
+    /// 履歴に加えて定義ファイルを読み込む、履歴の定義より優先する
+    #[arg(long, value_name = "FILE")]
+    context: Option<PathBuf>,
+
+    /// 出力の形式 (text | json)
+    #[arg(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// JSON で出力するとき、式の構文木も出力する
+    #[arg(long)]
+    ast: bool,
+
+    /// 入力の式や途中の式を表示せず、結果だけを表示する
+    #[arg(long)]
+    quiet: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum SubCommand {
+    /// 定義ファイルを正規の書式に整形する
+    Fmt {
+        /// 整形後のスタイル (lazy-k | ecmascript)
+        #[arg(long)]
+        style: Option<DisplayStyle>,
+
+        /// ファイルを書き換えずに整形結果を標準出力へ書き出す
+        #[arg(long)]
+        stdout: bool,
+
+        /// ファイルを書き換えず、整形が必要なファイルがあれば失敗する
+        #[arg(long, conflicts_with = "stdout")]
+        check: bool,
+
+        /// 整形する定義ファイル
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// 式の簡約を 1 ステップずつ対話的に進める
+    Debug {
+        /// 簡約する式
+        expr: String,
+    },
+
+    /// 定義ファイルのための Language Server Protocol サーバを標準入出力で動かす
+    Lsp,
+
+    /// 定義ファイルに埋め込んだ期待値を検査する
+    Test {
+        /// 検査する定義ファイル
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
 }
 
 fn main() {
     let args = Args::parse();
-    let command = args.command;
 
-    let file = open_or_create_history_file();
-    let context = rebuild_context(&file, None);
-    let mut logger = Logger::new(file);
+    match Config::load() {
+        Ok(mut config) => {
+            // コマンドライン引数は設定ファイルや環境変数より優先する
+            config.merge_args(&ConfigArgs {
+                display_style: args.style,
+                step_limit: args.limit,
+                strategy: args.strategy,
+            });
+            tuber::config::init(config);
+        }
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(2);
+        }
+    }
+
+    match (args.subcommand, args.command) {
+        (
+            Some(SubCommand::Fmt {
+                style,
+                stdout,
+                check,
+                files,
+            }),
+            _,
+        ) => {
+            let style = style.unwrap_or_else(display_style);
+            if !fmt(&files, &style, stdout, check) {
+                std::process::exit(1);
+            }
+        }
+
+        (Some(SubCommand::Debug { expr }), _) => debug(&expr),
+
+        (Some(SubCommand::Lsp), _) => {
+            let context = initial_context().unwrap_or_default();
+            let stdin = std::io::stdin();
+            if let Err(e) = lsp::serve(stdin.lock(), std::io::stdout(), context) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+
+        (Some(SubCommand::Test { files }), _) => {
+            if !test(&files) {
+                std::process::exit(1);
+            }
+        }
+
+        (None, Some(command)) => {
+            let options = RunOptions {
+                stats: args.stats,
+                no_history: args.no_history,
+                context: args.context,
+                quiet: args.quiet,
+                format: args.format,
+                ast: args.ast,
+            };
+            run(&command, &options);
+        }
+
+        // arg_required_else_help は引数が 1 つも無いときにしか働かないので、フラグだけが渡された場合はここに来る
+        (None, None) => Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "a command or a subcommand is required",
+            )
+            .exit(),
+    }
+}
 
-    match parse_lazy_k_style_command(&command)
-        .or_else(|_err| parse_ecmascript_style_command(&command))
-    {
+/// コマンドを実行するときのオプション
+struct RunOptions {
+    stats: bool,
+    no_history: bool,
+    context: Option<PathBuf>,
+    quiet: bool,
+    format: OutputFormat,
+    ast: bool,
+}
+
+fn run(command: &str, options: &RunOptions) {
+    let (mut timeline, mut logger) = if options.no_history {
+        let timeline = Timeline::new(initial_context().unwrap_or_default());
+        let logger: Logger<Box<dyn Write>> = Logger::new(Box::new(std::io::sink()));
+        (timeline, logger)
+    } else {
+        let file = open_or_create_history_file();
+        let timeline = rebuild_timeline(&file, initial_context());
+        let logger: Logger<Box<dyn Write>> = Logger::new(Box::new(file));
+        (timeline, logger)
+    };
+
+    // 定義ファイルの定義は履歴に記録しないので、取り消しの対象にならない
+    if let Some(path) = &options.context {
+        if let Err(e) = timeline.overlay(path) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    match parse_line(command) {
         Ok(command) => {
             logger.push(&command);
-            Engine::new(context).run(command);
+            let mut sink: Box<dyn EventSink> = match options.format {
+                OutputFormat::Text => {
+                    let mut output = TextOutput::new(std::io::stdout(), timeline.context());
+                    // 出力先が端末なら行幅に合わせて式を改行・インデントする
+                    if std::io::stdout().is_terminal() {
+                        output.line_width(Some(line_width()));
+                    }
+                    output.quiet(options.quiet);
+                    Box::new(output)
+                }
+                OutputFormat::Json => {
+                    let mut output = JsonOutput::new(std::io::stdout());
+                    output.ast(options.ast);
+                    Box::new(output)
+                }
+            };
+
+            let mut engine = Engine::with_timeline(timeline);
+            engine.show_stats(options.stats);
+            engine.run(command, sink.as_mut());
+        }
+        Err(e) => match options.format {
+            OutputFormat::Text => println!("{}", e),
+            OutputFormat::Json => println!("{}", serde_json::json!({ "error": e })),
+        },
+    }
+}
+
+fn debug(expr: &str) {
+    let file = open_or_create_history_file();
+    let context = rebuild_context(&file, initial_context());
+
+    match parse_line(expr) {
+        Ok(Command::Eval(e)) => {
+            let mut debugger =
+                Debugger::new(e, &context, display_style(), step_limit(), strategy());
+            let stdin = std::io::stdin();
+            debugger
+                .repl(stdin.lock(), &mut std::io::stdout())
+                .expect("入出力に失敗しました");
         }
+        Ok(_) => println!("debug expects an expression"),
         Err(e) => println!("{}", e),
     }
 }
+
+/// 設定で指定された prelude を読み込む、読み込めなければ終了する
+fn initial_context() -> Option<Context> {
+    let path = prelude()?;
+    match load_prelude(path) {
+        Ok(context) => Some(context),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// 定義ファイルの期待値を検査して結果をまとめて表示する、すべて一致したら true を返す
+///
+/// 各ファイルは prelude (指定が無ければ組み込みの定義) から始めて互いに独立に実行する
+fn test(files: &[PathBuf]) -> bool {
+    let style = display_style();
+    let context = initial_context().unwrap_or_default();
+
+    let mut ok = true;
+    let mut failures: Vec<(&PathBuf, Check)> = Vec::new();
+    let mut passed = 0;
+
+    for file in files {
+        let checks = std::fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|source| run_checks(&source, context.clone(), &style));
+        let checks = match checks {
+            Ok(checks) => checks,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+
+        for check in checks {
+            let result = if check.passed() { "ok" } else { "FAILED" };
+            println!("test {}:{} ... {}", file.display(), check.line, result);
+            if check.passed() {
+                passed += 1;
+            } else {
+                failures.push((file, check));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for (file, check) in &failures {
+            println!();
+            println!("{}:{}: {}", file.display(), check.line, check.source);
+            for mismatch in &check.mismatches {
+                println!("  {}:", mismatch.what);
+                println!("  - {}", mismatch.expected);
+                println!("  + {}", mismatch.actual);
+            }
+        }
+    }
+
+    ok &= failures.is_empty();
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if ok { "ok" } else { "FAILED" },
+        passed,
+        failures.len()
+    );
+    ok
+}
+
+/// 定義ファイルを整形する、すべてのファイルの整形に成功したら true を返す
+fn fmt(files: &[PathBuf], style: &DisplayStyle, stdout: bool, check: bool) -> bool {
+    let mut ok = true;
+
+    for file in files {
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+
+        let formatted = match format_source(&source, style) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+
+        if stdout {
+            print!("{}", formatted);
+        } else if check {
+            if formatted != source {
+                println!("{}: needs formatting", file.display());
+                ok = false;
+            }
+        } else if formatted != source {
+            if let Err(e) = std::fs::write(file, formatted) {
+                eprintln!("{}: {}", file.display(), e);
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
@@ -1,10 +1,14 @@
-use combine::parser::char::{char, digit, spaces, string};
+use combine::parser::char::{char, digit, lower, space, spaces, string};
 use combine::parser::choice::choice;
 #[allow(unused_imports)]
 use combine::EasyParser;
-use combine::{attempt, eof, many, many1, optional, parser, ParseError, Parser, Stream};
+use combine::{
+    attempt, eof, many, many1, not_followed_by, optional, parser, sep_by1, ParseError, Parser,
+    Stream,
+};
 
 use crate::command::Command;
+use crate::data::{Constructor, DataType, Encoding};
 use crate::expression::Expr;
 use crate::function::Func;
 use crate::identifier::Ident;
@@ -28,7 +32,14 @@ where
         From<::std::num::ParseIntError>,
 {
     choice((
+        data(),
         attempt(update()),
+        attempt(stats()),
+        attempt(history()),
+        attempt(whnf()),
+        attempt(hnf()),
+        attempt(assert()),
+        attempt(equiv()),
         eval(),
         attempt(eval_head()),
         attempt(eval_tail()),
@@ -53,24 +64,36 @@ where
     def_lhs()
         .skip(spaces().with(char('=')))
         .and(expr())
-        .map(|((i, is), rhs)| match rhs {
-            Expr::Variable(j) if is.is_empty() && i == j => return Command::Del(i),
-            _ => Command::Update(Func::new(i, is, rhs)),
+        .map(|((i, ps), rhs)| match &rhs {
+            Expr::Variable(j) if ps.is_empty() && &i == j => Command::Del(i),
+            _ => Command::Update(Func::with_patterns(i, ps, rhs)),
         })
 }
 
-fn def_lhs<Input>() -> impl Parser<Input, Output = (Ident, Vec<Ident>)>
+fn def_lhs<Input>() -> impl Parser<Input, Output = (Ident, Vec<Expr>)>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
         From<::std::num::ParseIntError>,
 {
-    def_lhs_()
+    identifier()
+        .and(spaces().with(optional(params())))
+        .map(|(i, ps)| (i, ps.unwrap_or_else(Vec::new)))
+}
+
+fn params<Input>() -> impl Parser<Input, Output = Vec<Expr>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    params_()
 }
 
 parser! {
-    fn def_lhs_[Input]()(Input) -> (Ident, Vec<Ident>)
+    fn params_[Input]()(Input) -> Vec<Expr>
     where [
         Input: Stream<Token = char>,
         Input::Error: ParseError<char, Input::Range, Input::Position>,
@@ -78,32 +101,95 @@ parser! {
             From<::std::num::ParseIntError>,
     ]
     {
-        identifier()
-            .and(spaces().with(optional(params())))
-            .map(|(i, is)| (i, is.unwrap_or_else(Vec::new)))
+        char('(')
+            .with(
+                optional(many(attempt(
+                    spaces().with(pattern()).skip(spaces()).skip(char(',')),
+                )))
+                .and(spaces().with(pattern()))
+                .map(|(ps, p)| {
+                    let mut ps: Vec<Expr> = ps.unwrap_or_else(Vec::new);
+                    ps.push(p);
+                    ps
+                }),
+            )
+            .skip(spaces().with(char(')')))
     }
 }
 
-fn params<Input>() -> impl Parser<Input, Output = Vec<Ident>>
+/// 仮引数のパターン、変数・シンボル・頭部がシンボルの適用 `:SUCC(n)` のいずれか
+fn pattern<Input>() -> impl Parser<Input, Output = Expr>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
         From<::std::num::ParseIntError>,
+{
+    let constructor = char(':')
+        .with(identifier())
+        .and(many(attempt(spaces().with(params()))))
+        .map(|(i, argss): (Ident, Vec<Vec<Expr>>)| {
+            let mut e = Expr::Symbol(i);
+            for arg in argss.into_iter().flatten() {
+                e = Expr::a(e, arg);
+            }
+            e
+        });
+
+    spaces().with(choice((constructor, identifier().map(Expr::Variable))))
+}
+
+// ========================================================================== //
+
+/// `data TREE = LEAF | NODE(TREE, v, TREE)`、`data church` なら Church 符号化
+///
+/// 型とコンストラクタの名前も識別子なので、大文字・数字・_ だけで書く
+/// `Tree` のような大文字と小文字の混ざった名前は `T` と `ree` に分かれてしまうので誤りにする
+fn data<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // 名前の直後に小文字が続くなら、`Tree` を T と ree に分けて読まないよう誤りにする
+    let name = || {
+        identifier().skip(not_followed_by(lower()).message(
+            "data type and constructor names must be uppercase, e.g. `TREE` instead of `Tree`",
+        ))
+    };
+    let constructor = name()
+        .and(optional(attempt(spaces().with(fields()))).map(|fs| fs.unwrap_or_else(Vec::new)))
+        .map(|(name, fields)| Constructor { name, fields });
+    let encoding = optional(attempt(spaces().with(string("church")))).map(|church| match church {
+        Some(_) => Encoding::Church,
+        None => Encoding::Scott,
+    });
+
+    // `data` に空白が続けば data 型の定義として読み、以降の誤りはそのまま報告する
+    attempt(spaces().with(string("data")).skip(space()))
+        .with(encoding)
+        .and(name())
+        .skip(spaces().with(char('=')))
+        .and(sep_by1(constructor, attempt(spaces().with(char('|')))))
+        .map(|((encoding, name), constructors)| {
+            Command::Data(DataType {
+                name,
+                encoding,
+                constructors,
+            })
+        })
+}
+
+fn fields<Input>() -> impl Parser<Input, Output = Vec<Ident>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     char('(')
-        .with(
-            optional(many(attempt(
-                spaces().with(identifier()).skip(spaces()).skip(char(',')),
-            )))
-            .and(spaces().with(identifier()))
-            .map(|(is, i)| {
-                let mut is = is.unwrap_or_else(Vec::new);
-                is.push(i);
-                is
-            }),
-        )
-        .skip(spaces().with(char(')')))
+        .with(sep_by1(
+            spaces().with(identifier()).skip(spaces()),
+            char(','),
+        ))
+        .skip(char(')'))
 }
 
 // ========================================================================== //
@@ -196,6 +282,93 @@ where
 
 // ========================================================================== //
 
+fn stats<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    spaces()
+        .skip(string(":stats"))
+        .with(expr())
+        .map(Command::Stats)
+}
+
+fn whnf<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    spaces()
+        .skip(string(":whnf"))
+        .with(expr())
+        .map(Command::Whnf)
+}
+
+fn hnf<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    spaces().skip(string(":hnf")).with(expr()).map(Command::Hnf)
+}
+
+fn assert<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    spaces()
+        .skip(string("=="))
+        .with(expr())
+        .and(expr())
+        .map(|(l, r)| Command::Assert(l, r))
+}
+
+fn equiv<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    spaces()
+        .skip(string(":equiv"))
+        .with(identifier())
+        .and(identifier())
+        .map(|(l, r)| Command::Equiv(l, r))
+}
+
+fn history<Input>() -> impl Parser<Input, Output = Command>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    // 回数を省略したら 1 回とする
+    let count = || {
+        optional(attempt(
+            spaces().with(many1(digit()).and_then(|x: String| x.parse::<usize>())),
+        ))
+        .map(|n| n.unwrap_or(1))
+    };
+
+    spaces().skip(char(':')).with(choice((
+        attempt(string("history")).map(|_| Command::History),
+        attempt(string("undo")).with(count()).map(Command::Undo),
+        attempt(string("redo")).with(count()).map(Command::Redo),
+        string("diff").with(count()).map(Command::Diff),
+    )))
+}
+
+// ========================================================================== //
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -410,4 +583,110 @@ mod tests {
             Ok((Command::Unlambda(Expr::l("x".into(), "x".into())), ""))
         );
     }
+
+    #[test]
+    fn test_head_normal_forms() {
+        let e = Expr::a("a".into(), "b".into());
+        assert_eq!(
+            command().easy_parse(":whnf a(b)"),
+            Ok((Command::Whnf(e.clone()), ""))
+        );
+        assert_eq!(command().easy_parse(":hnf a(b)"), Ok((Command::Hnf(e), "")));
+    }
+
+    #[test]
+    fn test_assert() {
+        let l = Expr::a(Expr::a("ADD".into(), "2".into()), "3".into());
+        assert_eq!(
+            command().easy_parse("== ADD(2, 3) 5"),
+            Ok((Command::Assert(l, "5".into()), ""))
+        );
+    }
+
+    #[test]
+    fn test_equiv() {
+        assert_eq!(
+            command().easy_parse(":equiv K k"),
+            Ok((Command::Equiv("K".into(), "k".into()), ""))
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        assert_eq!(history().easy_parse(":history"), Ok((Command::History, "")));
+        assert_eq!(command().easy_parse(":undo"), Ok((Command::Undo(1), "")));
+        assert_eq!(command().easy_parse(":undo 3 "), Ok((Command::Undo(3), "")));
+        assert_eq!(command().easy_parse(":redo 2"), Ok((Command::Redo(2), "")));
+        assert_eq!(command().easy_parse(":diff"), Ok((Command::Diff(1), "")));
+
+        assert!(command().easy_parse(":undo x").is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            stats().easy_parse(":stats a(b)"),
+            Ok((Command::Stats(Expr::a("a".into(), "b".into())), ""))
+        );
+        assert_eq!(
+            command().easy_parse(":stats  a(b)"),
+            Ok((Command::Stats(Expr::a("a".into(), "b".into())), ""))
+        );
+    }
+
+    #[test]
+    fn test_update_patterns() {
+        assert_eq!(
+            parse_command("f(:SUCC(n), :ZERO) = n"),
+            Ok(Command::Update(Func::with_patterns(
+                "f".into(),
+                vec![Expr::a(":SUCC".into(), "n".into()), ":ZERO".into()],
+                "n".into()
+            )))
+        );
+
+        // 頭部がシンボルでない適用はパターンにならない
+        assert!(parse_command("f(a(b)) = a").is_err());
+    }
+
+    #[test]
+    fn test_data_lowercase_names() {
+        // 識別子は小文字 1 文字か大文字・数字・_ の並びなので、`Tree` は 1 つの名前として読めない
+        let err = parse_command("data Tree = Leaf | Node(l, v, r)").unwrap_err();
+        assert!(
+            err.contains("data type and constructor names must be uppercase"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        let constructor = |name: &str, fields: Vec<&str>| Constructor {
+            name: name.into(),
+            fields: fields.into_iter().map(Ident::from).collect(),
+        };
+        assert_eq!(
+            parse_command("data TREE = LEAF | NODE(TREE, v, TREE)"),
+            Ok(Command::Data(DataType {
+                name: "TREE".into(),
+                encoding: Encoding::Scott,
+                constructors: vec![
+                    constructor("LEAF", vec![]),
+                    constructor("NODE", vec!["TREE", "v", "TREE"]),
+                ],
+            }))
+        );
+        assert_eq!(
+            parse_command("data church NAT = ZERO | SUCC(NAT)"),
+            Ok(Command::Data(DataType {
+                name: "NAT".into(),
+                encoding: Encoding::Church,
+                constructors: vec![
+                    constructor("ZERO", vec![]),
+                    constructor("SUCC", vec!["NAT"]),
+                ],
+            }))
+        );
+    }
 }
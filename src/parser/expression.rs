@@ -1,14 +1,21 @@
 pub mod ecmascript;
 
-use combine::parser::char::{char, spaces};
+use combine::error::Commit;
+use combine::parser::char::{char, spaces, string};
 use combine::parser::choice::choice;
+use combine::parser::function;
 #[allow(unused_imports)]
 use combine::EasyParser;
-use combine::{parser, ParseError, Parser, Stream};
+use combine::{attempt, many, optional, ParseError, Parser, Stream};
 
 use crate::expression::Expr;
+use crate::identifier::Ident;
 use crate::parser::identifier::identifier;
+use crate::primitive::match_symbol;
 
+/// Lazy K 風の記法の式をパースする
+///
+/// 深い式でもスタックを溢れさせないよう、式の構造は再帰下降ではなく明示的なスタックで組み立てる
 pub fn expr<Input>() -> impl Parser<Input, Output = Expr>
 where
     Input: Stream<Token = char>,
@@ -16,25 +23,85 @@ where
     <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
         From<::std::num::ParseIntError>,
 {
-    expr_()
+    function::parser(|input: &mut Input| {
+        let mut pending: Vec<Pending> = Vec::new();
+        let mut commit = Commit::Peek(());
+
+        loop {
+            let (token, c) = match token().parse_stream(input).into_result() {
+                Ok(ok) => ok,
+                Err(err) if commit.is_peek() => return Err(err),
+                Err(err) => return Err(err.into_commit()),
+            };
+            commit = commit.merge(c);
+
+            let mut expr = match token {
+                Token::Apply => {
+                    pending.push(Pending::Lhs);
+                    continue;
+                }
+                Token::Lambda(param) => {
+                    pending.push(Pending::Body(param));
+                    continue;
+                }
+                Token::Expr(expr) => expr,
+            };
+
+            // 読み終えた式で埋められる穴を内側から順に埋めていく
+            loop {
+                match pending.pop() {
+                    None => return Ok((expr, commit)),
+                    Some(Pending::Lhs) => {
+                        pending.push(Pending::Rhs(expr));
+                        break;
+                    }
+                    Some(Pending::Rhs(lhs)) => expr = Expr::a(lhs, expr),
+                    Some(Pending::Body(param)) => expr = Expr::l(param, expr),
+                }
+            }
+        }
+    })
+}
+
+/// 読み終えていない式
+enum Pending {
+    /// 関数適用の左辺を待っている
+    Lhs,
+
+    /// 左辺を読み終え、関数適用の右辺を待っている
+    Rhs(Expr),
+
+    /// ラムダ抽象の本体を待っている
+    Body(Ident),
+}
+
+enum Token {
+    /// 関数適用の始まりを表す `` ` ``
+    Apply,
+
+    /// ラムダ抽象の仮引数 `^x.`
+    Lambda(Ident),
+
+    /// 変数またはシンボル
+    Expr(Expr),
 }
 
-parser! {
-    fn expr_[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces().with(choice((
-            apply(),
-            lambda(),
-            symbol(),
-            var(),
-        )))
-    }
+fn token<Input>() -> impl Parser<Input, Output = Token>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    spaces().with(choice((
+        char('`').map(|_| Token::Apply),
+        choice((char('^'), char('λ')))
+            .with(identifier().skip(spaces().with(char('.'))))
+            .map(Token::Lambda),
+        symbol().map(Token::Expr),
+        attempt(match_expr(expr)).map(Token::Expr),
+        var().map(Token::Expr),
+    )))
 }
 
 #[test]
@@ -83,27 +150,73 @@ fn test_symbol() {
     assert_eq!(symbol().parse(":ABCabc"), Ok((":ABC".into(), "abc")));
 }
 
-// ========================================================================== //
-
-parser! {
-    fn apply[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
+/// シンボルで場合分けする `match e { :a => x, :b => y, _ => z }`
+///
+/// 式の部分は記法ごとの expr でパースし、`SYM_EQ` の連鎖に書き換えた式を返す
+pub(crate) fn match_expr<Input, P>(expr: fn() -> P) -> impl Parser<Input, Output = Expr>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    P: Parser<Input, Output = Expr>,
+{
+    let arm = move || {
         spaces()
-            .with(char('`'))
-            .with(
-                expr()
-                .and(expr())
-            )
-            .map(|(lhs, rhs)| Expr::a(lhs, rhs))
-    }
+            .with(string("=>"))
+            .with(expr())
+            .skip(spaces())
+            .skip(optional(char(',')))
+    };
+    let symbol_arm = spaces().with(char(':')).with(identifier()).and(arm());
+    let default_arm = spaces().with(char('_')).with(arm());
+
+    spaces()
+        .with(string("match"))
+        .with(expr())
+        .skip(spaces().with(char('{')))
+        .and(many(attempt(symbol_arm)))
+        .and(optional(attempt(default_arm)))
+        .skip(spaces().with(char('}')))
+        .map(
+            |((scrutinee, arms), default): ((Expr, Vec<(Ident, Expr)>), _)| {
+                match_symbol(scrutinee, arms, default)
+            },
+        )
+}
+
+#[test]
+fn test_match() {
+    let test = |s: &str| Expr::a(Expr::a("SYM_EQ".into(), "s".into()), Expr::s(s));
+    let chain = Expr::a(
+        Expr::a(test("a"), ":b".into()),
+        Expr::a("i".into(), "x".into()),
+    );
+    assert_eq!(
+        expr().easy_parse("match x { :a => :b, _ => `ix }"),
+        Ok((Expr::a(Expr::l("s".into(), chain), "x".into()), ""))
+    );
+    assert_eq!(
+        expr().easy_parse("`match x {} :a"),
+        Ok((Expr::a(":NO_MATCH".into(), ":a".into()), ""))
+    );
+
+    // match に続けて { が無ければ変数として読む
+    assert_eq!(
+        expr().easy_parse("````match"),
+        Ok((
+            Expr::a(
+                Expr::a(
+                    Expr::a(Expr::a("m".into(), "a".into()), "t".into()),
+                    "c".into()
+                ),
+                "h".into()
+            ),
+            ""
+        ))
+    );
 }
 
+// ========================================================================== //
+
 #[test]
 fn test_apply() {
     assert!(expr().easy_parse("`a").is_err());
@@ -132,26 +245,6 @@ fn test_apply() {
 
 // ========================================================================== //
 
-parser! {
-    fn lambda[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces()
-            .with(choice((char('^'), char('λ'))))
-            .with(
-                identifier()
-                .skip(spaces().with(char('.'))
-            )
-            .and(expr()))
-            .map(|(param, body)| Expr::l(param, body))
-    }
-}
-
 #[test]
 fn test_lambda() {
     assert!(expr().easy_parse("^a").is_err());
@@ -200,3 +293,14 @@ fn test_lambda() {
         Ok((Expr::l("a".into(), Expr::l("b".into(), "c".into())), ""))
     );
 }
+
+#[test]
+fn test_expr_deep() {
+    // 100 万段入れ子になった関数適用でもスタックを溢れさせない
+    let n = 1_000_000;
+    let source = format!("{}:a", "`:f".repeat(n));
+    let (expr, rest) = expr().easy_parse(source.as_str()).unwrap();
+
+    assert_eq!(rest, "");
+    assert_eq!(expr.depth(), n + 1);
+}
@@ -1,13 +1,19 @@
+use combine::error::{Commit, Tracked};
 use combine::parser::char::{char, spaces, string};
 use combine::parser::choice::choice;
+use combine::parser::function;
 #[allow(unused_imports)]
 use combine::EasyParser;
-use combine::{attempt, many, many1, optional, parser, ParseError, Parser, Stream};
+use combine::{attempt, many, optional, ParseError, Parser, Stream};
 
 use crate::expression::Expr;
 use crate::identifier::Ident;
+use crate::parser::expression::match_expr;
 use crate::parser::identifier::identifier;
 
+/// ECMAScript 風の記法の式をパースする
+///
+/// 深い式でもスタックを溢れさせないよう、式の構造は再帰下降ではなく明示的なスタックで組み立てる
 pub fn expr<Input>() -> impl Parser<Input, Output = Expr>
 where
     Input: Stream<Token = char>,
@@ -15,24 +21,152 @@ where
     <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
         From<::std::num::ParseIntError>,
 {
-    expr_()
+    function::parser(|input: &mut Input| {
+        let mut pending: Vec<Pending> = Vec::new();
+        let mut commit = Commit::Peek(());
+
+        loop {
+            let mut state = match step(head(), input, &mut commit)? {
+                Head::Parens => {
+                    pending.push(Pending::Parens);
+                    continue;
+                }
+                Head::Lambda(params) => {
+                    pending.push(Pending::Body(params));
+                    continue;
+                }
+                Head::Match(expr) => State::Done(expr),
+                Head::Atom(expr) => State::Callee(expr, false),
+            };
+
+            // 読み終えた式で埋められる穴を内側から順に埋めていき、次の式を読む必要があれば抜ける
+            loop {
+                state = match state {
+                    State::Callee(callee, required) => {
+                        let open = if required {
+                            Some(step(spaces().with(char('(')), input, &mut commit)?)
+                        } else {
+                            step(
+                                optional(attempt(spaces().with(char('(')))),
+                                input,
+                                &mut commit,
+                            )?
+                        };
+                        match open {
+                            Some(_) => {
+                                pending.push(Pending::Arg(callee));
+                                break;
+                            }
+                            None => State::Done(callee),
+                        }
+                    }
+
+                    State::Done(expr) => match pending.pop() {
+                        None => return Ok((expr, commit)),
+                        Some(Pending::Parens) => {
+                            step(spaces().with(char(')')), input, &mut commit)?;
+                            State::Callee(expr, true)
+                        }
+                        Some(Pending::Arg(callee)) => {
+                            let callee = Expr::a(callee, expr);
+                            let c = step(
+                                spaces().with(choice((char(','), char(')')))),
+                                input,
+                                &mut commit,
+                            )?;
+                            if c == ',' {
+                                pending.push(Pending::Arg(callee));
+                                break;
+                            }
+                            State::Callee(callee, false)
+                        }
+                        Some(Pending::Body(params)) => State::Done(
+                            params
+                                .into_iter()
+                                .rev()
+                                .fold(expr, |body, param| Expr::l(param, body)),
+                        ),
+                    },
+                }
+            }
+        }
+    })
 }
 
-parser! {
-    fn expr_[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces().with(choice((
-            attempt(apply()),
-            attempt(lambda()),
-            symbol(),
-            var(),
-        )))
+/// 読み終えていない式
+enum Pending {
+    /// パーレンの中の関数適用の左辺を読んでいる
+    Parens,
+
+    /// 左辺を読み終え、関数適用の引数を待っている
+    Arg(Expr),
+
+    /// ラムダ抽象の本体を待っている
+    Body(Vec<Ident>),
+}
+
+/// 式を 1 つ読み終えた後の状態
+enum State {
+    /// 関数適用の左辺になりうる式、true なら引数が続かなければならない
+    Callee(Expr, bool),
+
+    /// 読み終えた式
+    Done(Expr),
+}
+
+/// 式の先頭
+enum Head {
+    /// パーレンで囲まれた関数適用の左辺の始まり `(`
+    Parens,
+
+    /// ラムダ抽象の仮引数 `x =>` や `(x, y) =>`
+    Lambda(Vec<Ident>),
+
+    /// `match` 式
+    Match(Expr),
+
+    /// 変数またはシンボル
+    Atom(Expr),
+}
+
+fn head<Input>() -> impl Parser<Input, Output = Head>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
+        From<::std::num::ParseIntError>,
+{
+    let arrow = || spaces().with(string("=>"));
+
+    // 仮引数の並び `(x, y) =>` は、パーレンで囲まれた式の始まり `(` より先に試す
+    // match は変数 m として読めてしまうので、変数より先に試す
+    spaces().with(choice((
+        symbol().map(Head::Atom),
+        attempt(params().skip(arrow())).map(Head::Lambda),
+        char('(').map(|_| Head::Parens),
+        attempt(match_expr(expr)).map(Head::Match),
+        attempt(identifier().skip(arrow())).map(|param| Head::Lambda(vec![param])),
+        var().map(Head::Atom),
+    )))
+}
+
+/// 部分的なパーサーを実行し、入力を消費したかどうかを commit に積み上げる
+fn step<Input, P>(
+    mut parser: P,
+    input: &mut Input,
+    commit: &mut Commit<()>,
+) -> Result<P::Output, Commit<Tracked<Input::Error>>>
+where
+    Input: Stream<Token = char>,
+    P: Parser<Input>,
+{
+    match parser.parse_stream(input).into_result() {
+        Ok((output, c)) => {
+            *commit = commit.merge(c);
+            Ok(output)
+        }
+        Err(err) if commit.is_peek() => Err(err),
+        Err(err) => Err(err.into_commit()),
     }
 }
 
@@ -82,159 +216,78 @@ fn test_symbol() {
     assert_eq!(symbol().parse(":ABCabc"), Ok((":ABC".into(), "abc")));
 }
 
-// ========================================================================== //
-
-parser! {
-    fn apply[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces()
-            .with(callable())
-            .and(
-                many1(spaces().with(args()))
-            )
-            .map(|(mut e, argss)| {
-                let _: Vec<Vec<Expr>> = argss;
-                for args in argss {
-                    for arg in args {
-                        e = Expr::a(e, arg);
-                    }
-                }
-                e
-            })
-    }
-}
-
-fn callable<Input>() -> impl Parser<Input, Output = Expr>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-        From<::std::num::ParseIntError>,
-{
-    callable_()
-}
-
-parser! {
-    fn callable_[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces().with(choice((
-            attempt(parens(expr())), // パーレンで囲まれている式はパーレンを剥がしてから再度パースを試みる
-
-            // パーレンで囲まれていない場合、その後に許されるのは関数抽象, 変数, シンボルのみ
-            // つまり、パーレンで囲まれていない形での関数適用は弾く
-            // ここで関数適用を弾いておかないと左再帰で無限ループしてしまう
-            attempt(lambda()),
-            symbol(),
-            var(),
-        )))
-    }
+#[test]
+fn test_match() {
+    let test = |s: &str| Expr::a(Expr::a("SYM_EQ".into(), "s".into()), Expr::s(s));
+    let chain = Expr::a(
+        Expr::a(test("a"), ":b".into()),
+        Expr::a("i".into(), "x".into()),
+    );
+    assert_eq!(
+        expr().easy_parse("match x { :a => :b, _ => i(x) }"),
+        Ok((Expr::a(Expr::l("s".into(), chain), "x".into()), ""))
+    );
+    assert_eq!(
+        expr().easy_parse("m(a)"),
+        Ok((Expr::a("m".into(), "a".into()), ""))
+    );
 }
 
-fn args<Input>() -> impl Parser<Input, Output = Vec<Expr>>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-    <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-        From<::std::num::ParseIntError>,
-{
-    parens(
-        optional(many(attempt(
-            spaces().with(expr()).skip(spaces()).skip(char(',')),
-        )))
-        .and(spaces().with(expr())),
-    )
-    .map(|(es, e)| {
-        let mut es = es.unwrap_or_else(Vec::new);
-        es.push(e);
-        es
-    })
-}
+// ========================================================================== //
 
 #[test]
 fn test_apply() {
+    assert!(expr().easy_parse("(a)").is_err());
+    assert!(expr().easy_parse("a(b, )").is_err());
+
     assert_eq!(
-        apply().easy_parse("a(b)"),
+        expr().easy_parse("a(b)"),
         Ok((Expr::a("a".into(), "b".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("(a)(b)"),
+        expr().easy_parse("(a)(b)"),
         Ok((Expr::a("a".into(), "b".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("a(b)(c)"),
+        expr().easy_parse("a(b)(c)"),
         Ok((Expr::a(Expr::a("a".into(), "b".into()), "c".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("(a(b))(c)"),
+        expr().easy_parse("(a(b))(c)"),
         Ok((Expr::a(Expr::a("a".into(), "b".into()), "c".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse(" a (  b   )"),
+        expr().easy_parse(" a (  b   )"),
         Ok((Expr::a("a".into(), "b".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("a(b, c)"),
+        expr().easy_parse("a(\n  b(c)\n)"),
+        Ok((Expr::a("a".into(), Expr::a("b".into(), "c".into())), ""))
+    );
+    assert_eq!(
+        expr().easy_parse("a(b, c)"),
         Ok((Expr::a(Expr::a("a".into(), "b".into()), "c".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse(" a ( b ,  c  )"),
+        expr().easy_parse(" a ( b ,  c  )"),
         Ok((Expr::a(Expr::a("a".into(), "b".into()), "c".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("FOO(BAR)"),
+        expr().easy_parse("FOO(BAR)"),
         Ok((Expr::a("FOO".into(), "BAR".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse(":a(b)"),
+        expr().easy_parse(":a(b)"),
         Ok((Expr::a(":a".into(), "b".into()), ""))
     );
     assert_eq!(
-        apply().easy_parse("(x => x)(a)"),
+        expr().easy_parse("(x => x)(a)"),
         Ok((Expr::a(Expr::l("x".into(), "x".into()), "a".into()), ""))
     );
 }
 
 // ========================================================================== //
 
-parser! {
-    fn lambda[Input]()(Input) -> Expr
-    where [
-        Input: Stream<Token = char>,
-        Input::Error: ParseError<char, Input::Range, Input::Position>,
-        <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError:
-            From<::std::num::ParseIntError>,
-    ]
-    {
-        spaces()
-            .with(
-                choice((
-                    params(),
-                    identifier().map(|i| vec![i])),
-                ))
-                .skip(spaces().with(string("=>"))
-            )
-            .and(expr())
-            .map(|(params, mut body)| {
-                for param in params.into_iter().rev() {
-                    body = Expr::l(param, body);
-                }
-                body
-            })
-    }
-}
-
 fn params<Input>() -> impl Parser<Input, Output = Vec<Ident>>
 where
     Input: Stream<Token = char>,
@@ -258,19 +311,19 @@ where
 #[test]
 fn test_lambda() {
     assert_eq!(
-        lambda().easy_parse("a=>b"),
+        expr().easy_parse("a=>b"),
         Ok((Expr::l("a".into(), "b".into()), ""))
     );
     assert_eq!(
-        lambda().easy_parse(" a   =>  b"),
+        expr().easy_parse(" a   =>  b"),
         Ok((Expr::l("a".into(), "b".into()), ""))
     );
     assert_eq!(
-        lambda().easy_parse("a => b => c"),
+        expr().easy_parse("a => b => c"),
         Ok((Expr::l("a".into(), Expr::l("b".into(), "c".into())), ""))
     );
     assert_eq!(
-        lambda().easy_parse("(a, b) => c"),
+        expr().easy_parse("(a, b) => c"),
         Ok((Expr::l("a".into(), Expr::l("b".into(), "c".into())), ""))
     );
 }
@@ -300,3 +353,20 @@ fn test_parens() {
     assert!(parens(char('a')).easy_parse("a").is_err());
     assert!(parens(char('a')).easy_parse("((a))").is_err());
 }
+
+#[test]
+fn test_expr_deep() {
+    // 100 万段入れ子になった関数適用でもスタックを溢れさせない
+    let n = 1_000_000;
+    let sources = [
+        format!("{}:a{}", ":f(".repeat(n), ")".repeat(n)),
+        format!(":f({})", vec![":a"; n].join(", ")),
+    ];
+
+    for source in sources {
+        let (e, rest) = expr().easy_parse(source.as_str()).unwrap();
+
+        assert_eq!(rest, "");
+        assert_eq!(e.depth(), n + 1);
+    }
+}
@@ -0,0 +1,338 @@
+//! 整数のプリミティブ
+//!
+//! 数字だけからなるシンボル (`:42` など) を自然数のリテラルとして扱い、
+//! 組み込みの関数が引数のリテラルから直接結果を計算する (δ 簡約)
+//! シンボルどうしを比べる `SYM_EQ` と、それを使って組み立てる `match` 式もここで扱う
+//! 同じ名前の関数が定義されていれば、そちらを優先する
+//!
+//! 整数の関数は cargo の `integers` feature を有効にしたときだけ使える
+//! 無効なら `INT_ADD` などはただの未定義の変数になる
+
+use std::collections::HashSet;
+
+use crate::config::size_limit;
+use crate::context::Context;
+use crate::expression::{free_vars, Expr};
+use crate::identifier::Ident;
+use crate::limit::LimitExceeded;
+
+/// 組み込みの関数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Primitive {
+    /// `INT_ADD :m :n` → `:m+n`
+    Add,
+
+    /// `INT_SUB :m :n` → `:m-n`、負になる場合は `:0`
+    Sub,
+
+    /// `INT_MUL :m :n` → `:m*n`
+    Mul,
+
+    /// `INT_LT :m :n` → m < n なら TRUE、そうでなければ FALSE
+    Lt,
+
+    /// `INT_EQ :m :n` → m == n なら TRUE、そうでなければ FALSE
+    Eq,
+
+    /// `INT_SUCC :n` → `:n+1`
+    Succ,
+
+    /// `TO_CHURCH :n` → n の Church 数
+    ToChurch,
+
+    /// `FROM_CHURCH n` → ``n INT_SUCC :0`、Church 数をリテラルに戻す
+    FromChurch,
+
+    /// `SYM_EQ :a :b` → 同じシンボルなら TRUE、そうでなければ FALSE
+    SymEq,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 9] = [
+        Primitive::Add,
+        Primitive::Sub,
+        Primitive::Mul,
+        Primitive::Lt,
+        Primitive::Eq,
+        Primitive::Succ,
+        Primitive::ToChurch,
+        Primitive::FromChurch,
+        Primitive::SymEq,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Add => "INT_ADD",
+            Primitive::Sub => "INT_SUB",
+            Primitive::Mul => "INT_MUL",
+            Primitive::Lt => "INT_LT",
+            Primitive::Eq => "INT_EQ",
+            Primitive::Succ => "INT_SUCC",
+            Primitive::ToChurch => "TO_CHURCH",
+            Primitive::FromChurch => "FROM_CHURCH",
+            Primitive::SymEq => "SYM_EQ",
+        }
+    }
+
+    /// 式の頭部が Context で定義されていない組み込みの関数なら、それを返す
+    pub fn lookup(expr: &Expr, context: &Context) -> Option<Primitive> {
+        let Expr::Variable(id) = expr else {
+            return None;
+        };
+        if context.get(id).is_some() {
+            return None;
+        }
+        Primitive::ALL
+            .into_iter()
+            .filter(Primitive::is_enabled)
+            .find(|p| p.name() == id.label())
+    }
+
+    /// 整数の関数は integers feature を有効にしたときだけ使える
+    pub fn is_enabled(&self) -> bool {
+        cfg!(feature = "integers") || *self == Primitive::SymEq
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Primitive::Add
+            | Primitive::Sub
+            | Primitive::Mul
+            | Primitive::Lt
+            | Primitive::Eq
+            | Primitive::SymEq => 2,
+            Primitive::Succ | Primitive::ToChurch | Primitive::FromChurch => 1,
+        }
+    }
+
+    /// 引数がリテラルに簡約されるまで適用できないなら真
+    pub fn is_strict(&self) -> bool {
+        !matches!(self, Primitive::FromChurch)
+    }
+
+    /// 引数に適用した結果を返す、引数がリテラルでないか結果が溢れるなら None
+    ///
+    /// 結果の式が大きさの上限を超えるなら、作らずに Err を返す
+    pub fn apply(&self, args: &[Expr]) -> Result<Option<Expr>, LimitExceeded> {
+        match (self, args) {
+            (Primitive::FromChurch, [n]) => {
+                let succ = Expr::Variable(Ident::new(Primitive::Succ.name()));
+                return Ok(Some(Expr::a(Expr::a(n.clone(), succ), int(0))));
+            }
+            (Primitive::SymEq, [Expr::Symbol(l), Expr::Symbol(r)]) => {
+                return Ok(Some(boolean(l == r)));
+            }
+            (Primitive::SymEq, _) => return Ok(None),
+            _ => (),
+        }
+
+        let Some(n) = args.iter().map(literal).collect::<Option<Vec<_>>>() else {
+            return Ok(None);
+        };
+        Ok(match self {
+            Primitive::Add => n[0].checked_add(n[1]).map(int),
+            Primitive::Sub => Some(int(n[0].saturating_sub(n[1]))),
+            Primitive::Mul => n[0].checked_mul(n[1]).map(int),
+            Primitive::Lt => Some(boolean(n[0] < n[1])),
+            Primitive::Eq => Some(boolean(n[0] == n[1])),
+            Primitive::Succ => n[0].checked_add(1).map(int),
+            Primitive::ToChurch => {
+                // n の Church 数は 2 つのラムダ抽象と x、n 個の `f のノードからなる
+                let size = n[0].saturating_mul(2).saturating_add(3);
+                let limit = size_limit();
+                if size > limit as u64 {
+                    return Err(LimitExceeded::Size {
+                        size: usize::try_from(size).unwrap_or(usize::MAX),
+                        limit,
+                    });
+                }
+                Some(church(n[0]))
+            }
+            Primitive::FromChurch | Primitive::SymEq => unreachable!(),
+        })
+    }
+}
+
+/// 式が整数のリテラルなら、その値を返す
+pub fn literal(expr: &Expr) -> Option<u64> {
+    match expr {
+        Expr::Symbol(id) if id.label().chars().all(|c| c.is_ascii_digit()) => {
+            id.label().parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// 整数のリテラル
+pub fn int(n: u64) -> Expr {
+    Expr::Symbol(Ident::new(&n.to_string()))
+}
+
+/// シンボルで場合分けする `match` 式を `SYM_EQ` の連鎖に書き換える
+///
+/// `match e { :a => x, :b => y, _ => z }` は `` `^s.````SYM_EQ s :a x ````SYM_EQ s :b y z e `` になる
+/// e を一度だけ簡約するよう束縛し、その変数にはどの腕にも自由に現れない名前を選ぶ
+/// `_` の腕が無ければ、どの腕にも一致しないとき `:NO_MATCH` になる
+pub fn match_symbol(scrutinee: Expr, arms: Vec<(Ident, Expr)>, default: Option<Expr>) -> Expr {
+    let default = default.unwrap_or_else(|| Expr::s("NO_MATCH"));
+    if arms.is_empty() {
+        return default;
+    }
+
+    let used = arms
+        .iter()
+        .map(|(_, body)| body)
+        .chain([&default])
+        .flat_map(free_vars)
+        .cloned()
+        .collect::<HashSet<_>>();
+    let var = std::iter::once('s')
+        .chain('a'..='z')
+        .map(|c| Ident::new(&c.to_string()))
+        .find(|i| !used.contains(i))
+        .unwrap_or_else(|| Ident::new("s").new_name(&used));
+
+    let mut expr = default;
+    for (symbol, body) in arms.into_iter().rev() {
+        let test = Expr::a(
+            Expr::a(Primitive::SymEq.name().into(), Expr::Variable(var.clone())),
+            Expr::Symbol(symbol),
+        );
+        expr = Expr::a(Expr::a(test, body), expr);
+    }
+    Expr::a(Expr::l(var, expr), scrutinee)
+}
+
+fn boolean(b: bool) -> Expr {
+    let body = if b { "x" } else { "y" };
+    Expr::l("x".into(), Expr::l("y".into(), body.into()))
+}
+
+fn church(n: u64) -> Expr {
+    let mut body: Expr = "x".into();
+    for _ in 0..n {
+        body = Expr::a("f".into(), body);
+    }
+    Expr::l("f".into(), Expr::l("x".into(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Primitive::Add.apply(&[int(2), int(3)]), Ok(Some(int(5))));
+        assert_eq!(Primitive::Sub.apply(&[int(2), int(3)]), Ok(Some(int(0))));
+        assert_eq!(
+            Primitive::Mul.apply(&[int(10), int(10)]),
+            Ok(Some(int(100)))
+        );
+        assert_eq!(Primitive::Mul.apply(&[int(u64::MAX), int(2)]), Ok(None));
+        assert_eq!(
+            Primitive::Lt.apply(&[int(2), int(3)]),
+            Ok(Some(boolean(true)))
+        );
+        assert_eq!(
+            Primitive::Eq.apply(&[int(2), int(3)]),
+            Ok(Some(boolean(false)))
+        );
+        assert_eq!(Primitive::Add.apply(&[int(2), ":a".into()]), Ok(None));
+        assert_eq!(
+            Primitive::ToChurch.apply(&[int(2)]),
+            Ok(Some(Expr::l(
+                "f".into(),
+                Expr::l(
+                    "x".into(),
+                    Expr::a("f".into(), Expr::a("f".into(), "x".into()))
+                )
+            )))
+        );
+        assert_eq!(church(2).size(), 7);
+    }
+
+    #[test]
+    fn test_to_church_size_limit() {
+        // 上限を超える Church 数は作らずに上限を超えたことを返す
+        let n = size_limit() as u64;
+        assert_eq!(
+            Primitive::ToChurch.apply(&[int(n)]),
+            Err(LimitExceeded::Size {
+                size: 2 * size_limit() + 3,
+                limit: size_limit(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_sym_eq() {
+        let sym_eq = |l: &str, r: &str| Primitive::SymEq.apply(&[l.into(), r.into()]).unwrap();
+        assert_eq!(sym_eq(":a", ":a"), Some(boolean(true)));
+        assert_eq!(sym_eq(":a", ":b"), Some(boolean(false)));
+        assert_eq!(sym_eq(":a", "a"), None);
+    }
+
+    #[test]
+    fn test_match_symbol() {
+        let e = match_symbol("e".into(), vec![("a".into(), "x".into())], None);
+        let test = Expr::a(Expr::a("SYM_EQ".into(), "s".into()), ":a".into());
+        let chain = Expr::a(Expr::a(test, "x".into()), ":NO_MATCH".into());
+        assert_eq!(e, Expr::a(Expr::l("s".into(), chain), "e".into()));
+
+        // 束縛する変数は腕の本体に自由に現れる名前を避ける
+        let e = match_symbol(
+            "e".into(),
+            vec![("a".into(), "s".into())],
+            Some(Expr::a("a".into(), "b".into())),
+        );
+        let test = Expr::a(Expr::a("SYM_EQ".into(), "c".into()), ":a".into());
+        let chain = Expr::a(Expr::a(test, "s".into()), Expr::a("a".into(), "b".into()));
+        assert_eq!(e, Expr::a(Expr::l("c".into(), chain), "e".into()));
+
+        // 腕が無ければ束縛しない
+        assert_eq!(match_symbol("e".into(), vec![], None), ":NO_MATCH".into());
+    }
+
+    #[test]
+    #[cfg(feature = "integers")]
+    fn test_lookup() {
+        let context = Context::new();
+        assert_eq!(
+            Primitive::lookup(&"INT_ADD".into(), &context),
+            Some(Primitive::Add)
+        );
+        assert_eq!(Primitive::lookup(&":INT_ADD".into(), &context), None);
+
+        // 同じ名前の関数が定義されていれば組み込みの関数として扱わない
+        let context = Context::from(vec![crate::function::Func::new(
+            "INT_ADD".into(),
+            vec![],
+            ":a".into(),
+        )]);
+        assert_eq!(Primitive::lookup(&"INT_ADD".into(), &context), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "integers"))]
+    fn test_lookup_disabled() {
+        // integers feature が無効なら整数の関数は見つからない
+        let context = Context::new();
+        for p in Primitive::ALL
+            .into_iter()
+            .filter(|p| *p != Primitive::SymEq)
+        {
+            assert_eq!(Primitive::lookup(&p.name().into(), &context), None);
+        }
+        assert_eq!(
+            Primitive::lookup(&"SYM_EQ".into(), &context),
+            Some(Primitive::SymEq)
+        );
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(literal(&":42".into()), Some(42));
+        assert_eq!(literal(&":a".into()), None);
+        assert_eq!(literal(&"42".into()), None);
+    }
+}
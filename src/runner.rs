@@ -0,0 +1,295 @@
+//! 定義ファイルに埋め込んだ期待値を検査する
+//!
+//! 式の行の直後に `→ 式` の行を置くと正規形を、`steps: N` の行を置くと正規形までのステップ数を検査する
+//! 正規形はラムダ抽象の本体まで簡約して α 同値かどうかで比べる
+//! `== 左辺 右辺` の行はそれだけで 1 つの検査になる
+
+use crate::command::Command;
+use crate::config::DisplayStyle;
+use crate::context::Context;
+use crate::engine::{Assertion, Engine, EngineEvent, EventSink, Status};
+use crate::expression::Expr;
+use crate::format::{format_expr, parse_line};
+
+/// 式の行の直後に置く期待値
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    /// `→ 式`
+    NormalForm(Expr),
+
+    /// `steps: N`
+    Steps(usize),
+}
+
+/// 行が期待値なら、それをパースした結果を返す
+pub fn parse_expectation(line: &str) -> Option<Result<Expectation, String>> {
+    let line = line.trim();
+
+    if let Some(expr) = line.strip_prefix('→').or_else(|| line.strip_prefix("->")) {
+        return Some(match parse_line(expr) {
+            Ok(Command::Eval(e)) => Ok(Expectation::NormalForm(e)),
+            Ok(_) => Err(format!("expected an expression: {}", expr.trim())),
+            Err(e) => Err(e),
+        });
+    }
+
+    let steps = line.strip_prefix("steps:")?;
+    Some(
+        steps
+            .trim()
+            .parse()
+            .map(Expectation::Steps)
+            .map_err(|e| format!("invalid step count: {}", e)),
+    )
+}
+
+/// 期待値を指定したスタイルの正規の書式で印字する
+pub fn format_expectation(expectation: &Expectation, style: &DisplayStyle) -> String {
+    match expectation {
+        Expectation::NormalForm(e) => format!("→ {}", format_expr(e, style)),
+        Expectation::Steps(n) => format!("steps: {}", n),
+    }
+}
+
+/// 1 つの検査の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// 検査した式の行番号 (1 始まり)
+    pub line: usize,
+
+    /// 検査した行
+    pub source: String,
+
+    /// 期待値と一致しなかったもの
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// 期待値と実際の値の食い違い、どちらも表示スタイルで印字したもの
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub what: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// 定義ファイルを先頭から実行し、期待値の付いた式と `==` の行を検査する
+///
+/// 定義と削除は以降の行に反映する、パースできない行があればその行番号とともにエラーを返す
+pub fn run_checks(
+    source: &str,
+    context: Context,
+    style: &DisplayStyle,
+) -> Result<Vec<Check>, String> {
+    let mut engine = Engine::new(context);
+    let mut checks = Vec::new();
+
+    let lines = source.lines().enumerate().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < lines.len() {
+        let (n, line) = lines[i];
+        i += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let error = |e: String| format!("line {}: {}", n + 1, e);
+        if parse_expectation(line).is_some() {
+            return Err(error("expectation without an expression".to_string()));
+        }
+
+        let mut expectations = Vec::new();
+        while let Some(expectation) = lines.get(i).and_then(|(_, l)| parse_expectation(l)) {
+            expectations.push(expectation.map_err(|e| format!("line {}: {}", i + 1, e))?);
+            i += 1;
+        }
+
+        let mismatches = match parse_line(line).map_err(error)? {
+            Command::Assert(lhs, rhs) => {
+                let assertion = assert(&mut engine, lhs, rhs);
+                assertion_mismatch(&assertion, style).into_iter().collect()
+            }
+            Command::Eval(e) if !expectations.is_empty() => {
+                check(&mut engine, e, &expectations, style)
+            }
+            command => {
+                engine.run(command, &mut Recorder::default());
+                continue;
+            }
+        };
+
+        checks.push(Check {
+            line: n + 1,
+            source: line.trim().to_string(),
+            mismatches,
+        });
+    }
+
+    Ok(checks)
+}
+
+/// 式をそれぞれの期待値と比べる
+fn check(
+    engine: &mut Engine,
+    e: Expr,
+    expectations: &[Expectation],
+    style: &DisplayStyle,
+) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+
+    for expectation in expectations {
+        match expectation {
+            Expectation::NormalForm(expected) => {
+                let assertion = assert(engine, e.clone(), expected.clone());
+                mismatches.extend(assertion_mismatch(&assertion, style));
+            }
+            Expectation::Steps(expected) => {
+                let mut recorder = Recorder::default();
+                let reduction = engine.run(Command::Eval(e.clone()), &mut recorder);
+                let actual = match reduction.map(|r| r.status) {
+                    Some(Status::NormalForm) => recorder.steps.to_string(),
+                    Some(status) => format!("{} ({})", recorder.steps, status.name()),
+                    None => unreachable!(),
+                };
+                if actual != expected.to_string() {
+                    mismatches.push(Mismatch {
+                        what: "steps",
+                        expected: expected.to_string(),
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+
+    mismatches
+}
+
+fn assert(engine: &mut Engine, lhs: Expr, rhs: Expr) -> Assertion {
+    let mut recorder = Recorder::default();
+    engine.run(Command::Assert(lhs, rhs), &mut recorder);
+    recorder.assertion.expect("== の結果")
+}
+
+fn assertion_mismatch(assertion: &Assertion, style: &DisplayStyle) -> Option<Mismatch> {
+    if assertion.passed() {
+        return None;
+    }
+
+    let mut actual = format_expr(&assertion.lhs, style);
+    if assertion.status != Status::NormalForm {
+        actual = format!("{} ({})", actual, assertion.status.name());
+    }
+    Some(Mismatch {
+        what: "normal form",
+        expected: format_expr(&assertion.rhs, style),
+        actual,
+    })
+}
+
+/// 簡約のステップ数と等式の検査の結果を記録する
+#[derive(Default)]
+struct Recorder {
+    steps: usize,
+    assertion: Option<Assertion>,
+}
+
+impl EventSink for Recorder {
+    fn emit(&mut self, event: EngineEvent) {
+        match event {
+            EngineEvent::Step(_) => self.steps += 1,
+            EngineEvent::Asserted(assertion) => self.assertion = Some(assertion.clone()),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expectation() {
+        assert_eq!(
+            parse_expectation("→ :a"),
+            Some(Ok(Expectation::NormalForm(":a".into())))
+        );
+        assert_eq!(
+            parse_expectation("  -> `:a:b"),
+            Some(Ok(Expectation::NormalForm(Expr::a(
+                ":a".into(),
+                ":b".into()
+            ))))
+        );
+        assert_eq!(
+            parse_expectation("steps: 2"),
+            Some(Ok(Expectation::Steps(2)))
+        );
+        assert!(matches!(parse_expectation("steps: x"), Some(Err(_))));
+        assert_eq!(parse_expectation("```skk:a"), None);
+    }
+
+    #[test]
+    fn test_run_checks() {
+        let source = "\
+``FOOxy = `yx
+``FOO:a:b
+→ `:b:a
+steps: 1
+
+== ``ADD 2 3 5
+``FOO:a:b
+→ `:a:b
+steps: 3
+";
+        let checks = run_checks(source, Context::default(), &DisplayStyle::LazyK).unwrap();
+        assert_eq!(
+            checks,
+            vec![
+                Check {
+                    line: 2,
+                    source: "``FOO:a:b".to_string(),
+                    mismatches: vec![],
+                },
+                Check {
+                    line: 6,
+                    source: "== ``ADD 2 3 5".to_string(),
+                    mismatches: vec![],
+                },
+                Check {
+                    line: 7,
+                    source: "``FOO:a:b".to_string(),
+                    mismatches: vec![
+                        Mismatch {
+                            what: "normal form",
+                            expected: "`:a:b".to_string(),
+                            actual: "`:b:a".to_string(),
+                        },
+                        Mismatch {
+                            what: "steps",
+                            expected: "3".to_string(),
+                            actual: "1".to_string(),
+                        },
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_checks_errors() {
+        let style = DisplayStyle::LazyK;
+        assert_eq!(
+            run_checks("→ :a", Context::new(), &style),
+            Err("line 1: expectation without an expression".to_string())
+        );
+        assert!(run_checks(":a\nsteps: -1", Context::new(), &style)
+            .unwrap_err()
+            .starts_with("line 2: "));
+    }
+}
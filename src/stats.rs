@@ -0,0 +1,149 @@
+//! 簡約の統計情報を集める
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use crate::evaluate::Redex;
+use crate::expression::{substitution_counts, Expr};
+use crate::identifier::Ident;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// β変換 (関数適用を含む) の回数
+    pub steps: usize,
+
+    /// 定義済み関数ごとの適用回数
+    pub applications: HashMap<Ident, usize>,
+
+    /// ラムダ抽象の適用回数
+    pub lambda_applications: usize,
+
+    /// 簡約の途中に現れた式のノード数の最大値
+    pub max_size: usize,
+
+    /// 簡約の途中に現れた式の深さの最大値
+    pub max_depth: usize,
+
+    /// 変数の置換の回数
+    pub substitutions: usize,
+
+    /// 変数の衝突を避けるための α 変換の回数
+    pub renames: usize,
+
+    pub elapsed: Duration,
+}
+
+/// 簡約の各ステップを観測して統計情報を集める
+pub struct Profiler {
+    stats: Stats,
+    start: Instant,
+    counts_at_start: (usize, usize),
+}
+
+impl Profiler {
+    /// 簡約前の式を受け取り計測を始める
+    pub fn start(expr: &Expr) -> Self {
+        Profiler {
+            stats: Stats {
+                max_size: expr.size(),
+                max_depth: expr.depth(),
+                ..Stats::default()
+            },
+            start: Instant::now(),
+            counts_at_start: substitution_counts(),
+        }
+    }
+
+    /// 1 ステップ分の簡約で得た式と、そのステップで簡約した redex を記録する
+    pub fn record(&mut self, redex: Option<&Redex>, expr: &Expr) {
+        self.stats.steps += 1;
+
+        match redex {
+            Some(Redex::Func(name)) => {
+                *self.stats.applications.entry(name.clone()).or_insert(0) += 1;
+            }
+            Some(Redex::Lambda(_)) => self.stats.lambda_applications += 1,
+            None => {}
+        }
+
+        self.stats.max_size = self.stats.max_size.max(expr.size());
+        self.stats.max_depth = self.stats.max_depth.max(expr.depth());
+    }
+
+    pub fn finish(mut self) -> Stats {
+        let (substitutions, renames) = substitution_counts();
+        self.stats.substitutions = substitutions - self.counts_at_start.0;
+        self.stats.renames = renames - self.counts_at_start.1;
+        self.stats.elapsed = self.start.elapsed();
+        self.stats
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "steps: {}", self.steps)?;
+        writeln!(f, "time: {:?}", self.elapsed)?;
+        writeln!(f, "max size: {}", self.max_size)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        writeln!(f, "substitutions: {}", self.substitutions)?;
+        writeln!(f, "α-renames: {}", self.renames)?;
+        writeln!(f, "lambda applications: {}", self.lambda_applications)?;
+        write!(f, "function applications:")?;
+
+        // 適用回数の多い順に並べる
+        let mut applications = self.applications.iter().collect::<Vec<_>>();
+        applications.sort_by(|(l_name, l_count), (r_name, r_count)| {
+            r_count
+                .cmp(l_count)
+                .then_with(|| l_name.label().cmp(r_name.label()))
+        });
+
+        for (name, count) in applications {
+            write!(f, "\n  {}: {}", name, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::evaluate::EvalSteps;
+    use crate::function::Func;
+
+    #[test]
+    fn test_profiler() {
+        let context = Context::from(vec![
+            Func::new("i".into(), vec!["x".into()], "x".into()),
+            Func::new("k".into(), vec!["x".into(), "y".into()], "x".into()),
+        ]);
+
+        // ``k`i`i:a`^x.x:b
+        let expr = Expr::a(
+            Expr::a(
+                "k".into(),
+                Expr::a("i".into(), Expr::a("i".into(), ":a".into())),
+            ),
+            Expr::a(Expr::l("x".into(), "x".into()), ":b".into()),
+        );
+
+        let mut profiler = Profiler::start(&expr);
+        let mut steps = EvalSteps::new(expr, &context);
+        while let Some(e) = steps.next() {
+            profiler.record(steps.redex(), &e);
+        }
+        let stats = profiler.finish();
+
+        assert_eq!(stats.steps, 3);
+        assert_eq!(stats.applications.get(&"k".into()), Some(&1));
+        assert_eq!(stats.applications.get(&"i".into()), Some(&2));
+        assert_eq!(stats.lambda_applications, 0);
+        assert_eq!(stats.max_size, 12);
+        assert_eq!(stats.max_depth, 5);
+        assert_eq!(stats.substitutions, 4);
+        assert_eq!(stats.renames, 0);
+    }
+}
@@ -0,0 +1,200 @@
+//! JavaScript から使うための API
+//!
+//! ```js
+//! const tuber = new Tuber("lazy-k");
+//! tuber.define("`ix = x");
+//! const evaluation = tuber.evaluate("```skk:a");
+//! evaluation.step(); // "``k:a`k:a"
+//! evaluation.step(); // ":a"
+//! evaluation.step(); // undefined
+//! ```
+//!
+//! 簡約戦略とステップ数・式の大きさ・深さの上限は、CLI と同じく設定の値を使う
+
+use wasm_bindgen::prelude::*;
+
+use crate::command::Command;
+use crate::config::{step_limit, strategy, DisplayStyle, Strategy};
+use crate::context::Context;
+use crate::evaluate::EvalSteps;
+use crate::expression::Expr;
+use crate::format::{format_command, format_expr, parse_command, parse_line};
+use crate::history::MemoryHistory;
+use crate::identifier::Ident;
+use crate::limit::{Limits, Watchdog};
+
+/// 定義済み関数の Context と履歴を持つインタプリタ
+#[wasm_bindgen]
+pub struct Tuber {
+    context: Context,
+    history: MemoryHistory,
+    display_style: DisplayStyle,
+}
+
+#[wasm_bindgen]
+impl Tuber {
+    /// style は結果を印字するスタイル (lazy-k | ecmascript)
+    #[wasm_bindgen(constructor)]
+    pub fn new(style: &str) -> Result<Tuber, String> {
+        Ok(Tuber {
+            context: Context::default(),
+            history: MemoryHistory::new(),
+            display_style: style.parse()?,
+        })
+    }
+
+    /// コマンドをパースし、指定したスタイルの正規の書式で返す
+    pub fn parse(&self, line: &str) -> Result<String, String> {
+        let command = parse_line(line)?;
+        Ok(format_command(&command, &self.display_style))
+    }
+
+    /// 式の簡約を始める、ステップは返り値の step で 1 つずつ得る
+    pub fn evaluate(&self, expr: &str) -> Result<Evaluation, String> {
+        Ok(Evaluation {
+            expr: self.parse_expr(expr)?,
+            context: self.context.clone(),
+            display_style: self.display_style,
+            strategy: strategy(),
+            remaining: step_limit(),
+            watchdog: Limits::from_config().start(),
+        })
+    }
+
+    /// 式からラムダ抽象を除去する
+    pub fn unlambda(&self, expr: &str) -> Result<String, String> {
+        let expr = self.parse_expr(expr)?.unlambda();
+        Ok(format_expr(&expr, &self.display_style))
+    }
+
+    /// 関数またはデータ型を定義する、定義済みの関数は上書きする
+    pub fn define(&mut self, line: &str) -> Result<(), String> {
+        match parse_line(line)? {
+            Command::Update(f) => {
+                self.history.push(&Command::Update(f.clone()));
+                self.context.update(f);
+                Ok(())
+            }
+            Command::Data(d) => {
+                self.history.push(&Command::Data(d.clone()));
+                d.funcs().into_iter().for_each(|f| self.context.def(f));
+                Ok(())
+            }
+            _ => Err(format!("not a definition: {}", line)),
+        }
+    }
+
+    /// 関数を削除する、識別子の形でない名前ならエラーにする
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let name: Ident = name.parse()?;
+        self.context.del(&name);
+        self.history.push(&Command::Del(name));
+        Ok(())
+    }
+
+    /// 定義済みの関数を名前順に返す
+    pub fn functions(&self) -> Vec<String> {
+        let mut functions = Vec::new();
+        self.context.for_each(|_, f| functions.push(f));
+        functions.sort_by(|l, r| l.name().label().cmp(r.name().label()));
+
+        functions
+            .into_iter()
+            .map(|f| format_command(&Command::Update(f.clone()), &self.display_style))
+            .collect()
+    }
+
+    /// これまでの定義と削除を古い順に返す
+    pub fn history(&self) -> Vec<String> {
+        self.history.lines().to_vec()
+    }
+
+    fn parse_expr(&self, expr: &str) -> Result<Expr, String> {
+        match parse_command(expr, &self.display_style).or_else(|_| parse_line(expr))? {
+            Command::Eval(e) => Ok(e),
+            _ => Err(format!("not an expression: {}", expr)),
+        }
+    }
+}
+
+/// 簡約中の式
+#[wasm_bindgen]
+pub struct Evaluation {
+    expr: Expr,
+    context: Context,
+    display_style: DisplayStyle,
+    strategy: Strategy,
+
+    /// 残りのステップ数
+    remaining: usize,
+
+    watchdog: Watchdog,
+}
+
+#[wasm_bindgen]
+impl Evaluation {
+    /// 簡約を 1 ステップ進めて式を返す、正規形に達していれば undefined を返す
+    ///
+    /// ステップ数や式の大きさ・深さ・時間の上限を超えたらエラーにする
+    pub fn step(&mut self) -> Result<Option<String>, String> {
+        // EvalSteps は Context を借用するので持ち続けられず、毎回根から辿り直して redex を探す
+        let mut steps = EvalSteps::with_strategy(self.expr.clone(), &self.context, self.strategy);
+        let Some(next) = steps.next() else {
+            return Ok(None);
+        };
+        if self.remaining == 0 {
+            return Err(format!("step limit exceeded: {}", step_limit()));
+        }
+        self.remaining -= 1;
+        self.watchdog.check(&next).map_err(|e| e.to_string())?;
+
+        self.expr = next;
+        Ok(Some(format_expr(&self.expr, &self.display_style)))
+    }
+
+    /// 現在の式
+    pub fn current(&self) -> String {
+        format_expr(&self.expr, &self.display_style)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuber() {
+        let mut tuber = Tuber::new("lazy-k").unwrap();
+        tuber.define("``FOOxy = `yx").unwrap();
+
+        let mut evaluation = tuber.evaluate("``FOO:a:b").unwrap();
+        assert_eq!(evaluation.step(), Ok(Some("`:b:a".to_string())));
+        assert_eq!(evaluation.step(), Ok(None));
+        assert_eq!(evaluation.current(), "`:b:a");
+
+        assert_eq!(tuber.unlambda("^x.x").unwrap(), "i");
+
+        tuber.delete("FOO").unwrap();
+        assert!(tuber.delete("foo").is_err());
+        assert_eq!(tuber.history(), ["``FOOxy = `yx", "FOO = FOO"]);
+        assert!(tuber.define("`i:a").is_err());
+    }
+
+    #[test]
+    fn test_evaluation_limit() {
+        let tuber = Tuber::new("lazy-k").unwrap();
+
+        // 停止しない式はステップ数の上限でエラーになる
+        let mut evaluation = tuber.evaluate("```sii``sii").unwrap();
+        let mut steps = 0;
+        let err = loop {
+            match evaluation.step() {
+                Ok(Some(_)) => steps += 1,
+                Ok(None) => panic!("normal form"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(steps, step_limit());
+        assert_eq!(err, format!("step limit exceeded: {}", step_limit()));
+    }
+}
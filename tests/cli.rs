@@ -0,0 +1,127 @@
+//! コマンドラインから実行して、`--format json` の出力の形や失敗したときの終了ステータスを確かめる
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::{json, Value};
+
+/// 履歴や利用者の設定を読まずに tuber を実行するコマンド
+fn tuber(args: &[&str]) -> Command {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let mut cli = Command::new(env!("CARGO_BIN_EXE_tuber"));
+    cli.arg("--no-history")
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir);
+    for (key, _) in std::env::vars().filter(|(key, _)| key.starts_with("TUBER_")) {
+        cli.env_remove(key);
+    }
+    cli
+}
+
+/// コマンドを 1 つ実行し、出力の各行を JSON として返す
+fn run(command: &str) -> Vec<Value> {
+    let output = tuber(&["--format", "json", command]).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_eval() {
+    assert_eq!(
+        run("```skk:a"),
+        vec![json!({
+            "command": "eval",
+            "input": "```skk:a",
+            "steps": ["``k:a`k:a", ":a"],
+            "result": ":a",
+            "status": "normal_form",
+            "strategy": "normal",
+        })]
+    );
+}
+
+#[test]
+fn test_whnf() {
+    assert_eq!(
+        run(":whnf `^x.^y.`ix:a"),
+        vec![json!({
+            "command": "eval",
+            "input": "`^x.^y.`ix:a",
+            "steps": ["^y.`i:a"],
+            "result": "^y.`i:a",
+            "status": "normal_form",
+            "strategy": "whnf",
+        })]
+    );
+}
+
+#[test]
+fn test_assert() {
+    assert_eq!(
+        run("== ``k:a:b :b"),
+        vec![json!({
+            "command": "assert",
+            "lhs": ":a",
+            "rhs": ":b",
+            "passed": false,
+            "status": "normal_form",
+        })]
+    );
+}
+
+#[test]
+fn test_info() {
+    assert_eq!(
+        run("? k"),
+        vec![json!({
+            "command": "info",
+            "name": "k",
+            "function": { "name": "k", "params": ["x", "y"], "body": "x" },
+        })]
+    );
+    assert_eq!(
+        run("? NOPE"),
+        vec![json!({ "command": "info", "name": "NOPE", "function": null })]
+    );
+
+    let global = run("?");
+    assert_eq!(global.len(), 1);
+    assert_eq!(global[0]["command"], "global");
+    assert!(global[0]["functions"]
+        .as_array()
+        .unwrap()
+        .contains(&json!({ "name": "i", "params": ["x"], "body": "x" })));
+}
+
+#[test]
+fn test_context_error() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("broken-context.txt");
+    std::fs::write(&path, "`ix = x\n`ix = )\n").unwrap();
+
+    // 定義ファイルにパースできない行があれば、行番号を示して失敗する
+    let output = tuber(&["--context", path.to_str().unwrap(), "`i:a"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("{}: line 2: ", path.display())));
+}
+
+#[test]
+fn test_missing_command() {
+    // フラグだけでコマンドが無ければ、使い方を示して失敗する
+    for flags in [&[][..], &["--stats"], &["--format", "json"]] {
+        let output = tuber(flags).output().unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("a command or a subcommand is required"));
+        assert!(stderr.contains("Usage:"));
+    }
+}